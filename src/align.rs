@@ -0,0 +1,128 @@
+use std::path::Path;
+
+use crate::diff::tokens;
+use crate::error::ConvError;
+use crate::whisper::{Transcript, Utterance};
+
+/// one subtitle per non-blank line
+pub fn load_lines<P: AsRef<Path>>(path: P) -> Result<Vec<String>, ConvError> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Match,
+    Substitute,
+    Delete,
+    Insert,
+}
+
+// levenshtein keeping every cell's step so the path can be walked back, a byte per cell.
+// for each reference token the hypothesis token it lines up with, and whether they are equal
+fn align(reference: &[String], hypothesis: &[String]) -> Vec<Option<(usize, bool)>> {
+    let width = hypothesis.len() + 1;
+    let mut steps = vec![Step::Insert; (reference.len() + 1) * width];
+    let mut prev = (0..width).collect::<Vec<_>>();
+    for (i, r) in reference.iter().enumerate() {
+        let mut row = vec![i + 1];
+        steps[(i + 1) * width] = Step::Delete;
+        for (j, h) in hypothesis.iter().enumerate() {
+            // on a tie a reference token is rather left out than substituted, so a misheard word stays with the
+            // line it ends instead of drifting to a later one that was never sung
+            let matched = if r == h { prev[j] } else { usize::MAX };
+            let candidates = [(matched, Step::Match), (prev[j + 1] + 1, Step::Delete), (prev[j] + 1, Step::Substitute), (row[j] + 1, Step::Insert)];
+            let (cost, step) = candidates.into_iter().min_by_key(|c| c.0).unwrap();
+            row.push(cost);
+            steps[(i + 1) * width + j + 1] = step;
+        }
+        prev = row;
+    }
+
+    let mut aligned = vec![None; reference.len()];
+    let (mut i, mut j) = (reference.len(), hypothesis.len());
+    while i > 0 || j > 0 {
+        match steps[i * width + j] {
+            step @ (Step::Match | Step::Substitute) => {
+                aligned[i - 1] = Some((j - 1, step == Step::Match));
+                i -= 1;
+                j -= 1;
+            }
+            Step::Delete => i -= 1,
+            Step::Insert => j -= 1,
+        }
+    }
+    aligned
+}
+
+impl Transcript {
+    /// the given lines timed by the recognized words they line up with. a line none of whose words were heard
+    /// is a guess at best, its index is returned and it gets an even share of the time between its neighbours
+    pub fn align_lines(&self, lines: &[String]) -> (Transcript, Vec<usize>) {
+        // a cue with several tokens (a segment, or CJK characters in one word) splits its time evenly
+        let (mut heard, mut times) = (vec![], vec![]);
+        for u in self.word_utterances.as_ref().unwrap_or(&self.utterances) {
+            let words = tokens(&u.text);
+            let n = words.len() as i64;
+            for (k, word) in (0..).zip(words) {
+                heard.push(word);
+                times.push((u.start + (u.end - u.start) * k / n, u.start + (u.end - u.start) * (k + 1) / n));
+            }
+        }
+        let (mut reference, mut owners) = (vec![], vec![]);
+        for (n, line) in lines.iter().enumerate() {
+            for word in tokens(line) {
+                reference.push(word);
+                owners.push(n);
+            }
+        }
+
+        let mut spans = vec![None; lines.len()];
+        let mut matched = vec![false; lines.len()];
+        for (owner, aligned) in owners.into_iter().zip(align(&reference, &heard)) {
+            let Some((j, equal)) = aligned else {
+                continue;
+            };
+            let (start, end) = times[j];
+            spans[owner] = Some(spans[owner].map_or((start, end), |(s, e): (i64, i64)| (s.min(start), e.max(end))));
+            matched[owner] |= equal;
+        }
+        let unmatched = (0..lines.len()).filter(|n| !matched[*n]).collect::<Vec<_>>();
+        for n in &unmatched {
+            spans[*n] = None;
+        }
+
+        let first = self.utterances.first().map_or(0, |u| u.start);
+        let last = self.utterances.last().map_or(0, |u| u.end);
+        let mut n = 0;
+        while n < lines.len() {
+            if spans[n].is_some() {
+                n += 1;
+                continue;
+            }
+            let run = spans[n..].iter().take_while(|span| span.is_none()).count();
+            let from = n.checked_sub(1).and_then(|k| spans[k]).map_or(first, |(_, end)| end);
+            let to = spans.get(n + run).copied().flatten().map_or(last, |(start, _)| start).max(from);
+            let share = |k: usize| from + (to - from) * k as i64 / run as i64;
+            for k in 0..run {
+                spans[n + k] = Some((share(k), share(k + 1)));
+            }
+            n += run;
+        }
+
+        let utterances = lines
+            .iter()
+            .zip(spans)
+            .map(|(line, span)| {
+                let (start, end) = span.unwrap_or_default();
+                Utterance { start, end, text: line.clone(), lang: None, avg_logprob: None, no_speech_prob: None, speaker: None, translation: None }
+            })
+            .collect();
+        let aligned = Transcript { utterances, word_utterances: None, processing_time: self.processing_time, timings: self.timings };
+        (aligned, unmatched)
+    }
+}
@@ -0,0 +1,528 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use anyhow::{anyhow, Result};
+use clap_builder::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::config::{Language, Model};
+use crate::error::ConvError;
+use crate::event::AppEvent;
+use crate::whisper::{Format, TranscribeOptions, Transcript};
+use crate::worker::WhisperWorker;
+
+#[derive(Debug, Clone)]
+pub struct BatchItem {
+    pub audio: PathBuf,
+    pub lang: Option<Language>,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchPolicy {
+    StopOnError,
+    #[default]
+    SkipAndContinue,
+    RetryN(u32),
+}
+
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    pub succeeded: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, String)>,
+    pub skipped: Vec<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    Path(PathBuf),
+    Item { audio: PathBuf, lang: Option<String> },
+}
+
+fn parse_lang(code: &str) -> Result<Language> {
+    Language::from_str(code.trim(), true).map_err(|_| anyhow!("unknown language: {}", code))
+}
+
+/// a.mp3
+/// b.wav | ja
+/// or [{"audio": "b.wav", "lang": "ja"}, "a.mp3"]
+pub fn load_playlist<P: AsRef<Path>>(path: P) -> Result<Vec<BatchItem>> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)?;
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let items = if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json")) {
+        serde_json::from_str::<Vec<Entry>>(&text)?
+            .into_iter()
+            .map(|entry| match entry {
+                Entry::Path(audio) => Ok(BatchItem { audio, lang: None }),
+                Entry::Item { audio, lang } => Ok(BatchItem { audio, lang: lang.as_deref().map(parse_lang).transpose()? }),
+            })
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| match line.rsplit_once('|') {
+                Some((audio, lang)) => Ok(BatchItem { audio: audio.trim().into(), lang: Some(parse_lang(lang)?) }),
+                None => Ok(BatchItem { audio: line.into(), lang: None }),
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    Ok(items
+        .into_iter()
+        .map(|item| BatchItem { audio: base.join(item.audio), ..item })
+        .collect())
+}
+
+/// * and ? stay within one path component, ** crosses them. patterns without a / only look at the file name
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    fn matches(p: &[char], s: &[char]) -> bool {
+        match p {
+            [] => s.is_empty(),
+            ['*', '*', rest @ ..] => (0..=s.len()).any(|i| matches(rest, &s[i..])),
+            ['*', rest @ ..] => (0..=s.len()).take_while(|&i| i == 0 || s[i - 1] != '/').any(|i| matches(rest, &s[i..])),
+            ['?', rest @ ..] => s.first().is_some_and(|&c| c != '/') && matches(rest, &s[1..]),
+            [c, rest @ ..] => s.first() == Some(c) && matches(rest, &s[1..]),
+        }
+    }
+    let path = path.replace('\\', "/");
+    let target = if pattern.contains('/') { path.as_str() } else { path.rsplit('/').next().unwrap_or_default() };
+    matches(&pattern.chars().collect::<Vec<_>>(), &target.chars().collect::<Vec<_>>())
+}
+
+/// files under dir with one of the extensions, sorted, each directory visited once so symlink loops end
+pub fn discover(dir: &Path, recursive: bool, extensions: &[&str], include: &[String], exclude: &[String]) -> std::io::Result<Vec<PathBuf>> {
+    let mut visited = HashSet::new();
+    let mut found = vec![];
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        if !visited.insert(current.canonicalize()?) {
+            warn!("skipping {}, already visited through a link", current.display());
+            continue;
+        }
+        for entry in std::fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if recursive {
+                    pending.push(path);
+                }
+                continue;
+            }
+            let relative = path.strip_prefix(dir).unwrap_or(&path).to_string_lossy().into_owned();
+            let supported = path.extension().and_then(|e| e.to_str()).is_some_and(|e| extensions.contains(&e.to_lowercase().as_str()));
+            if supported
+                && (include.is_empty() || include.iter().any(|p| glob_match(p, &relative)))
+                && !exclude.iter().any(|p| glob_match(p, &relative)) {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+pub async fn process(worker: &WhisperWorker, audio: &Path, lang: Language, model: Model, options: &TranscribeOptions, formats: &[Format]) -> Result<Transcript> {
+    options.events.send(AppEvent::TranscribeStarted { path: audio.to_path_buf() });
+    let outputs = |_: &Transcript| formats.iter().map(|format| audio.with_extension(format.extension())).collect();
+    options.events.finish(transcribe_file(worker, audio, lang, model, options, formats).await, outputs)
+}
+
+async fn transcribe_file(worker: &WhisperWorker, audio: &Path, lang: Language, model: Model, options: &TranscribeOptions, formats: &[Format]) -> Result<Transcript> {
+    let options = TranscribeOptions { stream_to: Some((audio.to_path_buf(), formats.to_vec())), ..options.clone() };
+    let t = worker.transcribe(audio.to_path_buf(), lang, model, options).await?;
+    for format in formats {
+        t.write_file(audio, *format)?;
+    }
+    Ok(t)
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    pub lang: Language,
+    pub model: Model,
+    pub options: TranscribeOptions,
+    pub formats: Vec<Format>,
+    pub policy: BatchPolicy,
+    /// files transcribed at the same time, they share one loaded model
+    pub concurrency: usize,
+    /// records finished files so an interrupted batch picks up where it stopped
+    pub state: Option<PathBuf>,
+}
+
+/// playlist.txt -> playlist.state.json
+pub fn state_path(playlist: &Path) -> PathBuf {
+    playlist.with_extension("state.json")
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BatchState {
+    pub done: HashSet<PathBuf>,
+}
+
+impl BatchState {
+    pub fn load(path: &Path) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+                warn!("ignoring broken batch state {}: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// written next to the target and renamed over it, a crash mid-write leaves the old state
+    pub fn save(&self, path: &Path) {
+        let tmp = path.with_extension("tmp");
+        let result = std::fs::write(&tmp, serde_json::to_string(self).unwrap()).and_then(|_| std::fs::rename(&tmp, path));
+        if let Err(e) = result {
+            warn!("failed to save batch state {}: {}", path.display(), e);
+        }
+    }
+}
+
+pub enum BatchEvent<'a> {
+    Started(&'a Path),
+    Finished(&'a Path, Option<&'a str>),
+}
+
+pub async fn run_batch(items: &[BatchItem], config: BatchConfig, on_event: impl FnMut(BatchEvent)) -> BatchReport {
+    run_batch_with(items, config, WhisperWorker::spawn, on_event).await
+}
+
+// each worker of the pool comes from spawn
+async fn run_batch_with(items: &[BatchItem], config: BatchConfig, spawn: impl Fn() -> WhisperWorker, mut on_event: impl FnMut(BatchEvent)) -> BatchReport {
+    let mut state = config.state.as_deref().map(BatchState::load).unwrap_or_default();
+    let pending = Arc::new(
+        (0..items.len())
+            .filter(|&i| !state.done.contains(&items[i].audio))
+            .collect::<Vec<_>>(),
+    );
+    if pending.len() < items.len() {
+        info!("resuming batch, {} of {} already done", items.len() - pending.len(), items.len());
+    }
+    let items = Arc::new(items.to_vec());
+    let config = Arc::new(config);
+    let next = Arc::new(AtomicUsize::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+    let (sender, mut events) = mpsc::unbounded_channel();
+    let workers = (0..config.concurrency.clamp(1, pending.len().max(1))).map(|_| spawn()).collect::<Vec<_>>();
+    if workers.len() > 1 {
+        // load the model once up front, the other workers find it in the cache
+        let _ = workers[0].load(config.lang, config.model, &config.options.cancel, &config.options.events).await;
+    }
+
+    for worker in workers {
+        let (items, pending, config, next, stop, sender) = (items.clone(), pending.clone(), config.clone(), next.clone(), stop.clone(), sender.clone());
+        tokio::spawn(async move {
+            let attempts = match config.policy {
+                BatchPolicy::RetryN(n) => n + 1,
+                _ => 1,
+            };
+            while let Some(&i) = pending.get(next.fetch_add(1, Ordering::Relaxed)) {
+                if stop.load(Ordering::Relaxed) || config.options.cancel.is_cancelled() {
+                    break;
+                }
+                let item = &items[i];
+                let _ = sender.send((i, None));
+                let lang = item.lang.unwrap_or(config.lang);
+                let mut result = Err(anyhow!("not attempted"));
+                for _ in 0..attempts {
+                    result = process(&worker, &item.audio, lang, config.model, &config.options, &config.formats).await;
+                    let retryable = match result {
+                        Ok(_) => false,
+                        Err(ref e) => e.downcast_ref::<ConvError>().is_none_or(ConvError::is_retryable),
+                    };
+                    if !retryable {
+                        break;
+                    }
+                }
+                if result.is_err() && config.policy == BatchPolicy::StopOnError {
+                    stop.store(true, Ordering::Relaxed);
+                }
+                let _ = sender.send((i, Some(result.map(|_| ()).map_err(|e| e.to_string()))));
+            }
+        });
+    }
+    drop(sender);
+
+    let mut results = vec![None; items.len()];
+    while let Some((i, result)) = events.recv().await {
+        let audio = &items[i].audio;
+        match result {
+            None => on_event(BatchEvent::Started(audio)),
+            Some(result) => {
+                match result {
+                    Ok(_) => info!("batch {}/{} done: {}", i + 1, items.len(), audio.display()),
+                    Err(ref e) => info!("batch {}/{} failed: {}: {}", i + 1, items.len(), audio.display(), e),
+                }
+                if let (Ok(_), Some(ref path)) = (&result, &config.state) {
+                    state.done.insert(audio.clone());
+                    state.save(path);
+                }
+                on_event(BatchEvent::Finished(audio, result.as_ref().err().map(String::as_str)));
+                results[i] = Some(result);
+            }
+        }
+    }
+
+    // a finished batch starts over the next time it runs
+    if let Some(ref path) = config.state {
+        if pending.iter().all(|&i| matches!(results[i], Some(Ok(_)))) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    // in playlist order no matter which worker finished first, files done in an earlier run count as skipped
+    let mut report = BatchReport::default();
+    for (item, result) in items.iter().zip(results) {
+        match result {
+            Some(Ok(_)) => report.succeeded.push(item.audio.clone()),
+            Some(Err(e)) => report.failed.push((item.audio.clone(), e)),
+            None => report.skipped.push(item.audio.clone()),
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use crate::event::Events;
+    use crate::transcriber::Transcriber;
+    use crate::whisper::{Timings, Utterance};
+
+    use super::*;
+
+    // reports like whisper does while it works, a segment then the progress, or fails outright
+    struct Fake {
+        fail: bool,
+    }
+
+    impl Transcriber for Fake {
+        fn set_language(&mut self, _lang: Language) {}
+
+        fn transcribe(&mut self, audio: &[f32], options: &TranscribeOptions) -> Result<Transcript, ConvError> {
+            if self.fail {
+                return Err(ConvError::Transcribe("fake".to_string()));
+            }
+            let end = audio.len() as i64 / 160;
+            let segment = Utterance { start: 0, end, text: " Hello".to_string(), lang: None, avg_logprob: None, no_speech_prob: None, speaker: None, translation: None };
+            options.events.send(AppEvent::Segment(segment.clone()));
+            options.events.send(AppEvent::TranscribeProgress { percent: 100 });
+            Ok(Transcript { processing_time: Duration::ZERO, utterances: vec![segment], word_utterances: None, timings: Timings::default() })
+        }
+    }
+
+    fn names(events: &[AppEvent]) -> Vec<String> {
+        events.iter().map(|event| serde_json::to_value(event).unwrap()["event"].as_str().unwrap().to_string()).collect()
+    }
+
+    async fn run(fail: bool) -> (Result<Transcript>, Vec<AppEvent>, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("conv-batch-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let audio = dir.join("call.wav");
+        std::fs::copy(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/mulaw-8k.wav"), &audio).unwrap();
+        let worker = WhisperWorker::with_transcriber(Model::Base, Box::new(Fake { fail }));
+        let (events, mut receiver) = Events::channel();
+        let options = TranscribeOptions { events, ..Default::default() };
+        let result = process(&worker, &audio, Language::English, Model::Base, &options, &[Format::Srt, Format::Txt]).await;
+        drop(options);
+        let mut received = vec![];
+        while let Some(event) = receiver.recv().await {
+            received.push(event);
+        }
+        (result, received, dir)
+    }
+
+    #[tokio::test]
+    async fn process_events() {
+        let (result, events, dir) = run(false).await;
+        assert_eq!(result.unwrap().to_txt(), "Hello\n");
+        assert_eq!(names(&events), ["transcribe_started", "segment", "transcribe_progress", "completed"]);
+        let AppEvent::Completed { ref outputs } = events[3] else { unreachable!() };
+        assert_eq!(*outputs, [dir.join("call.srt"), dir.join("call.txt")]);
+        assert_eq!(std::fs::read_to_string(dir.join("call.txt")).unwrap(), "Hello\n");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn process_failure_events() {
+        let (result, events, dir) = run(true).await;
+        assert!(result.is_err());
+        assert_eq!(names(&events), ["transcribe_started", "failed"]);
+        let AppEvent::Failed { code, .. } = &events[1] else { unreachable!() };
+        assert_eq!(*code, "transcribe");
+        assert!(!dir.join("call.srt").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // files are told apart by their length, file i of a batch has i + 1 tenths of a second
+    struct Script {
+        delays: Vec<u64>,
+        // failures left per file
+        failures: Mutex<Vec<u32>>,
+        retryable: bool,
+        // the file that cancels the batch once it's done
+        cancel_at: Option<usize>,
+        attempts: Mutex<Vec<usize>>,
+        running: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    impl Script {
+        fn new(delays: Vec<u64>) -> Self {
+            let failures = Mutex::new(vec![0; delays.len()]);
+            Self { delays, failures, retryable: true, cancel_at: None, attempts: Default::default(), running: AtomicUsize::new(0), peak: AtomicUsize::new(0) }
+        }
+    }
+
+    struct Scripted(Arc<Script>);
+
+    impl Transcriber for Scripted {
+        fn set_language(&mut self, _lang: Language) {}
+
+        fn transcribe(&mut self, audio: &[f32], options: &TranscribeOptions) -> Result<Transcript, ConvError> {
+            let script = &self.0;
+            let i = audio.len() / 1600 - 1;
+            script.attempts.lock().unwrap().push(i);
+            let running = script.running.fetch_add(1, Ordering::SeqCst) + 1;
+            script.peak.fetch_max(running, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(script.delays[i]));
+            script.running.fetch_sub(1, Ordering::SeqCst);
+            if script.cancel_at == Some(i) {
+                options.cancel.cancel();
+            }
+            let mut failures = script.failures.lock().unwrap();
+            if failures[i] > 0 {
+                failures[i] -= 1;
+                return Err(match script.retryable {
+                    true => ConvError::Transcribe(format!("fake {}", i)),
+                    false => ConvError::ModelLoad(format!("fake {}", i)),
+                });
+            }
+            Ok(Transcript { processing_time: Duration::ZERO, utterances: vec![], word_utterances: None, timings: Timings::default() })
+        }
+    }
+
+    // a playlist of 16kHz mono wavs as long as the script has delays
+    fn playlist(script: &Script) -> (Vec<BatchItem>, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("conv-batch-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let items = (0..script.delays.len())
+            .map(|i| {
+                let pcm = vec![0; (i + 1) * 1600 * 2];
+                let mut wav = b"RIFF".to_vec();
+                wav.extend((36 + pcm.len() as u32).to_le_bytes());
+                wav.extend(b"WAVEfmt \x10\0\0\0\x01\0\x01\0");
+                wav.extend(16000u32.to_le_bytes());
+                wav.extend(32000u32.to_le_bytes());
+                wav.extend(b"\x02\0\x10\0data");
+                wav.extend((pcm.len() as u32).to_le_bytes());
+                wav.extend(pcm);
+                let audio = dir.join(format!("{}.wav", i));
+                std::fs::write(&audio, wav).unwrap();
+                BatchItem { audio, lang: None }
+            })
+            .collect();
+        (items, dir)
+    }
+
+    fn batch_config(policy: BatchPolicy, concurrency: usize) -> BatchConfig {
+        BatchConfig { lang: Language::English, model: Model::Base, options: TranscribeOptions::default(), formats: vec![], policy, concurrency, state: None }
+    }
+
+    // the report and the order files finished in
+    async fn run_script(script: Script, config: BatchConfig) -> (BatchReport, Vec<PathBuf>, Arc<Script>, Vec<BatchItem>) {
+        let script = Arc::new(script);
+        let (items, dir) = playlist(&script);
+        let mut finished = vec![];
+        let spawn = || WhisperWorker::with_transcriber(Model::Base, Box::new(Scripted(script.clone())));
+        let report = run_batch_with(&items, config, spawn, |event| {
+            if let BatchEvent::Finished(audio, _) = event {
+                finished.push(audio.to_path_buf());
+            }
+        })
+        .await;
+        std::fs::remove_dir_all(dir).unwrap();
+        (report, finished, script, items)
+    }
+
+    fn audio(items: &[BatchItem], indices: &[usize]) -> Vec<PathBuf> {
+        indices.iter().map(|&i| items[i].audio.clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn pool_runs_files_side_by_side() {
+        let (report, _, script, items) = run_script(Script::new(vec![100; 4]), batch_config(BatchPolicy::SkipAndContinue, 2)).await;
+        assert_eq!(script.peak.load(Ordering::SeqCst), 2);
+        assert_eq!(report.succeeded, audio(&items, &[0, 1, 2, 3]));
+        assert!(report.failed.is_empty() && report.skipped.is_empty());
+    }
+
+    #[tokio::test]
+    async fn report_keeps_playlist_order() {
+        let (report, finished, _, items) = run_script(Script::new(vec![300, 0, 0]), batch_config(BatchPolicy::SkipAndContinue, 3)).await;
+        assert_eq!(finished.last(), Some(&items[0].audio));
+        assert_eq!(report.succeeded, audio(&items, &[0, 1, 2]));
+    }
+
+    #[tokio::test]
+    async fn cancel_stops_the_pool() {
+        let script = Script { cancel_at: Some(0), ..Script::new(vec![50, 100, 0, 0, 0, 0]) };
+        let (report, _, script, items) = run_script(script, batch_config(BatchPolicy::SkipAndContinue, 2)).await;
+        let mut attempts = script.attempts.lock().unwrap().clone();
+        attempts.sort();
+        assert_eq!(attempts, [0, 1]);
+        assert_eq!(report.succeeded, audio(&items, &[0, 1]));
+        assert_eq!(report.skipped, audio(&items, &[2, 3, 4, 5]));
+    }
+
+    #[tokio::test]
+    async fn stop_on_error_skips_the_rest() {
+        let script = Script::new(vec![0; 4]);
+        script.failures.lock().unwrap()[1] = 1;
+        let (report, _, script, items) = run_script(script, batch_config(BatchPolicy::StopOnError, 1)).await;
+        assert_eq!(*script.attempts.lock().unwrap(), [0, 1]);
+        assert_eq!(report.succeeded, audio(&items, &[0]));
+        assert_eq!(report.failed, [(items[1].audio.clone(), "转换失败: fake 1".to_string())]);
+        assert_eq!(report.skipped, audio(&items, &[2, 3]));
+    }
+
+    #[tokio::test]
+    async fn skip_and_continue_goes_on() {
+        let script = Script::new(vec![0; 3]);
+        script.failures.lock().unwrap()[1] = 1;
+        let (report, _, _, items) = run_script(script, batch_config(BatchPolicy::SkipAndContinue, 1)).await;
+        assert_eq!(report.succeeded, audio(&items, &[0, 2]));
+        assert_eq!(report.failed.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn retry_n_tries_again() {
+        let script = Script::new(vec![0; 2]);
+        *script.failures.lock().unwrap() = vec![2, 3];
+        let (report, _, script, items) = run_script(script, batch_config(BatchPolicy::RetryN(2), 1)).await;
+        // the first succeeds on its third attempt, the second is out of attempts after three
+        assert_eq!(*script.attempts.lock().unwrap(), [0, 0, 0, 1, 1, 1]);
+        assert_eq!(report.succeeded, audio(&items, &[0]));
+        assert_eq!(report.failed.iter().map(|(audio, _)| audio.clone()).collect::<Vec<_>>(), audio(&items, &[1]));
+    }
+
+    #[tokio::test]
+    async fn retry_n_gives_up_on_errors_that_would_repeat() {
+        let script = Script { retryable: false, ..Script::new(vec![0; 1]) };
+        script.failures.lock().unwrap()[0] = 1;
+        let (report, _, script, _) = run_script(script, batch_config(BatchPolicy::RetryN(3), 1)).await;
+        assert_eq!(*script.attempts.lock().unwrap(), [0]);
+        assert_eq!(report.failed.len(), 1);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::whisper::Transcript;
+
+// a chapter starts wherever the speech pauses this long, 5s like the mkv chapters
+const GAP: i64 = 500;
+// without enough pauses, one every 5 minutes at the segment starting nearest to it
+const EVERY: i64 = 30000;
+// youtube only takes chapters of at least 10s, and at least three of them
+const MIN_LENGTH: i64 = 1000;
+const MIN_CHAPTERS: usize = 3;
+const TITLE_WORDS: usize = 6;
+const TITLE_CHARS: usize = 30;
+
+// 03:12, or 1:03:12 once the chapters go past an hour
+fn clock(time: i64, hours: bool) -> String {
+    let seconds = time.max(0) / 100;
+    match hours {
+        true => format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60),
+        false => format!("{:02}:{:02}", seconds / 60, seconds % 60),
+    }
+}
+
+// the first few whole words of the segment, the start of a word too long for it alone, e.g. text without spaces.
+// without the punctuation it trails off with
+fn title(text: &str) -> String {
+    let line = text.trim().lines().next().unwrap_or_default();
+    let mut title = String::new();
+    for word in line.split_whitespace().take(TITLE_WORDS) {
+        if title.is_empty() {
+            title = word.chars().take(TITLE_CHARS).collect();
+        } else if title.chars().count() + 1 + word.chars().count() <= TITLE_CHARS {
+            title = format!("{} {}", title, word);
+        } else {
+            break;
+        }
+    }
+    title.trim_end_matches(|c: char| !c.is_alphanumeric()).to_string()
+}
+
+impl Transcript {
+    // the indices of the segments chapters start at, the first always starts one
+    fn chapter_starts(&self) -> Vec<usize> {
+        let segments = &self.utterances;
+        if segments.is_empty() {
+            return vec![];
+        }
+        let end = segments.last().map_or(0, |u| u.end);
+        // a chapter too close to the one before or to the end is dropped, they'd be shorter than youtube allows
+        let keep = |candidates: Vec<usize>| {
+            let mut starts = vec![0];
+            let mut from = 0;
+            for i in candidates {
+                if segments[i].start - from >= MIN_LENGTH && end - segments[i].start >= MIN_LENGTH {
+                    starts.push(i);
+                    from = segments[i].start;
+                }
+            }
+            starts
+        };
+        let paused = keep((1..segments.len()).filter(|&i| segments[i].start - segments[i - 1].end >= GAP).collect());
+        if paused.len() >= MIN_CHAPTERS {
+            return paused;
+        }
+        let nearest = |time: i64| (1..segments.len()).min_by_key(|&i| (segments[i].start - time).abs());
+        let mut every = (1..).map(|n| n * EVERY).take_while(|time| *time < end).filter_map(nearest).collect::<Vec<_>>();
+        every.dedup();
+        keep(every)
+    }
+
+    /// "00:00 Intro" lines to paste into a youtube description, the first at 00:00 as youtube requires
+    pub fn to_chapters(&self) -> String {
+        let starts = self.chapter_starts();
+        let hours = starts.last().is_some_and(|&i| self.utterances[i].start >= 360000);
+        let mut chapters = String::new();
+        for (n, &i) in starts.iter().enumerate() {
+            let start = if n == 0 { 0 } else { self.utterances[i].start };
+            let title = match title(&self.utterances[i].text) {
+                title if title.is_empty() => format!("Chapter {}", n + 1),
+                title => title,
+            };
+            chapters += &format!("{} {}\n", clock(start, hours), title);
+        }
+        chapters
+    }
+}
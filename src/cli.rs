@@ -0,0 +1,423 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use clap_complete::Shell;
+use tracing::Level;
+
+use crate::config::{self, Language, Model};
+use crate::markers::FrameRate;
+use crate::naming::Naming;
+use crate::translate::BilingualOrder;
+use crate::utils::{ChannelSelect, Resample};
+use crate::whisper::Format;
+
+#[derive(Debug, Default, Parser)]
+#[command(about = "使用FFmpeg合并音频，图片和字幕生成视频的工具", disable_version_flag = true)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// 配置文件，默认为 ~/.config/conv/config.toml
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// 服务器支持时把模型分成几段同时下载，默认为配置文件中的 connections 或 1
+    #[arg(long, global = true, value_parser = clap::value_parser!(u8).range(1..=config::MAX_CONNECTIONS as i64))]
+    pub connections: Option<u8>,
+    /// 音频/图片/字幕文件，按扩展名填入对应位置，视频链接填入链接框
+    pub paths: Vec<PathBuf>,
+    #[arg(long, value_enum)]
+    pub lang: Option<Language>,
+    #[arg(long, value_enum)]
+    pub model: Option<Model>,
+    /// auto: 必要时用FFmpeg转换为16kHz单声道; assert: 只接受16kHz单声道WAV，否则报错
+    #[arg(long, value_enum, default_value_t)]
+    pub resample: Resample,
+    /// 立体声只转换一个声道，每个声道一位说话人时分别转换左右声道
+    #[arg(long, value_enum, default_value_t)]
+    pub channel: ChannelSelect,
+    /// 术语表文件，每行一个术语，用作Whisper的初始提示
+    #[arg(long)]
+    pub glossary: Option<PathBuf>,
+    /// 每隔多少秒重新检测一次语言并标注到字幕段上，用于混合多种语言的音频
+    #[arg(long)]
+    pub language_window: Option<u32>,
+    /// 已有的歌词或文本，每行一条字幕，只用音频确定时间，无界面模式请用 transcribe --align
+    #[arg(long, value_name = "FILE", conflicts_with = "no_gui")]
+    pub align: Option<PathBuf>,
+    /// 启动后立即开始转换字幕
+    #[arg(long)]
+    pub auto_start: bool,
+    /// 不启动界面，转换字幕后退出
+    #[arg(long)]
+    pub no_gui: bool,
+    /// 无界面模式下转换后合并音频/图片/字幕
+    #[arg(long, requires = "no_gui")]
+    pub merge: bool,
+    /// 合并为带软字幕和章节的MKV，不生成画面
+    #[arg(long, requires = "merge")]
+    pub mkv: bool,
+    /// 不转交给已运行的实例，另开一个窗口
+    #[arg(long)]
+    pub new_instance: bool,
+    /// 日志文件的级别: error, warn, info, debug, trace (默认 info)，环境变量 RUST_LOG 优先
+    #[arg(long)]
+    pub log_level: Option<Level>,
+    /// 输出版本号，配合 --verbose 输出诊断信息
+    #[arg(short = 'V', long)]
+    pub version: bool,
+    /// 在标准错误输出更详细的日志，-v 为 debug，-vv 为 trace
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+    /// 只输出错误和最终结果
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// 无界面模式下与参考字幕对比，输出词错误率
+    #[arg(long, requires = "no_gui")]
+    pub reference: Option<PathBuf>,
+    /// 无界面模式下忽略英语模型与语言不匹配的检查
+    #[arg(long)]
+    pub force: bool,
+    /// 无界面模式下同时导出JSON
+    #[arg(long)]
+    pub json: bool,
+    /// 只下载模型后退出，不转换
+    #[arg(long, value_enum, value_name = "MODEL")]
+    pub download: Option<Model>,
+    /// 进度的输出方式，json 为每行一个事件的 JSON，输出到标准错误
+    #[arg(long, value_enum, default_value_t)]
+    pub progress: ProgressFormat,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ProgressFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 不启动界面，把音频转换为字幕
+    Transcribe(TranscribeArgs),
+    /// 监视目录，转换新放入的音频/视频，Ctrl+C 在当前文件完成后退出
+    Watch(WatchArgs),
+    /// 合并音频、图片/视频和字幕，与界面上的合并按钮相同
+    Merge(MergeArgs),
+    /// 转换字幕格式，可同时平移、合并和整理字幕
+    Convert(ConvertArgs),
+    /// 预先下载模型
+    DownloadModel(DownloadModelArgs),
+    /// 显示配置文件的位置
+    Config(ConfigArgs),
+    /// 输出 shell 补全脚本，如 conv completions bash > /etc/bash_completion.d/conv
+    Completions(CompletionsArgs),
+    /// 以 HTTP 服务运行，接收上传的音频并排队转换
+    Serve(ServeArgs),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ServeArgs {
+    /// 监听的地址
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub bind: SocketAddr,
+    /// 请求未指定 lang 时使用的语言
+    #[arg(long, value_enum)]
+    pub lang: Option<Language>,
+    /// 启动时加载，所有任务共用
+    #[arg(long, value_enum)]
+    pub model: Option<Model>,
+    /// 上传文件的大小上限，MB
+    #[arg(long, default_value_t = 500)]
+    pub max_upload: usize,
+    /// 最多排队的任务数，队列满时返回 503
+    #[arg(long, default_value_t = 16)]
+    pub queue: usize,
+    /// 要求请求带上 Authorization: Bearer <TOKEN>，也可用环境变量 CONV_SERVE_TOKEN 设置
+    #[arg(long)]
+    pub token: Option<String>,
+    #[command(flatten)]
+    pub webhook: WebhookArgs,
+    /// 不接受表单字段 webhook 为单个任务指定的地址
+    #[arg(long, conflicts_with = "job_webhook_host")]
+    pub no_job_webhooks: bool,
+    /// 单个任务指定的 webhook 只能发往这些主机，可重复或用逗号分隔
+    #[arg(long, value_delimiter = ',')]
+    pub job_webhook_host: Vec<String>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct WebhookArgs {
+    /// 每个任务完成或失败时 POST 一个 JSON 到这个地址，失败时重试 3 次
+    #[arg(long)]
+    pub webhook: Option<String>,
+    /// 用于签名的密钥，请求带上 X-Conv-Signature: sha256=<请求体的 HMAC-SHA256>
+    #[arg(long)]
+    pub webhook_secret: Option<String>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct CompletionsArgs {
+    #[arg(value_enum)]
+    pub shell: Shell,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ConfigArgs {
+    /// 输出带注释的默认配置
+    #[arg(long)]
+    pub print_default: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct DownloadModelArgs {
+    /// 要下载的模型
+    #[arg(value_enum, required_unless_present = "all")]
+    pub models: Vec<Model>,
+    /// 下载全部模型
+    #[arg(long, conflicts_with = "models")]
+    pub all: bool,
+    /// 模型保存的目录，默认为配置文件中的 model_dir 或当前目录
+    #[arg(long)]
+    pub dir: Option<PathBuf>,
+    /// 校验 SHA-1，已存在但校验失败的模型会重新下载
+    #[arg(long)]
+    pub verify: bool,
+    /// 已存在时也重新下载
+    #[arg(long)]
+    pub force: bool,
+    /// 进度的输出方式，json 为每行一个事件的 JSON，输出到标准错误
+    #[arg(long, value_enum, default_value_t)]
+    pub progress: ProgressFormat,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ConvertArgs {
+    /// 字幕文件，扩展名无法识别时按内容判断格式
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+    /// 输出格式，用逗号分隔
+    #[arg(long, value_enum, value_delimiter = ',', required_unless_present = "markers")]
+    pub to: Vec<Format>,
+    /// 同时输出 FCPXML 时间线标记，参数为帧率，如 25、29.97 (非丢帧时间码)
+    #[arg(long, value_name = "FPS")]
+    pub markers: Option<FrameRate>,
+    /// 时间平移，毫秒，可为负数
+    #[arg(long, default_value_t = 0, allow_hyphen_values = true)]
+    pub shift_ms: i64,
+    /// 合并间隔不超过这么多毫秒的相邻字幕
+    #[arg(long)]
+    pub merge_gap: Option<i64>,
+    /// 合并后每条字幕最多的字符数
+    #[arg(long, default_value_t = 84, requires = "merge_gap")]
+    pub max_chars: usize,
+    /// 整理标点前后的空格
+    #[arg(long)]
+    pub clean: bool,
+    /// 每行最多的字符数，超出时换行
+    #[arg(long)]
+    pub wrap: Option<usize>,
+    /// 输出目录，默认与输入相同
+    #[arg(long)]
+    pub out_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct MergeArgs {
+    /// 音频文件
+    #[arg(long)]
+    pub audio: PathBuf,
+    /// 字幕文件，不指定时只合并音频和画面
+    #[arg(long)]
+    pub subtitle: Option<PathBuf>,
+    /// 背景图片
+    #[arg(long, group = "background")]
+    pub image: Option<PathBuf>,
+    /// 背景视频，循环播放到音频结束
+    #[arg(long, group = "background")]
+    pub video: Option<PathBuf>,
+    /// 背景颜色，如 000000
+    #[arg(long, group = "background", value_parser = parse_color)]
+    pub color: Option<[u8; 3]>,
+    /// 以音频波形作为画面
+    #[arg(long, group = "background")]
+    pub waveform: bool,
+    /// 输出文件，默认为与音频同名的 mp4 (--mkv 时为 mkv)
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+    /// 画面分辨率，如 1080p、720p 或 1920x1080
+    #[arg(long, value_parser = parse_resolution)]
+    pub resolution: Option<(u32, u32)>,
+    /// 把字幕烧录进画面，否则作为可关闭的字幕轨
+    #[arg(long, requires = "subtitle")]
+    pub burn: bool,
+    /// 合并为带软字幕和章节的MKV，不生成画面
+    #[arg(long, requires = "subtitle", conflicts_with_all = ["background", "resolution", "burn"])]
+    pub mkv: bool,
+    /// 烧录字幕的位置，小键盘方位 (2 底部, 5 中间, 8 顶部)，默认为 2
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=9))]
+    pub alignment: Option<u8>,
+    /// 烧录字幕与上下边缘的距离，默认为 10
+    #[arg(long)]
+    pub margin_v: Option<u32>,
+    /// 字幕时间偏移，毫秒，可为负数
+    #[arg(long, default_value_t = 0, allow_hyphen_values = true)]
+    pub offset: i64,
+    /// 输出文件已存在时覆盖
+    #[arg(long)]
+    pub overwrite: bool,
+    /// 进度的输出方式，json 为每行一个事件的 JSON，输出到标准错误
+    #[arg(long, value_enum, default_value_t)]
+    pub progress: ProgressFormat,
+    #[command(flatten)]
+    pub webhook: WebhookArgs,
+}
+
+// 000000 or #ff8800
+fn parse_color(s: &str) -> Result<[u8; 3], String> {
+    let hex = s.trim_start_matches('#');
+    let channel = |i: usize| hex.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok([r, g, b]),
+        _ => Err(format!("无效的颜色 \"{}\"，应为 RRGGBB", s)),
+    }
+}
+
+// 1080p is 1920x1080, any height gets a 16:9 width rounded to an even number for yuv420p
+fn parse_resolution(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("无效的分辨率 \"{}\"，应为 1080p 或 1920x1080", s);
+    let lower = s.to_lowercase();
+    let (width, height) = match lower.strip_suffix('p') {
+        Some(height) => {
+            let height = height.parse::<u32>().map_err(|_| invalid())?;
+            ((height * 16 / 9).div_ceil(2) * 2, height)
+        }
+        None => {
+            let (width, height) = lower.split_once('x').ok_or_else(invalid)?;
+            (width.parse().map_err(|_| invalid())?, height.parse().map_err(|_| invalid())?)
+        }
+    };
+    if width == 0 || height == 0 || width % 2 == 1 || height % 2 == 1 {
+        return Err(format!("{}，宽高须为正偶数", invalid()));
+    }
+    Ok((width, height))
+}
+
+#[derive(Debug, clap::Args)]
+pub struct WatchArgs {
+    /// 监视的目录，字幕写在音频旁边
+    pub dir: PathBuf,
+    #[arg(long, value_enum)]
+    pub lang: Option<Language>,
+    #[arg(long, value_enum)]
+    pub model: Option<Model>,
+    /// 输出格式，用逗号分隔，默认为 lrc,srt,vtt
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub format: Vec<Format>,
+    /// 字幕文件的命名模板，可用 {stem} {lang} {ext}，如 "{stem}.{lang}.{ext}"，
+    /// 或预设 plain (a.srt)、media-server (a.en.srt)、subs-dir (Subs/a.en.srt)，默认为配置文件中的 naming 或 plain
+    #[arg(long, value_name = "TEMPLATE")]
+    pub naming: Option<Naming>,
+    /// 同时监视子目录
+    #[arg(long, short)]
+    pub recursive: bool,
+    /// 文件大小多少秒不变后才开始转换，避免处理还在复制的文件
+    #[arg(long, default_value_t = 5)]
+    pub settle: u64,
+    /// 进度的输出方式，json 为每行一个事件的 JSON，输出到标准错误
+    #[arg(long, value_enum, default_value_t)]
+    pub progress: ProgressFormat,
+    #[command(flatten)]
+    pub webhook: WebhookArgs,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct TranscribeArgs {
+    /// 音频文件、目录，或视频链接 (http(s):// 开头，或 youtu.be/... 等)，链接用 yt-dlp 下载音频，字幕以视频标题命名
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+    /// 保留从链接下载的音频，放在字幕旁边
+    #[arg(long)]
+    pub keep_audio: bool,
+    #[arg(long, value_enum)]
+    pub lang: Option<Language>,
+    #[arg(long, value_enum)]
+    pub model: Option<Model>,
+    /// 输出格式，用逗号分隔或重复指定，重复的只输出一次，默认为 lrc,srt,vtt
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub format: Vec<Format>,
+    /// 字幕文件的命名模板，可用 {stem} {lang} {ext}，如 "{stem}.{lang}.{ext}"，
+    /// 或预设 plain (a.srt)、media-server (a.en.srt)、subs-dir (Subs/a.en.srt)，默认为配置文件中的 naming 或 plain
+    #[arg(long, value_name = "TEMPLATE")]
+    pub naming: Option<Naming>,
+    /// 翻译为英语
+    #[arg(long)]
+    pub translate: bool,
+    /// 按词输出时间轴
+    #[arg(long)]
+    pub word_timestamps: bool,
+    /// 字幕仍按段落输出，同时写一份按词的 JSON (未指定 json 格式时自动加上)，只需识别一遍
+    #[arg(long, conflicts_with_all = ["word_timestamps", "stdout"])]
+    pub word_json: bool,
+    /// 字幕写入的目录，默认与音频相同
+    #[arg(long)]
+    pub output_dir: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t)]
+    pub resample: Resample,
+    /// 立体声只转换一个声道，每个声道一位说话人时分别转换左右声道
+    #[arg(long, value_enum, default_value_t)]
+    pub channel: ChannelSelect,
+    /// 每段字幕最多生成的 token 数，用于限制失控的长段落，0 为不限制
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(..=i32::MAX as i64))]
+    pub max_tokens: u32,
+    /// 忽略英语模型与语言不匹配的检查
+    #[arg(long)]
+    pub force: bool,
+    /// 输入为目录时包含子目录
+    #[arg(long, short)]
+    pub recursive: bool,
+    /// 字幕已存在时重新转换
+    #[arg(long)]
+    pub overwrite: bool,
+    /// 只处理匹配的文件，如 "*.mp3" 或 "2023/**"，可重复
+    #[arg(long)]
+    pub include: Vec<String>,
+    /// 跳过匹配的文件，可重复
+    #[arg(long)]
+    pub exclude: Vec<String>,
+    /// 把字幕输出到标准输出而不写文件，只能有一个音频和一种格式
+    #[arg(long, conflicts_with = "output_dir")]
+    pub stdout: bool,
+    /// 转换后翻译为这种语言，另存为 a.ja.srt 等，需要同时指定 --translator
+    #[arg(long, value_enum, requires = "translator")]
+    pub translate_to: Option<Language>,
+    /// 翻译用的命令，从标准输入每行读一段原文，向标准输出每行写一段译文，
+    /// 环境变量 CONV_FROM / CONV_TO 为语言代码
+    #[arg(long, requires = "translate_to")]
+    pub translator: Option<String>,
+    /// 每次交给翻译命令的段数
+    #[arg(long, default_value_t = 20)]
+    pub translate_batch: usize,
+    /// 每隔多少秒重新检测一次语言并标注到字幕段上，用于混合多种语言的音频
+    #[arg(long)]
+    pub language_window: Option<u32>,
+    /// 在每段字幕前加上 [zh] 这样的语言标记
+    #[arg(long)]
+    pub lang_tags: bool,
+    /// 只保留这种语言的字幕段，用于自动识别语言的混合音频
+    #[arg(long, value_enum)]
+    pub only_lang: Option<Language>,
+    /// 已有的歌词或文本，每行一条字幕，不用识别出的文字，只用音频确定每行的时间。只能有一个音频
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["translate", "word_timestamps", "word_json", "bilingual", "lang_tags", "only_lang"])]
+    pub align: Option<PathBuf>,
+    /// 每条字幕上下两行，原文和英文翻译，英文由第二遍识别得到。指定 --translate-to 时改用 --translator 的翻译
+    #[arg(long, conflicts_with_all = ["translate", "stdout"])]
+    pub bilingual: bool,
+    /// 双语字幕中哪一行在上
+    #[arg(long, value_enum, default_value_t)]
+    pub bilingual_order: BilingualOrder,
+    /// 进度的输出方式，json 为每行一个事件的 JSON，输出到标准错误
+    #[arg(long, value_enum, default_value_t)]
+    pub progress: ProgressFormat,
+    #[command(flatten)]
+    pub webhook: WebhookArgs,
+}
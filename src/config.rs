@@ -1,16 +1,25 @@
 use std::cmp::min;
 use std::fmt::Display;
-use std::fs::File;
-use std::io::{ErrorKind, Write};
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
-use once_cell::sync::Lazy;
-use reqwest::Client;
+use clap::ValueEnum;
+use once_cell::sync::{Lazy, OnceCell};
+use reqwest::{header, Client, Proxy, StatusCode};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
+use sha1::{Digest, Sha1};
+use tracing::{error, info};
 
-use crate::utils::DOWNLOADING;
+use crate::error::{ConvError, DownloadError};
+use crate::event::{AppEvent, DownloadStage, Events};
+use crate::job::CancelToken;
+use crate::utils::{available_memory, DOWNLOADING};
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
 pub enum Language {
     #[clap(name = "auto")]
     Auto,
@@ -214,6 +223,48 @@ pub enum Language {
     Sundanese,
 }
 
+// both are written as the codes the command line takes, "zh" and "medium"
+fn serialize_code<T: ValueEnum, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(value.to_possible_value().unwrap().get_name())
+}
+
+fn deserialize_code<'de, T: ValueEnum, D: Deserializer<'de>>(deserializer: D, kind: &str) -> Result<T, D::Error> {
+    let code = String::deserialize(deserializer)?;
+    T::from_str(&code, true).map_err(|_| {
+        let expected = T::value_variants()
+            .iter()
+            .filter_map(|v| v.to_possible_value())
+            .map(|v| v.get_name().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        D::Error::custom(format!("unknown {} \"{}\", expected one of {}", kind, code, expected))
+    })
+}
+
+impl Serialize for Language {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_code(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Language {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_code(deserializer, "language")
+    }
+}
+
+impl Serialize for Model {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_code(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Model {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_code(deserializer, "model")
+    }
+}
+
 impl From<Language> for &str {
     fn from(val: Language) -> Self {
         match val {
@@ -321,7 +372,377 @@ impl From<Language> for &str {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+// built from value_variants so it can't drift from the enum
+static LANGUAGES: Lazy<Vec<(Language, &'static str, &'static str)>> = Lazy::new(|| {
+    Language::value_variants()
+        .iter()
+        .map(|l| (*l, <&str>::from(*l), l.name()))
+        .collect()
+});
+
+impl Language {
+    /// (variant, ISO code, English name)
+    pub fn all() -> &'static [(Language, &'static str, &'static str)] {
+        &LANGUAGES
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Auto => "Auto",
+            Self::English => "English",
+            Self::Chinese => "Chinese",
+            Self::German => "German",
+            Self::Spanish => "Spanish",
+            Self::Russian => "Russian",
+            Self::Korean => "Korean",
+            Self::French => "French",
+            Self::Japanese => "Japanese",
+            Self::Portuguese => "Portuguese",
+            Self::Turkish => "Turkish",
+            Self::Polish => "Polish",
+            Self::Catalan => "Catalan",
+            Self::Dutch => "Dutch",
+            Self::Arabic => "Arabic",
+            Self::Swedish => "Swedish",
+            Self::Italian => "Italian",
+            Self::Indonesian => "Indonesian",
+            Self::Hindi => "Hindi",
+            Self::Finnish => "Finnish",
+            Self::Vietnamese => "Vietnamese",
+            Self::Hebrew => "Hebrew",
+            Self::Ukrainian => "Ukrainian",
+            Self::Greek => "Greek",
+            Self::Malay => "Malay",
+            Self::Czech => "Czech",
+            Self::Romanian => "Romanian",
+            Self::Danish => "Danish",
+            Self::Hungarian => "Hungarian",
+            Self::Tamil => "Tamil",
+            Self::Norwegian => "Norwegian",
+            Self::Thai => "Thai",
+            Self::Urdu => "Urdu",
+            Self::Croatian => "Croatian",
+            Self::Bulgarian => "Bulgarian",
+            Self::Lithuanian => "Lithuanian",
+            Self::Latin => "Latin",
+            Self::Maori => "Maori",
+            Self::Malayalam => "Malayalam",
+            Self::Welsh => "Welsh",
+            Self::Slovak => "Slovak",
+            Self::Telugu => "Telugu",
+            Self::Persian => "Persian",
+            Self::Latvian => "Latvian",
+            Self::Bengali => "Bengali",
+            Self::Serbian => "Serbian",
+            Self::Azerbaijani => "Azerbaijani",
+            Self::Slovenian => "Slovenian",
+            Self::Kannada => "Kannada",
+            Self::Estonian => "Estonian",
+            Self::Macedonian => "Macedonian",
+            Self::Breton => "Breton",
+            Self::Basque => "Basque",
+            Self::Icelandic => "Icelandic",
+            Self::Armenian => "Armenian",
+            Self::Nepali => "Nepali",
+            Self::Mongolian => "Mongolian",
+            Self::Bosnian => "Bosnian",
+            Self::Kazakh => "Kazakh",
+            Self::Albanian => "Albanian",
+            Self::Swahili => "Swahili",
+            Self::Galician => "Galician",
+            Self::Marathi => "Marathi",
+            Self::Punjabi => "Punjabi",
+            Self::Sinhala => "Sinhala",
+            Self::Khmer => "Khmer",
+            Self::Shona => "Shona",
+            Self::Yoruba => "Yoruba",
+            Self::Somali => "Somali",
+            Self::Afrikaans => "Afrikaans",
+            Self::Occitan => "Occitan",
+            Self::Georgian => "Georgian",
+            Self::Belarusian => "Belarusian",
+            Self::Tajik => "Tajik",
+            Self::Sindhi => "Sindhi",
+            Self::Gujarati => "Gujarati",
+            Self::Amharic => "Amharic",
+            Self::Yiddish => "Yiddish",
+            Self::Lao => "Lao",
+            Self::Uzbek => "Uzbek",
+            Self::Faroese => "Faroese",
+            Self::HaitianCreole => "Haitian Creole",
+            Self::Pashto => "Pashto",
+            Self::Turkmen => "Turkmen",
+            Self::Nynorsk => "Nynorsk",
+            Self::Maltese => "Maltese",
+            Self::Sanskrit => "Sanskrit",
+            Self::Luxembourgish => "Luxembourgish",
+            Self::Myanmar => "Myanmar",
+            Self::Tibetan => "Tibetan",
+            Self::Tagalog => "Tagalog",
+            Self::Malagasy => "Malagasy",
+            Self::Assamese => "Assamese",
+            Self::Tatar => "Tatar",
+            Self::Hawaiian => "Hawaiian",
+            Self::Lingala => "Lingala",
+            Self::Hausa => "Hausa",
+            Self::Bashkir => "Bashkir",
+            Self::Javanese => "Javanese",
+            Self::Sundanese => "Sundanese",
+        }
+    }
+
+    pub fn native_name(&self) -> &'static str {
+        match self {
+            Self::Auto => "自动检测",
+            Self::English => "English",
+            Self::Chinese => "中文",
+            Self::German => "Deutsch",
+            Self::Spanish => "Español",
+            Self::Russian => "Русский",
+            Self::Korean => "한국어",
+            Self::French => "Français",
+            Self::Japanese => "日本語",
+            Self::Portuguese => "Português",
+            Self::Turkish => "Türkçe",
+            Self::Polish => "Polski",
+            Self::Catalan => "Català",
+            Self::Dutch => "Nederlands",
+            Self::Arabic => "العربية",
+            Self::Swedish => "Svenska",
+            Self::Italian => "Italiano",
+            Self::Indonesian => "Bahasa Indonesia",
+            Self::Hindi => "हिन्दी",
+            Self::Finnish => "Suomi",
+            Self::Vietnamese => "Tiếng Việt",
+            Self::Hebrew => "עברית",
+            Self::Ukrainian => "Українська",
+            Self::Greek => "Ελληνικά",
+            Self::Malay => "Bahasa Melayu",
+            Self::Czech => "Čeština",
+            Self::Romanian => "Română",
+            Self::Danish => "Dansk",
+            Self::Hungarian => "Magyar",
+            Self::Tamil => "தமிழ்",
+            Self::Norwegian => "Norsk",
+            Self::Thai => "ไทย",
+            Self::Urdu => "اردو",
+            Self::Croatian => "Hrvatski",
+            Self::Bulgarian => "Български",
+            Self::Lithuanian => "Lietuvių",
+            Self::Latin => "Latina",
+            Self::Maori => "Te Reo Māori",
+            Self::Malayalam => "മലയാളം",
+            Self::Welsh => "Cymraeg",
+            Self::Slovak => "Slovenčina",
+            Self::Telugu => "తెలుగు",
+            Self::Persian => "فارسی",
+            Self::Latvian => "Latviešu",
+            Self::Bengali => "বাংলা",
+            Self::Serbian => "Српски",
+            Self::Azerbaijani => "Azərbaycan",
+            Self::Slovenian => "Slovenščina",
+            Self::Kannada => "ಕನ್ನಡ",
+            Self::Estonian => "Eesti",
+            Self::Macedonian => "Македонски",
+            Self::Breton => "Brezhoneg",
+            Self::Basque => "Euskara",
+            Self::Icelandic => "Íslenska",
+            Self::Armenian => "Հայերեն",
+            Self::Nepali => "नेपाली",
+            Self::Mongolian => "Монгол",
+            Self::Bosnian => "Bosanski",
+            Self::Kazakh => "Қазақ",
+            Self::Albanian => "Shqip",
+            Self::Swahili => "Kiswahili",
+            Self::Galician => "Galego",
+            Self::Marathi => "मराठी",
+            Self::Punjabi => "ਪੰਜਾਬੀ",
+            Self::Sinhala => "සිංහල",
+            Self::Khmer => "ខ្មែរ",
+            Self::Shona => "chiShona",
+            Self::Yoruba => "Yorùbá",
+            Self::Somali => "Soomaali",
+            Self::Afrikaans => "Afrikaans",
+            Self::Occitan => "Occitan",
+            Self::Georgian => "ქართული",
+            Self::Belarusian => "Беларуская",
+            Self::Tajik => "Тоҷикӣ",
+            Self::Sindhi => "سنڌي",
+            Self::Gujarati => "ગુજરાતી",
+            Self::Amharic => "አማርኛ",
+            Self::Yiddish => "ייִדיש",
+            Self::Lao => "ລາວ",
+            Self::Uzbek => "Oʻzbek",
+            Self::Faroese => "Føroyskt",
+            Self::HaitianCreole => "Kreyòl Ayisyen",
+            Self::Pashto => "پښتو",
+            Self::Turkmen => "Türkmen",
+            Self::Nynorsk => "Nynorsk",
+            Self::Maltese => "Malti",
+            Self::Sanskrit => "संस्कृतम्",
+            Self::Luxembourgish => "Lëtzebuergesch",
+            Self::Myanmar => "မြန်မာ",
+            Self::Tibetan => "བོད་སྐད",
+            Self::Tagalog => "Tagalog",
+            Self::Malagasy => "Malagasy",
+            Self::Assamese => "অসমীয়া",
+            Self::Tatar => "Татар",
+            Self::Hawaiian => "ʻŌlelo Hawaiʻi",
+            Self::Lingala => "Lingála",
+            Self::Hausa => "Hausa",
+            Self::Bashkir => "Башҡорт",
+            Self::Javanese => "Basa Jawa",
+            Self::Sundanese => "Basa Sunda",
+        }
+    }
+
+    pub fn label(&self) -> String {
+        format!("{} — {} ({})", <&str>::from(*self), self.name(), self.native_name())
+    }
+
+    /// None for Auto
+    pub fn group(&self) -> Option<LanguageGroup> {
+        match self {
+            Self::Auto => None,
+            Self::English
+            | Self::German
+            | Self::Spanish
+            | Self::Russian
+            | Self::French
+            | Self::Portuguese
+            | Self::Polish
+            | Self::Catalan
+            | Self::Dutch
+            | Self::Swedish
+            | Self::Italian
+            | Self::Finnish
+            | Self::Ukrainian
+            | Self::Greek
+            | Self::Czech
+            | Self::Romanian
+            | Self::Danish
+            | Self::Hungarian
+            | Self::Norwegian
+            | Self::Croatian
+            | Self::Bulgarian
+            | Self::Lithuanian
+            | Self::Latin
+            | Self::Welsh
+            | Self::Slovak
+            | Self::Latvian
+            | Self::Serbian
+            | Self::Slovenian
+            | Self::Estonian
+            | Self::Macedonian
+            | Self::Breton
+            | Self::Basque
+            | Self::Icelandic
+            | Self::Bosnian
+            | Self::Albanian
+            | Self::Galician
+            | Self::Occitan
+            | Self::Belarusian
+            | Self::Yiddish
+            | Self::Faroese
+            | Self::Nynorsk
+            | Self::Maltese
+            | Self::Luxembourgish
+            | Self::Tatar
+            | Self::Bashkir
+            => Some(LanguageGroup::European),
+            Self::Chinese
+            | Self::Korean
+            | Self::Japanese
+            | Self::Mongolian
+            | Self::Tibetan
+            => Some(LanguageGroup::EastAsian),
+            Self::Indonesian
+            | Self::Vietnamese
+            | Self::Malay
+            | Self::Thai
+            | Self::Khmer
+            | Self::Lao
+            | Self::Myanmar
+            | Self::Tagalog
+            | Self::Javanese
+            | Self::Sundanese
+            => Some(LanguageGroup::SoutheastAsian),
+            Self::Hindi
+            | Self::Tamil
+            | Self::Urdu
+            | Self::Malayalam
+            | Self::Telugu
+            | Self::Bengali
+            | Self::Kannada
+            | Self::Nepali
+            | Self::Marathi
+            | Self::Punjabi
+            | Self::Sinhala
+            | Self::Sindhi
+            | Self::Gujarati
+            | Self::Sanskrit
+            | Self::Assamese
+            => Some(LanguageGroup::SouthAsian),
+            Self::Turkish
+            | Self::Arabic
+            | Self::Hebrew
+            | Self::Persian
+            | Self::Azerbaijani
+            | Self::Armenian
+            | Self::Kazakh
+            | Self::Georgian
+            | Self::Tajik
+            | Self::Uzbek
+            | Self::Pashto
+            | Self::Turkmen
+            => Some(LanguageGroup::WestAsian),
+            Self::Swahili
+            | Self::Shona
+            | Self::Yoruba
+            | Self::Somali
+            | Self::Afrikaans
+            | Self::Amharic
+            | Self::Malagasy
+            | Self::Lingala
+            | Self::Hausa
+            => Some(LanguageGroup::African),
+            Self::Maori
+            | Self::Hawaiian
+            => Some(LanguageGroup::Oceanian),
+            Self::HaitianCreole => Some(LanguageGroup::American),
+        }
+    }
+}
+
+/// where a language is mainly spoken, in the order the picker shows them
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LanguageGroup {
+    European,
+    EastAsian,
+    SoutheastAsian,
+    SouthAsian,
+    WestAsian,
+    African,
+    Oceanian,
+    American,
+}
+
+impl LanguageGroup {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::European => "欧洲",
+            Self::EastAsian => "东亚",
+            Self::SoutheastAsian => "东南亚",
+            Self::SouthAsian => "南亚",
+            Self::WestAsian => "西亚与中亚",
+            Self::African => "非洲",
+            Self::Oceanian => "大洋洲",
+            Self::American => "美洲",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
 pub enum Model {
     #[clap(name = "tiny.en")]
     TinyEnglish,
@@ -365,40 +786,388 @@ impl Display for Model {
 
 pub static FILE_SIZE: AtomicU64 = AtomicU64::new(!0);
 pub static DOWNLOADED: AtomicU64 = AtomicU64::new(0);
-pub static CLIENT: Lazy<Client> = Lazy::new(|| Client::new());
+pub static CLIENT: Lazy<Client> = Lazy::new(|| match PROXY.get() {
+    Some(proxy) => Client::builder().proxy(proxy.clone()).build().unwrap_or_default(),
+    None => Client::new(),
+});
+static PROXY: OnceCell<Proxy> = OnceCell::new();
+static MODEL_DIR: OnceCell<PathBuf> = OnceCell::new();
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(1);
+pub const MAX_CONNECTIONS: usize = 16;
+// smaller ranges aren't worth another request
+const MIN_RANGE: u64 = 8 << 20;
+
+/// has to be called before the first request, later calls are ignored
+pub fn set_proxy(url: &str) -> Result<(), reqwest::Error> {
+    let _ = PROXY.set(Proxy::all(url)?);
+    Ok(())
+}
+
+/// how many ranges a download is split into when the server accepts them, unlike the proxy later calls win
+pub fn set_connections(connections: usize) {
+    CONNECTIONS.store(connections.clamp(1, MAX_CONNECTIONS), Ordering::Relaxed);
+}
+
+pub fn set_model_dir(dir: PathBuf) {
+    let _ = MODEL_DIR.set(dir);
+}
+
+pub fn model_dir() -> PathBuf {
+    match MODEL_DIR.get() {
+        Some(dir) => dir.clone(),
+        None => std::env::current_dir().unwrap(),
+    }
+}
 
 impl Model {
+    /// rough seconds of processing per second of audio on a laptop CPU
+    pub fn realtime_factor(&self) -> f64 {
+        match self {
+            Self::TinyEnglish | Self::Tiny => 0.05,
+            Self::BaseEnglish | Self::Base => 0.1,
+            Self::SmallEnglish | Self::Small => 0.3,
+            Self::MediumEnglish | Self::Medium => 0.8,
+            Self::Large | Self::LargeV1 => 1.6,
+        }
+    }
+
+    pub fn is_english_only(&self) -> bool {
+        matches!(self, Self::TinyEnglish | Self::BaseEnglish | Self::SmallEnglish | Self::MediumEnglish)
+    }
+
+    /// small.en -> small
+    pub fn multilingual(&self) -> Self {
+        match self {
+            Self::TinyEnglish => Self::Tiny,
+            Self::BaseEnglish => Self::Base,
+            Self::SmallEnglish => Self::Small,
+            Self::MediumEnglish => Self::Medium,
+            model => *model,
+        }
+    }
+
+    pub fn language_warning(&self, lang: Language) -> Option<String> {
+        if !self.is_english_only() {
+            return None;
+        }
+        match lang {
+            Language::English => None,
+            Language::Auto => Some(format!("{} 只支持英语，自动检测语言没有意义", self)),
+            lang => Some(format!("{} 只支持英语，无法转换{}", self, lang.name())),
+        }
+    }
+
+    /// MB needed to load the model, from the whisper.cpp README
+    pub fn required_memory(&self) -> u64 {
+        match self {
+            Self::TinyEnglish | Self::Tiny => 273,
+            Self::BaseEnglish | Self::Base => 388,
+            Self::SmallEnglish | Self::Small => 852,
+            Self::MediumEnglish | Self::Medium => 2100,
+            Self::Large | Self::LargeV1 => 3900,
+        }
+    }
+
+    /// whisper uses 4 threads per job, and every job beyond the first needs its own state, roughly half the model
+    pub fn default_concurrency(&self) -> usize {
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get()) / 4;
+        let memory = available_memory().map_or(1, |available| (available / (self.required_memory() / 2)) as usize);
+        cores.min(memory).max(1)
+    }
+
+    pub fn memory_warning(&self) -> Option<String> {
+        let available = available_memory()?;
+        (available < self.required_memory()).then(|| format!("可用内存 {} MB，{} 模型约需 {} MB，可能加载失败，建议选择更小的模型", available, self, self.required_memory()))
+    }
+
     pub fn get_path(&self) -> PathBuf {
-        let current = std::env::current_dir().unwrap();
-        current.join(format!("{}.bin", self))
+        self.path_in(&model_dir())
+    }
+
+    pub fn path_in(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{}.bin", self))
     }
 
-    pub async fn download(&self) -> std::io::Result<()> {
-        let path = self.get_path();
+    /// of ggml-{model}.bin, from the whisper.cpp models README
+    pub fn sha1(&self) -> &'static str {
+        match self {
+            Self::TinyEnglish => "c78c86eb1a8faa21b369bcd33207cc90d64ae9df",
+            Self::Tiny => "bd577a113a864445d4c299885e0cb97d4ba92b5f",
+            Self::BaseEnglish => "137c40403d78fd54d454da0f9bd998f78703390c",
+            Self::Base => "465707469ff3a37a2b9b8d8f89f2f99de7299dac",
+            Self::SmallEnglish => "db8a495a91d927739e50b3fc1cc4c6b8f6c2d022",
+            Self::Small => "55356645c2b361a969dfd0ef2c5a50d530afd8d5",
+            Self::MediumEnglish => "8c30f0e44ce9560643ebd10bbe50cd20eafd3723",
+            Self::Medium => "fd9727b6e1217c2f614f9b698455c4ffd82463b4",
+            Self::Large => "0f4c8e34f21cf1a914c59d8b3ce882345ad349d6",
+            Self::LargeV1 => "b1caaf735c4cc1429223d5a74f0f4d0b9b59a299",
+        }
+    }
+
+    /// whether the file at path hashes to sha1()
+    pub fn verify(&self, path: &Path) -> Result<bool, ConvError> {
+        let mut file = File::open(path)?;
+        let mut hasher = Sha1::new();
+        let mut buffer = vec![0; 1 << 20];
+        loop {
+            match file.read(&mut buffer)? {
+                0 => break,
+                n => hasher.update(&buffer[..n]),
+            }
+        }
+        let digest = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        Ok(digest == self.sha1())
+    }
+
+    /// fetches the model without loading it, for warming a cache ahead of time
+    pub async fn preload(&self, cancel: &CancelToken, events: &Events) -> Result<PathBuf, ConvError> {
+        self.download(cancel, events).await?;
+        Ok(self.get_path())
+    }
+
+    pub async fn download(&self, cancel: &CancelToken, events: &Events) -> Result<(), ConvError> {
+        self.download_to(&self.get_path(), cancel, events).await
+    }
+
+    /// Download events around the DownloadProgress ones, FILE_SIZE and DOWNLOADED follow along for polling
+    pub async fn download_to(&self, path: &Path, cancel: &CancelToken, events: &Events) -> Result<(), ConvError> {
+        self.download_from(&self.url(), path, cancel, events).await
+    }
+
+    fn url(&self) -> String {
+        format!("https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-{}.bin", self)
+    }
+
+    async fn download_from(&self, url: &str, path: &Path, cancel: &CancelToken, events: &Events) -> Result<(), ConvError> {
         if path.exists() {
             return Ok(());
         }
+        let result = self.fetch(url, path, cancel, events).await;
+        let stage = match result {
+            Ok(()) => DownloadStage::Finished { path: path.to_path_buf() },
+            Err(ref e) => DownloadStage::Failed { error: e.to_string() },
+        };
+        events.send(AppEvent::Download { model: *self, stage });
+        result
+    }
+
+    // written under <name>.part and renamed once complete, so a download that is killed, cancelled
+    // or cut short never leaves a file behind that would pass for the model later
+    async fn fetch(&self, url: &str, path: &Path, cancel: &CancelToken, events: &Events) -> Result<(), ConvError> {
         DOWNLOADING.store(true, Ordering::Relaxed);
-        let mut model = File::create(path)?;
-        let mut file = CLIENT.get(&format!("https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-{}.bin", self))
-            .send()
-            .await
-            .map_err(|_| std::io::Error::from(ErrorKind::NotConnected))?;
-        FILE_SIZE.store(file.content_length().unwrap(), Ordering::Relaxed);
+        info!("downloading {} to {}", url, path.display());
+        let mut part = path.as_os_str().to_owned();
+        part.push(".part");
+        let part = PathBuf::from(part);
+        let result = match self.fetch_to(url, &part, cancel, events).await {
+            Ok(()) => std::fs::rename(&part, path).map_err(ConvError::from),
+            Err(e) => Err(e),
+        };
+        DOWNLOADING.store(false, Ordering::Relaxed);
+        match result {
+            Ok(()) => info!("downloaded {} of {} bytes", DOWNLOADED.load(Ordering::Relaxed), FILE_SIZE.load(Ordering::Relaxed)),
+            Err(ref e) => {
+                let _ = std::fs::remove_file(&part);
+                match e {
+                    ConvError::Cancelled => info!("download of {} cancelled", self),
+                    e => error!("download of {} from {} failed: {}", self, url, e),
+                }
+            }
+        }
         DOWNLOADED.store(0, Ordering::Relaxed);
+        FILE_SIZE.store(!0, Ordering::Relaxed);
+        result
+    }
 
-        while let Some(item) = file.chunk().await.map_err(|_| std::io::Error::from(ErrorKind::InvalidData))? {
-            if !DOWNLOADING.load(Ordering::Relaxed) {
-                break;
+    async fn fetch_to(&self, url: &str, part: &Path, cancel: &CancelToken, events: &Events) -> Result<(), ConvError> {
+        let failed = |e: reqwest::Error| ConvError::ModelDownload { model: *self, source: e.into() };
+        let mut file = CLIENT.get(url).send().await.map_err(failed)?;
+        // an error page streamed into the file would pass for a downloaded model later
+        if !file.status().is_success() {
+            return Err(ConvError::ModelDownload { model: *self, source: DownloadError::Http(file.status().as_u16()).into() });
+        }
+        // only created once the server answers, and under .part until fetch renames it
+        let mut model = File::create(part)?;
+        let total = file.content_length();
+        FILE_SIZE.store(total.unwrap_or(!0), Ordering::Relaxed);
+        DOWNLOADED.store(0, Ordering::Relaxed);
+        events.send(AppEvent::Download { model: *self, stage: DownloadStage::Started { total } });
+
+        let ranges = file.headers().get(header::ACCEPT_RANGES).is_some_and(|v| v.as_bytes() == b"bytes");
+        let connections = match total {
+            Some(total) if ranges => CONNECTIONS.load(Ordering::Relaxed).min((total / MIN_RANGE) as usize),
+            _ => 1,
+        };
+        if let (Some(total), 2..) = (total, connections) {
+            // the ranges are requested afresh, this response only told us they can be
+            drop(file);
+            info!("downloading {} in {} ranges", self, connections);
+            model.set_len(total)?;
+            drop(model);
+            return self.fetch_ranges(url, part, total, connections, cancel, events).await;
+        }
+        let mut written = 0;
+        while let Some(item) = file.chunk().await.map_err(failed)? {
+            // DOWNLOADING is cleared by the gui to stop the download
+            if cancel.is_cancelled() || !DOWNLOADING.load(Ordering::Relaxed) {
+                return Err(ConvError::Cancelled);
             }
             model.write_all(&item)?;
-            let new = min(DOWNLOADED.load(Ordering::Relaxed) + (item.len() as u64), FILE_SIZE.load(Ordering::Relaxed));
-            DOWNLOADED.store(new, Ordering::Relaxed);
+            written += item.len() as u64;
+            self.advance(item.len() as u64, total, events);
         }
-        DOWNLOADING.store(false, Ordering::Relaxed);
+        match total.filter(|total| written < *total) {
+            Some(total) => Err(ConvError::ModelDownload { model: *self, source: DownloadError::Truncated(total - written).into() }),
+            None => Ok(()),
+        }
+    }
 
-        DOWNLOADED.store(0, Ordering::Relaxed);
-        FILE_SIZE.store(!0, Ordering::Relaxed);
+    // each range writes through its own handle, so the seeks don't race
+    async fn fetch_ranges(&self, url: &str, path: &Path, total: u64, connections: usize, cancel: &CancelToken, events: &Events) -> Result<(), ConvError> {
+        let size = total.div_ceil(connections as u64);
+        let mut tasks = tokio::task::JoinSet::new();
+        for start in (0..total).step_by(size as usize) {
+            let (model, url, path, cancel, events) = (*self, url.to_string(), path.to_path_buf(), cancel.clone(), events.clone());
+            let range = start..min(start + size, total);
+            tasks.spawn(async move { model.fetch_range(&url, &path, range, total, &cancel, &events).await });
+        }
+        // dropping the set on the first error aborts the other ranges
+        while let Some(result) = tasks.join_next().await {
+            result.map_err(|e| ConvError::ModelDownload { model: *self, source: e.into() })??;
+        }
+        Ok(())
+    }
+
+    async fn fetch_range(&self, url: &str, path: &Path, range: Range<u64>, total: u64, cancel: &CancelToken, events: &Events) -> Result<(), ConvError> {
+        let failed = |e: reqwest::Error| ConvError::ModelDownload { model: *self, source: e.into() };
+        let mut response = CLIENT
+            .get(url)
+            .header(header::RANGE, format!("bytes={}-{}", range.start, range.end - 1))
+            .send()
+            .await
+            .map_err(failed)?;
+        // a 200 would be the whole file, written at the wrong offset
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(ConvError::ModelDownload { model: *self, source: DownloadError::Http(response.status().as_u16()).into() });
+        }
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.seek(SeekFrom::Start(range.start))?;
+        let mut left = range.end - range.start;
+        while let Some(item) = response.chunk().await.map_err(failed)? {
+            if cancel.is_cancelled() || !DOWNLOADING.load(Ordering::Relaxed) {
+                return Err(ConvError::Cancelled);
+            }
+            let item = &item[..min(item.len() as u64, left) as usize];
+            file.write_all(item)?;
+            left -= item.len() as u64;
+            self.advance(item.len() as u64, Some(total), events);
+        }
+        if left > 0 {
+            return Err(ConvError::ModelDownload { model: *self, source: DownloadError::Truncated(left).into() });
+        }
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn advance(&self, bytes: u64, total: Option<u64>, events: &Events) {
+        let old = DOWNLOADED.fetch_add(bytes, Ordering::Relaxed);
+        let new = min(old + bytes, FILE_SIZE.load(Ordering::Relaxed));
+        // one event per percent, or per MB when the size is unknown
+        let step = total.map_or(1 << 20, |total| (total / 100).max(1));
+        if new / step != old / step {
+            events.send(AppEvent::DownloadProgress { model: *self, done: new, total });
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn languages_round_trip() {
+        for lang in Language::value_variants() {
+            let json = serde_json::to_string(lang).unwrap();
+            assert_eq!(json, format!("\"{}\"", <&str>::from(*lang)));
+            assert_eq!(serde_json::from_str::<Language>(&json).unwrap(), *lang);
+        }
+    }
+
+    #[test]
+    fn models_round_trip() {
+        for model in Model::value_variants() {
+            let json = serde_json::to_string(model).unwrap();
+            assert_eq!(json, format!("\"{}\"", model.to_possible_value().unwrap().get_name()));
+            assert_eq!(serde_json::from_str::<Model>(&json).unwrap(), *model);
+        }
+    }
+
+    #[test]
+    fn native_names() {
+        for lang in Language::value_variants().iter().filter(|lang| **lang != Language::Auto) {
+            let name = lang.native_name();
+            assert!(!name.is_empty() && name.trim() == name, "{:?}: {:?}", lang, name);
+        }
+    }
+
+    #[test]
+    fn language_table() {
+        assert_eq!(Language::all().len(), Language::value_variants().len());
+        let mut codes = Language::all().iter().map(|(_, code, _)| *code).collect::<Vec<_>>();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), Language::all().len());
+        for (lang, code, name) in Language::all() {
+            assert_eq!(Language::from_str(code, false), Ok(*lang));
+            assert!(!name.is_empty());
+        }
+    }
+
+    #[test]
+    fn unknown_code() {
+        let e = serde_json::from_str::<Language>("\"xx\"").unwrap_err();
+        assert!(e.to_string().starts_with("unknown language \"xx\""), "{}", e);
+        assert!(serde_json::from_str::<Model>("\"huge\"").is_err());
+    }
+
+    // answers a single request with the given bytes
+    fn serve(response: &'static [u8]) -> String {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.read(&mut [0; 4096]);
+            let _ = stream.write_all(response);
+        });
+        format!("http://{}/ggml-base.bin", addr)
+    }
+
+    // one test, DOWNLOADING is shared and a download running alongside would read its reset as a cancel
+    #[tokio::test]
+    async fn downloads_leave_no_partial_file() {
+        let path = std::env::temp_dir().join(format!("conv-{}.bin", uuid::Uuid::new_v4()));
+        let part = path.with_extension("bin.part");
+        let (events, mut receiver) = Events::channel();
+
+        let url = serve(b"HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\nConnection: close\r\n\r\nNot Found");
+        let e = Model::Base.download_from(&url, &path, &CancelToken::default(), &events).await.unwrap_err();
+        let ConvError::ModelDownload { source, .. } = e else { panic!("{:?}", e) };
+        assert!(matches!(source.downcast_ref::<DownloadError>(), Some(DownloadError::Http(404))), "{:?}", source);
+        assert!(!path.exists() && !part.exists());
+        assert!(matches!(receiver.try_recv(), Ok(AppEvent::Download { stage: DownloadStage::Failed { .. }, .. })));
+
+        let url = serve(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\nConnection: close\r\n\r\nggml");
+        let e = Model::Base.download_from(&url, &path, &CancelToken::default(), &events).await.unwrap_err();
+        let ConvError::ModelDownload { source, .. } = e else { panic!("{:?}", e) };
+        assert!(!path.exists() && !part.exists(), "{}", source);
+        assert!(!DOWNLOADING.load(Ordering::Relaxed));
+
+        let url = serve(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\nggml");
+        Model::Base.download_from(&url, &path, &CancelToken::default(), &events).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"ggml");
+        assert!(!part.exists());
+        assert!(!DOWNLOADING.load(Ordering::Relaxed));
+        std::fs::remove_file(path).unwrap();
+    }
+}
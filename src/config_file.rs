@@ -0,0 +1,340 @@
+use std::path::{Path, PathBuf};
+
+use clap_builder::ValueEnum;
+use once_cell::sync::OnceCell;
+use toml::{Table, Value};
+use toml_edit::{value, Array, DocumentMut};
+
+use crate::config::{self, Language, Model};
+use crate::naming::Naming;
+use crate::utils::{self, SubtitleStyle};
+use crate::webhook::Webhook;
+use crate::whisper::Format;
+use crate::ytdlp;
+
+const KEYS: [&str; 12] = ["model_dir", "ffmpeg", "yt_dlp", "proxy", "connections", "lang", "model", "formats", "naming", "style", "webhook", "webhook_secret"];
+const STYLE_KEYS: [&str; 2] = ["alignment", "margin_v"];
+const DEFAULT_FORMATS: [Format; 3] = [Format::Lrc, Format::Srt, Format::Vtt];
+
+pub const DEFAULT_TEMPLATE: &str = r#"# conv 配置文件
+# 默认位置为 ~/.config/conv/config.toml (Windows 为 %APPDATA%\conv\config.toml)，可用 --config 或 CONV_CONFIG 指定
+# 优先级: 命令行参数 > 环境变量 > 本文件 > 默认值，未写的项使用默认值
+
+# 模型所在的目录，默认为当前目录 (CONV_MODEL_DIR)
+# model_dir = "/path/to/models"
+
+# ffmpeg 的路径，ffprobe 和 ffplay 在同一目录下查找，默认从 PATH 中查找 (CONV_FFMPEG)
+# ffmpeg = "/usr/local/bin/ffmpeg"
+
+# yt-dlp 的路径，用于转换视频链接，默认从 PATH 中查找 (CONV_YT_DLP)
+# yt_dlp = "/usr/local/bin/yt-dlp"
+
+# 下载模型使用的代理 (CONV_PROXY)
+# proxy = "http://127.0.0.1:7890"
+
+# 服务器支持时把模型分成几段同时下载，1 到 16 (CONV_CONNECTIONS)
+# connections = 4
+
+# 识别的语言 (CONV_LANG)
+# lang = "auto"
+
+# 使用的模型 (CONV_MODEL)
+# model = "medium"
+
+# 输出格式 (CONV_FORMATS，用逗号分隔)
+# formats = ["lrc", "srt", "vtt"]
+
+# 字幕文件的命名模板，可用 {stem} {lang} {ext}，或预设 plain、media-server、subs-dir (CONV_NAMING)
+# naming = "{stem}.{lang}.{ext}"
+
+# 每个任务完成或失败时 POST 的地址 (CONV_WEBHOOK)
+# webhook = "http://127.0.0.1:9000/conv"
+
+# 设置后请求带上 X-Conv-Signature: sha256=<请求体的 HMAC-SHA256> (CONV_WEBHOOK_SECRET)
+# webhook_secret = "secret"
+
+# 烧录字幕的样式
+# [style]
+# 小键盘方位 (2 底部, 5 中间, 8 顶部)
+# alignment = 2
+# 与上下边缘的距离
+# margin_v = 10
+"#;
+
+static CONFIG_FILE: OnceCell<ConfigFile> = OnceCell::new();
+
+// the file merged with the CONV_* environment variables, command line arguments are applied by the callers
+#[derive(Debug, Clone, Default)]
+pub struct ConfigFile {
+    pub path: PathBuf,
+    pub model_dir: Option<PathBuf>,
+    pub ffmpeg: Option<PathBuf>,
+    pub yt_dlp: Option<PathBuf>,
+    pub proxy: Option<String>,
+    pub connections: Option<usize>,
+    pub lang: Option<Language>,
+    pub model: Option<Model>,
+    pub formats: Option<Vec<Format>>,
+    pub naming: Option<Naming>,
+    pub style: Option<SubtitleStyle>,
+    pub webhook: Option<String>,
+    pub webhook_secret: Option<String>,
+    pub warnings: Vec<String>,
+}
+
+// %APPDATA%\conv\config.toml, $XDG_CONFIG_HOME/conv/config.toml or ~/.config/conv/config.toml
+pub fn default_path() -> PathBuf {
+    let var = |key: &str| std::env::var_os(key).filter(|v| !v.is_empty()).map(PathBuf::from);
+    let base = if cfg!(windows) {
+        var("APPDATA")
+    } else {
+        var("XDG_CONFIG_HOME").or_else(|| var("HOME").map(|home| home.join(".config")))
+    };
+    base.unwrap_or_else(std::env::temp_dir).join("conv").join("config.toml")
+}
+
+// loads the file once at startup and points the library at the configured model directory, ffmpeg, yt-dlp and proxy
+pub fn init(path: Option<&Path>) -> &'static ConfigFile {
+    CONFIG_FILE.get_or_init(|| {
+        let mut file = ConfigFile::load(path);
+        if let Some(ref dir) = file.model_dir {
+            config::set_model_dir(dir.clone());
+        }
+        if let Some(ref ffmpeg) = file.ffmpeg {
+            utils::set_ffmpeg(ffmpeg.clone());
+        }
+        if let Some(ref yt_dlp) = file.yt_dlp {
+            ytdlp::set_yt_dlp(yt_dlp.clone());
+        }
+        if let Some(ref proxy) = file.proxy {
+            if let Err(e) = config::set_proxy(proxy) {
+                file.warnings.push(format!("无效的代理 \"{}\": {}", proxy, e));
+            }
+        }
+        if let Some(connections) = file.connections {
+            config::set_connections(connections);
+        }
+        file
+    })
+}
+
+pub fn get() -> &'static ConfigFile {
+    init(None)
+}
+
+impl ConfigFile {
+    pub fn load(path: Option<&Path>) -> Self {
+        let explicit = path.map(Path::to_path_buf).or_else(|| std::env::var_os("CONV_CONFIG").filter(|v| !v.is_empty()).map(PathBuf::from));
+        let mut file = Self { path: explicit.clone().unwrap_or_else(default_path), ..Default::default() };
+        match std::fs::read_to_string(&file.path) {
+            Ok(text) => match text.parse::<Table>() {
+                Ok(table) => file.read_table(&table),
+                Err(e) => file.warnings.push(format!("无法解析配置文件 {}: {}", file.path.display(), e.message())),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && explicit.is_none() => {}
+            Err(e) => file.warnings.push(format!("无法读取配置文件 {}: {}", file.path.display(), e)),
+        }
+        file.read_env();
+        file
+    }
+
+    fn read_table(&mut self, table: &Table) {
+        for key in table.keys().filter(|key| !KEYS.contains(&key.as_str())) {
+            self.warn::<()>(key, "未知的配置项，已忽略");
+        }
+        self.model_dir = self.string(table, "model_dir").map(PathBuf::from);
+        self.ffmpeg = self.string(table, "ffmpeg").map(PathBuf::from);
+        self.yt_dlp = self.string(table, "yt_dlp").map(PathBuf::from);
+        self.proxy = self.string(table, "proxy");
+        self.connections = match table.get("connections").map(Value::as_integer) {
+            None => None,
+            Some(Some(connections @ 1..=16)) => Some(connections as usize),
+            Some(_) => self.warn("connections", "应为 1 到 16 的整数"),
+        };
+        self.lang = self.string(table, "lang").and_then(|s| self.value_enum("lang", &s));
+        self.model = self.string(table, "model").and_then(|s| self.value_enum("model", &s));
+        self.formats = match table.get("formats") {
+            None => None,
+            Some(Value::Array(values)) => {
+                let formats = values.iter().filter_map(|value| match value.as_str() {
+                    Some(s) => self.value_enum("formats", s),
+                    None => self.warn("formats", "应为字符串数组"),
+                });
+                Some(formats.collect::<Vec<_>>()).filter(|formats| !formats.is_empty())
+            }
+            Some(_) => self.warn("formats", "应为字符串数组"),
+        };
+        self.naming = self.string(table, "naming").and_then(|s| self.parse_naming("naming", &s));
+        self.webhook = self.string(table, "webhook");
+        self.webhook_secret = self.string(table, "webhook_secret");
+        self.style = match table.get("style") {
+            None => None,
+            Some(Value::Table(style)) => Some(self.read_style(style)),
+            Some(_) => self.warn("style", "应为表"),
+        };
+    }
+
+    fn read_style(&mut self, table: &Table) -> SubtitleStyle {
+        for key in table.keys().filter(|key| !STYLE_KEYS.contains(&key.as_str())) {
+            self.warn::<()>(&format!("style.{}", key), "未知的配置项，已忽略");
+        }
+        let mut style = SubtitleStyle::default();
+        match table.get("alignment").map(Value::as_integer) {
+            None => {}
+            Some(Some(alignment @ 1..=9)) => style.alignment = alignment as u8,
+            Some(_) => {
+                self.warn::<()>("style.alignment", "应为 1 到 9 的整数");
+            }
+        }
+        match table.get("margin_v").map(Value::as_integer) {
+            None => {}
+            Some(Some(margin_v @ 0..)) => style.margin_v = margin_v.min(u32::MAX as i64) as u32,
+            Some(_) => {
+                self.warn::<()>("style.margin_v", "应为非负整数");
+            }
+        }
+        style
+    }
+
+    fn read_env(&mut self) {
+        let var = |key: &str| std::env::var(key).ok().filter(|v| !v.is_empty());
+        if let Some(dir) = var("CONV_MODEL_DIR") {
+            self.model_dir = Some(PathBuf::from(dir));
+        }
+        if let Some(ffmpeg) = var("CONV_FFMPEG") {
+            self.ffmpeg = Some(PathBuf::from(ffmpeg));
+        }
+        if let Some(yt_dlp) = var("CONV_YT_DLP") {
+            self.yt_dlp = Some(PathBuf::from(yt_dlp));
+        }
+        if let Some(proxy) = var("CONV_PROXY") {
+            self.proxy = Some(proxy);
+        }
+        if let Some(connections) = var("CONV_CONNECTIONS") {
+            self.connections = match connections.parse() {
+                Ok(connections @ 1..=16) => Some(connections),
+                _ => self.warn("CONV_CONNECTIONS", "应为 1 到 16 的整数").or(self.connections),
+            };
+        }
+        if let Some(lang) = var("CONV_LANG") {
+            self.lang = self.value_enum("CONV_LANG", &lang).or(self.lang);
+        }
+        if let Some(model) = var("CONV_MODEL") {
+            self.model = self.value_enum("CONV_MODEL", &model).or(self.model);
+        }
+        if let Some(naming) = var("CONV_NAMING") {
+            if let Some(naming) = self.parse_naming("CONV_NAMING", &naming) {
+                self.naming = Some(naming);
+            }
+        }
+        if let Some(webhook) = var("CONV_WEBHOOK") {
+            self.webhook = Some(webhook);
+        }
+        if let Some(secret) = var("CONV_WEBHOOK_SECRET") {
+            self.webhook_secret = Some(secret);
+        }
+        if let Some(formats) = var("CONV_FORMATS") {
+            let formats = formats.split(',').filter_map(|s| self.value_enum("CONV_FORMATS", s.trim())).collect::<Vec<_>>();
+            if !formats.is_empty() {
+                self.formats = Some(formats);
+            }
+        }
+    }
+
+    fn string(&mut self, table: &Table, key: &str) -> Option<String> {
+        match table.get(key)? {
+            Value::String(s) => Some(s.clone()),
+            _ => self.warn(key, "应为字符串"),
+        }
+    }
+
+    fn value_enum<T: ValueEnum>(&mut self, key: &str, s: &str) -> Option<T> {
+        match T::from_str(s, true) {
+            Ok(value) => Some(value),
+            Err(_) => self.warn(key, &format!("无效的值 \"{}\"", s)),
+        }
+    }
+
+    fn parse_naming(&mut self, key: &str, s: &str) -> Option<Naming> {
+        match s.parse() {
+            Ok(naming) => Some(naming),
+            Err(e) => self.warn(key, &e),
+        }
+    }
+
+    fn warn<T>(&mut self, key: &str, message: &str) -> Option<T> {
+        let warning = match key.starts_with("CONV_") {
+            true => format!("环境变量 {}: {}", key, message),
+            false => format!("配置文件 {} 中的 {}: {}", self.path.display(), key, message),
+        };
+        self.warnings.push(warning);
+        None
+    }
+
+    pub fn lang(&self, arg: Option<Language>) -> Language {
+        arg.or(self.lang).unwrap_or(Language::Auto)
+    }
+
+    pub fn model(&self, arg: Option<Model>) -> Model {
+        arg.or(self.model).unwrap_or(Model::Medium)
+    }
+
+    pub fn formats(&self, arg: Vec<Format>) -> Vec<Format> {
+        match arg.is_empty() {
+            true => self.formats.clone().unwrap_or_else(|| DEFAULT_FORMATS.to_vec()),
+            false => arg,
+        }
+    }
+
+    pub fn naming(&self, arg: Option<Naming>) -> Naming {
+        arg.or_else(|| self.naming.clone()).unwrap_or_default()
+    }
+
+    pub fn style(&self) -> SubtitleStyle {
+        self.style.unwrap_or_default()
+    }
+
+    // None when no url is set anywhere
+    pub fn webhook(&self, url: Option<String>, secret: Option<String>) -> Result<Option<Webhook>, String> {
+        let Some(url) = url.or_else(|| self.webhook.clone()) else {
+            return Ok(None);
+        };
+        let secret = secret.or_else(|| self.webhook_secret.clone());
+        Webhook::new(&url, secret).map(Some).map_err(|e| format!("无效的 webhook 地址 \"{}\": {}", url, e))
+    }
+
+    // writes the given values back, keeping the comments and the other keys of the file
+    pub fn save(&self, lang: Option<Language>, model: Option<Model>, formats: Option<&[Format]>, style: Option<SubtitleStyle>) -> std::io::Result<()> {
+        if lang.is_none() && model.is_none() && formats.is_none() && style.is_none() {
+            return Ok(());
+        }
+        let text = match std::fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut doc = text.parse::<DocumentMut>().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        if let Some(lang) = lang {
+            doc["lang"] = value(name(lang));
+        }
+        if let Some(model) = model {
+            doc["model"] = value(name(model));
+        }
+        if let Some(formats) = formats {
+            doc["formats"] = value(formats.iter().copied().map(name).collect::<Array>());
+        }
+        if let Some(style) = style {
+            doc["style"]["alignment"] = value(style.alignment as i64);
+            doc["style"]["margin_v"] = value(style.margin_v as i64);
+        }
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.path, doc.to_string())
+    }
+}
+
+// lrc-millis as it is written on the command line
+fn name<T: ValueEnum>(value: T) -> String {
+    value.to_possible_value().map(|v| v.get_name().to_string()).unwrap_or_default()
+}
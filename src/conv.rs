@@ -1,27 +1,218 @@
+use std::collections::HashMap;
+use std::net::TcpListener;
 use std::path::{Path, PathBuf};
+use std::future::Future;
 use std::sync::{Arc, Mutex};
-use std::sync::atomic::Ordering;
 
+use anyhow::bail;
 use eframe::CreationContext;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tracing::{error, info, warn};
 use egui::FontFamily::Proportional;
 use egui::FontId;
 use egui::TextStyle::{Body, Button, Heading, Monospace, Name, Small};
 
+use crate::align::load_lines;
+use crate::cli::Args;
+use crate::batch::{BatchConfig, BatchEvent, BatchPolicy, BatchReport, load_playlist, run_batch, state_path};
 use crate::config::{Language, Model};
+use crate::event::{AppEvent, DownloadStage, Events};
 use crate::font::load_fonts;
-use crate::utils::{MERGE, merge, WHISPER};
-use crate::whisper::{Format, Whisper};
+use crate::{config_file, inhibit, instance, update};
+use crate::config_file::ConfigFile;
+use crate::player::Player;
+use crate::preview::Preview;
+use crate::settings::Settings;
+use crate::update::Release;
+use crate::subtitle::{Numbering, parse_timestamp, reflow_file, shift_file};
+use crate::job::{CancelToken, Job, JobHandle, JobKind};
+use crate::translate::BilingualOrder;
+use crate::utils::{audio_info, AudioInfo, Background, ChannelSelect, is_media, merge_mkv, merge_video, read_file, Resample, SubtitleStyle, VideoOptions};
+use crate::waveform::Envelope;
+use crate::whisper::{Format, load_glossary, Sampling, SYSTEM_INFO, TranscribeOptions, Transcript, VttCue};
+use crate::worker::WhisperWorker;
+use crate::ytdlp;
 
 #[derive(Clone)]
 pub struct Conv {
     pub files: Arc<Mutex<Files>>,
     pub config: Config,
+    pub settings: Arc<Mutex<Settings>>,
+    pub player: Arc<Mutex<Player>>,
+    pub preview: Arc<Mutex<Option<Preview>>>,
+    // plain text, srt and youtube chapters of the last transcript, built with it so copying a long one doesn't stall a frame
+    pub copies: Arc<Mutex<Option<(String, String, String)>>>,
+    pub waveform: Arc<Mutex<Option<(PathBuf, Envelope)>>>,
+    pub audio_info: Arc<Mutex<HashMap<PathBuf, Option<AudioInfo>>>>,
+    pub batch: Arc<Mutex<Option<anyhow::Result<BatchReport>>>>,
+    pub update: Arc<Mutex<Option<Release>>>,
+    pub worker: Arc<WhisperWorker>,
+    pub jobs: Vec<JobHandle>,
+    pub cancel: CancelToken,
+    pub events: Events,
+    pub event_receiver: Arc<Mutex<UnboundedReceiver<AppEvent>>>,
+    pub progress: Progress,
+    pub show_preview: bool,
+    pub show_log: bool,
+    pub show_about: bool,
+    pub preview_error: Option<String>,
+    pub lang_picker: LanguagePicker,
+    pub warnings: Vec<String>,
+    // the config as it was at startup, to tell what to write back to the config file
+    pub initial_config: Config,
 }
 
-#[derive(Clone)]
+// what the pipeline last reported, rebuilt from AppEvents every frame
+#[derive(Clone, Default)]
+pub struct Progress {
+    pub download: Option<(Model, u64, Option<u64>)>,
+    pub transcribe: Option<u8>,
+    pub segment: Option<String>,
+    pub merge: Option<f32>,
+}
+
+#[derive(Clone, Default)]
+pub struct LanguagePicker {
+    pub open: bool,
+    pub filter: String,
+    pub cursor: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Config {
     pub lang: Language,
     pub model: Model,
+    pub verbose: bool,
+    pub policy: BatchPolicy,
+    // 0 picks one from the model size and the machine
+    pub concurrency: usize,
+    pub json: bool,
+    // words in the json, one cue per segment in the others
+    pub word_json: bool,
+    pub txt: bool,
+    pub ass_karaoke: bool,
+    pub crlf: bool,
+    pub lrc_millis: bool,
+    pub vtt_cue: VttCue,
+    pub subtitle_offset: i64,
+    pub sampling: Sampling,
+    pub reflow_width: usize,
+    pub reflow_gap: i64,
+    pub reflow_max_duration: i64,
+    pub trim_start: String,
+    pub trim_end: String,
+    pub trim_relative: bool,
+    pub split: bool,
+    pub split_gap: i64,
+    pub numbering: Numbering,
+    pub resample: Resample,
+    pub channel: ChannelSelect,
+    pub background_color: [u8; 3],
+    pub waveform_background: bool,
+    pub subtitle_style: SubtitleStyle,
+    pub detect_languages: bool,
+    pub clean_text: bool,
+    pub preview_seconds: u32,
+    pub bilingual: bool,
+    pub bilingual_order: BilingualOrder,
+}
+
+impl Config {
+    // the startup config: the config file with the command line on top
+    pub fn new(file: &ConfigFile, args: &Args) -> Self {
+        let formats = file.formats(vec![]);
+        Self {
+            lang: file.lang(args.lang),
+            model: file.model(args.model),
+            verbose: false,
+            policy: BatchPolicy::SkipAndContinue,
+            concurrency: 1,
+            json: formats.contains(&Format::Json),
+            word_json: false,
+            txt: formats.contains(&Format::Txt),
+            ass_karaoke: formats.contains(&Format::AssKaraoke),
+            crlf: formats.contains(&Format::SrtCrlf),
+            lrc_millis: formats.contains(&Format::LrcMillis),
+            vtt_cue: match formats.iter().find(|format| format.extension() == "vtt") {
+                Some(Format::VttPlain) => VttCue::Plain,
+                Some(Format::VttVoice) => VttCue::Voice,
+                _ => VttCue::Dash,
+            },
+            subtitle_offset: 0,
+            sampling: Sampling::default(),
+            reflow_width: 42,
+            reflow_gap: 500,
+            reflow_max_duration: 7000,
+            trim_start: String::new(),
+            trim_end: String::new(),
+            trim_relative: false,
+            split: false,
+            split_gap: 5000,
+            numbering: Numbering::default(),
+            resample: args.resample,
+            channel: args.channel,
+            background_color: [0, 0, 0],
+            waveform_background: false,
+            subtitle_style: file.style(),
+            detect_languages: args.language_window.is_some(),
+            clean_text: true,
+            preview_seconds: 30,
+            bilingual: false,
+            bilingual_order: BilingualOrder::default(),
+        }
+    }
+
+    pub fn options(&self) -> TranscribeOptions {
+        TranscribeOptions {
+            verbose: self.verbose,
+            sampling: self.sampling,
+            resample: self.resample,
+            channel: self.channel,
+            language_window_ms: if self.detect_languages { 30_000 } else { 0 },
+            clean_text: self.clean_text,
+            ..Default::default()
+        }
+    }
+
+    pub fn formats(&self) -> Vec<Format> {
+        let srt = if self.crlf { Format::SrtCrlf } else { Format::Srt };
+        let lrc = if self.lrc_millis { Format::LrcMillis } else { Format::Lrc };
+        let mut formats = vec![lrc, srt, self.vtt_cue.format()];
+        if self.json {
+            formats.push(Format::Json);
+        }
+        if self.txt {
+            formats.push(Format::Txt);
+        }
+        if self.ass_karaoke {
+            formats.push(Format::AssKaraoke);
+        }
+        Format::dedup(formats)
+    }
+
+    // (start, end) in centiseconds, end is None to transcribe until the end of the file
+    pub fn trim(&self, duration: Option<f64>) -> Result<(i64, Option<i64>), String> {
+        let parse = |s: &str| match s.trim() {
+            "" => Ok(None),
+            s => parse_timestamp(s).map(Some).ok_or_else(|| format!("无效的时间: {}", s)),
+        };
+        let start = parse(&self.trim_start)?.unwrap_or(0);
+        let end = parse(&self.trim_end)?;
+        if end.is_some_and(|end| end <= start) {
+            return Err("结束时间必须晚于开始时间".to_string());
+        }
+        if let Some(length) = duration.map(|d| (d * 100.0) as i64) {
+            if start >= length {
+                return Err("开始时间超出音频长度".to_string());
+            }
+            if end.is_some_and(|end| end > length) {
+                return Err("结束时间超出音频长度".to_string());
+            }
+        }
+        Ok((start, end))
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -29,10 +220,43 @@ pub struct Files {
     pub audio: Option<PathBuf>,
     pub image: Option<PathBuf>,
     pub subtitle: Option<PathBuf>,
+    pub glossary: Option<PathBuf>,
+    // lines to time instead of the recognized text
+    pub lyrics: Option<PathBuf>,
+    // a video link to fetch the audio of
+    pub url: String,
+}
+
+pub use crate::utils::{AUDIO_EXTENSIONS, VIDEO_EXTENSIONS};
+pub const IMAGE_EXTENSIONS: [&str; 2] = ["jpg", "png"];
+// ass only to burn in, e.g. a karaoke file, it isn't read back
+pub const SUBTITLE_EXTENSIONS: [&str; 4] = ["srt", "lrc", "vtt", "ass"];
+
+impl Files {
+    pub fn insert(&mut self, path: PathBuf) -> Result<(), String> {
+        if let Some(url) = path.to_str().and_then(ytdlp::parse_url).filter(|_| !path.exists()) {
+            self.url = url;
+            return Ok(());
+        }
+        if !path.exists() {
+            return Err(format!("文件不存在: {}", path.display()));
+        }
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+        if is_media(&path) {
+            self.audio = Some(path);
+        } else if IMAGE_EXTENSIONS.contains(&ext.as_str()) {
+            self.image = Some(path);
+        } else if SUBTITLE_EXTENSIONS.contains(&ext.as_str()) {
+            self.subtitle = Some(path);
+        } else {
+            return Err(format!("无法识别的文件: {}", path.display()));
+        }
+        Ok(())
+    }
 }
 
 impl Conv {
-    pub fn new(cc: &CreationContext) -> Box<Self> {
+    pub fn new(cc: &CreationContext, args: Args, listener: Option<TcpListener>) -> Box<Self> {
         load_fonts(&cc.egui_ctx);
         let mut style = (*cc.egui_ctx.style()).clone();
         style.text_styles = [
@@ -47,16 +271,64 @@ impl Conv {
             .into();
         cc.egui_ctx.set_style(style);
 
-        Box::new(Self {
-            files: Default::default(),
-            config: Config { lang: Language::Auto, model: Model::Medium },
-        })
+        let file = config_file::get();
+        let config = Config::new(file, &args);
+        let mut warnings = file.warnings.clone();
+        let mut files = Files { glossary: args.glossary.clone(), lyrics: args.align.clone(), ..Default::default() };
+        for path in args.paths {
+            if let Err(e) = files.insert(path) {
+                warnings.push(e);
+            }
+        }
+        if args.auto_start && files.audio.is_none() {
+            warnings.push("未指定音频，无法自动开始".to_string());
+        }
+
+
+        let (events, event_receiver) = Events::channel();
+        let mut conv = Self {
+            files: Arc::new(Mutex::new(files)),
+            config: config.clone(),
+            settings: Arc::new(Mutex::new(Settings::load())),
+            player: Default::default(),
+            preview: Default::default(),
+            copies: Default::default(),
+            waveform: Default::default(),
+            audio_info: Default::default(),
+            batch: Default::default(),
+            update: Default::default(),
+            worker: Arc::new(WhisperWorker::spawn()),
+            jobs: vec![],
+            cancel: Default::default(),
+            events,
+            event_receiver: Arc::new(Mutex::new(event_receiver)),
+            progress: Default::default(),
+            show_preview: false,
+            show_log: false,
+            show_about: false,
+            preview_error: None,
+            lang_picker: Default::default(),
+            warnings,
+            initial_config: config,
+        };
+        Lazy::force(&SYSTEM_INFO);
+        inhibit::spawn(conv.settings.clone());
+        tokio::spawn(update::check(conv.settings.clone(), conv.update.clone()));
+        if let Some(listener) = listener {
+            instance::serve(listener, conv.files.clone());
+        }
+        if args.auto_start {
+            let job = conv.whisper();
+            conv.push_job(job);
+        }
+        Box::new(conv)
     }
 
     pub fn open_audio(&self, files: Arc<Mutex<Files>>) {
         tokio::spawn(async move {
             if let Some(path) = rfd::FileDialog::new()
-                .add_filter("Audio File", &["mp3", "wav"])
+                .add_filter("Audio File", &AUDIO_EXTENSIONS)
+                .add_filter("Video File", &VIDEO_EXTENSIONS)
                 .pick_file() {
                 files.lock().unwrap().audio = Some(path);
             }
@@ -66,7 +338,7 @@ impl Conv {
     pub fn open_image(&self, files: Arc<Mutex<Files>>) {
         tokio::spawn(async move {
             if let Some(path) = rfd::FileDialog::new()
-                .add_filter("Image File", &["jpg", "png"])
+                .add_filter("Image File", &IMAGE_EXTENSIONS)
                 .pick_file() {
                 files.lock().unwrap().image = Some(path);
             }
@@ -76,73 +348,495 @@ impl Conv {
     pub fn open_subtitle(&self, files: Arc<Mutex<Files>>) {
         tokio::spawn(async move {
             if let Some(path) = rfd::FileDialog::new()
-                .add_filter("Subtitle File", &["srt", "lrc", "vtt"])
+                .add_filter("Subtitle File", &SUBTITLE_EXTENSIONS)
                 .pick_file() {
                 files.lock().unwrap().subtitle = Some(path);
             }
         });
     }
 
-    pub fn whisper(&self) {
+    pub fn open_glossary(&self, files: Arc<Mutex<Files>>) {
+        tokio::spawn(async move {
+            if let Some(path) = rfd::FileDialog::new()
+                .add_filter("Glossary", &["txt"])
+                .pick_file() {
+                files.lock().unwrap().glossary = Some(path);
+            }
+        });
+    }
+
+    pub fn open_lyrics(&self, files: Arc<Mutex<Files>>) {
+        tokio::spawn(async move {
+            if let Some(path) = rfd::FileDialog::new()
+                .add_filter("Lyrics", &["txt"])
+                .pick_file() {
+                files.lock().unwrap().lyrics = Some(path);
+            }
+        });
+    }
+
+    pub fn load_preview(&mut self) {
+        let subtitle = self.files.lock().unwrap().subtitle.clone();
+        if let Some(ref subtitle) = subtitle {
+            match Transcript::from_file(subtitle) {
+                Ok(t) if t.is_empty() => self.preview_error = Some("字幕为空".to_string()),
+                Ok(t) => {
+                    *self.preview.lock().unwrap() = Some(Preview::new(t.utterances));
+                    self.preview_error = None;
+                }
+                Err(e) => self.preview_error = Some(e.to_string()),
+            }
+        }
+        self.show_preview = true;
+    }
+
+    pub fn reflow_subtitle(&mut self) {
+        let subtitle = self.files.lock().unwrap().subtitle.clone();
+        if let Some(ref subtitle) = subtitle {
+            match reflow_file(subtitle, self.config.reflow_gap / 10, self.config.reflow_width, self.config.reflow_max_duration / 10) {
+                Ok(output) => {
+                    self.files.lock().unwrap().subtitle = Some(output);
+                    self.load_preview();
+                }
+                Err(e) => self.warnings.push(format!("重排字幕失败: {}", e)),
+            }
+        }
+    }
+
+    pub fn load_waveform(&self) {
+        let audio = self.files.lock().unwrap().audio.clone();
+        let waveform = self.waveform.clone();
+        if let Some(audio) = audio {
+            std::thread::spawn(move || {
+                if let Ok(samples) = read_file(&audio, Resample::Auto, ChannelSelect::Mix) {
+                    *waveform.lock().unwrap() = Some((audio, Envelope::new(&samples)));
+                }
+            });
+        }
+    }
+
+    pub fn audio_info(&self) -> Option<AudioInfo> {
+        let audio = self.files.lock().unwrap().audio.clone()?;
+        let mut cache = self.audio_info.lock().unwrap();
+        if let Some(info) = cache.get(&audio) {
+            return *info;
+        }
+        cache.insert(audio.clone(), None);
+        let cache = self.audio_info.clone();
+        std::thread::spawn(move || {
+            let info = audio_info(&audio).ok();
+            cache.lock().unwrap().insert(audio, info);
+        });
+        None
+    }
+
+    fn glossary(&self) -> Result<Vec<String>, String> {
+        let path = self.files.lock().unwrap().glossary.clone();
+        match path.map(load_glossary).transpose() {
+            Ok(glossary) => Ok(glossary.unwrap_or_default()),
+            Err(e) => Err(format!("无法读取术语表: {}", e)),
+        }
+    }
+
+    fn lyrics(&self) -> Result<Option<Vec<String>>, String> {
+        let path = self.files.lock().unwrap().lyrics.clone();
+        match path.map(load_lines).transpose() {
+            Ok(Some(lines)) if lines.is_empty() => Err("歌词为空".to_string()),
+            Ok(lines) => Ok(lines),
+            Err(e) => Err(format!("无法读取歌词: {}", e)),
+        }
+    }
+
+    // finished handles of the same kind are replaced, running ones are kept until they finish
+    pub fn push_job(&mut self, handle: JobHandle) {
+        self.jobs.retain(|job| job.id != handle.id && (job.kind != handle.kind || !job.is_finished()));
+        self.jobs.push(handle);
+    }
+
+    // only what was changed in the window, so values from the environment or the command line are not persisted
+    pub fn save_config_file(&self) {
+        let (config, initial) = (&self.config, &self.initial_config);
+        let formats = config.formats();
+        let result = config_file::get().save(
+            (config.lang != initial.lang).then_some(config.lang),
+            (config.model != initial.model).then_some(config.model),
+            (formats != initial.formats()).then_some(formats.as_slice()),
+            (config.subtitle_style != initial.subtitle_style).then_some(config.subtitle_style),
+        );
+        if let Err(e) = result {
+            error!("failed to save the config file: {}", e);
+        }
+    }
+
+    // running jobs see the old token, new ones get a fresh one
+    pub fn cancel_all(&mut self) {
+        std::mem::take(&mut self.cancel).cancel();
+    }
+
+    pub fn handle_events(&mut self) {
+        let mut receiver = self.event_receiver.lock().unwrap();
+        while let Ok(event) = receiver.try_recv() {
+            let progress = &mut self.progress;
+            match event {
+                AppEvent::Download { model, stage: DownloadStage::Started { total } } => progress.download = Some((model, 0, total)),
+                AppEvent::Download { stage: DownloadStage::Finished { .. } | DownloadStage::Failed { .. }, .. } => progress.download = None,
+                AppEvent::DownloadProgress { model, done, total } => progress.download = Some((model, done, total)),
+                AppEvent::TranscribeStarted { .. } => {
+                    progress.transcribe = Some(0);
+                    progress.segment = None;
+                }
+                AppEvent::Segment(u) => progress.segment = Some(u.text.trim().to_string()),
+                AppEvent::TranscribeProgress { percent } => progress.transcribe = Some(percent),
+                AppEvent::MergeProgress { fraction } => progress.merge = Some(fraction),
+                AppEvent::Completed { .. } | AppEvent::Failed { .. } => {
+                    progress.download = None;
+                    progress.transcribe = None;
+                    progress.merge = None;
+                }
+            }
+        }
+    }
+
+    pub fn latest_job(&self, kinds: &[JobKind]) -> Option<&JobHandle> {
+        self.jobs.iter().rev().find(|job| kinds.contains(&job.kind))
+    }
+
+    pub fn whisper(&self) -> JobHandle {
+        let (job, handle) = Job::new(JobKind::Whisper);
+        let (start, end) = match self.config.trim(self.audio_info().map(|info| info.duration.as_secs_f64())) {
+            Ok(trim) => trim,
+            Err(e) => {
+                job.fail(e);
+                return handle;
+            }
+        };
+        let glossary = match self.glossary() {
+            Ok(glossary) => glossary,
+            Err(e) => {
+                job.fail(e);
+                return handle;
+            }
+        };
+        let lyrics = match self.lyrics() {
+            Ok(lyrics) => lyrics,
+            Err(e) => {
+                job.fail(e);
+                return handle;
+            }
+        };
         let file = self.files.lock().unwrap();
-        let audio = file.audio.clone();
+        let Some(audio) = file.audio.clone() else {
+            job.fail("未选择音频");
+            return handle;
+        };
         let model = self.config.model;
         let lang = self.config.lang;
+        let word_json = self.config.json && self.config.word_json;
+        let options = TranscribeOptions {
+            offset_ms: start as i32 * 10,
+            duration_ms: end.map(|end| (end - start) as i32 * 10).unwrap_or(0),
+            glossary,
+            cancel: self.cancel.clone(),
+            // aligned lyrics replace the recognized text, which isn't worth writing in the meantime
+            stream_to: lyrics.is_none().then(|| (audio.clone(), self.config.formats())),
+            events: self.events.clone(),
+            word_timestamps: lyrics.is_some() || self.config.ass_karaoke || word_json,
+            ..self.config.options()
+        };
+        drop(file);
+        self.load_waveform();
+        let seconds = match options.duration_ms {
+            0 => self.audio_info().map(|info| info.duration.as_secs_f64() - options.offset_ms as f64 / 1000.0),
+            ms => Some(ms as f64 / 1000.0),
+        };
+        let relative = self.config.trim_relative;
+        let settings = self.settings.clone();
+        let split = self.config.split.then_some((self.config.split_gap / 10, self.config.numbering.clone()));
+        let formats = self.config.formats();
+        let bilingual = self.config.bilingual.then_some(self.config.bilingual_order);
+        let preview = self.preview.clone();
+        let copies = self.copies.clone();
+        let worker = self.worker.clone();
+        job.running(Some(audio.clone()));
         tokio::spawn(async move {
-            if let Some(ref audio) = audio {
-                if let Ok(ref mut w) = Whisper::new(lang, model).await {
-                    WHISPER.store(true, Ordering::Relaxed);
-                    if let Ok(ref t) = w.transcribe(audio, false, false) {
-                        t.write_file(audio, Format::Lrc);
-                        t.write_file(audio, Format::Srt);
-                        t.write_file(audio, Format::Vtt);
+            let events = options.events.clone();
+            let result = async {
+                events.send(AppEvent::TranscribeStarted { path: audio.clone() });
+                let mut t = match (lyrics, bilingual) {
+                    (Some(lines), _) => {
+                        let (aligned, unmatched) = worker.transcribe(audio.clone(), lang, model, options).await?.align_lines(&lines);
+                        for n in unmatched {
+                            warn!("line {} of the lyrics wasn't heard, timed between its neighbours: {}", n + 1, lines[n]);
+                        }
+                        aligned
                     }
+                    (None, Some(order)) => worker.transcribe_bilingual(audio.clone(), lang, model, options).await?.bilingual(order),
+                    (None, None) => worker.transcribe(audio.clone(), lang, model, options).await?,
+                };
+                if let Some(speed) = t.throughput() {
+                    info!("{} transcribed at {:.2}x with {}", audio.display(), speed, model);
+                }
+                if let Some(seconds) = seconds {
+                    let mut settings = settings.lock().unwrap();
+                    settings.push_realtime_factor(model, t.processing_time.as_secs_f64(), seconds);
+                    settings.save();
+                }
+                if relative {
+                    t.shift(-start);
                 }
+                // the word cues are only there for the karaoke file and the word level json, the others stay one cue per segment
+                let with_words = |format: &&Format| **format == Format::AssKaraoke || (word_json && **format == Format::Json);
+                for format in formats.iter().filter(with_words) {
+                    t.write_file(&audio, *format)?;
+                }
+                t.word_utterances = None;
+                for format in formats.iter().filter(|format| !with_words(format)) {
+                    t.write_file(&audio, *format)?;
+                }
+                if let Some((gap, ref numbering)) = split {
+                    t.write_parts(&audio, &formats, gap, numbering)?;
+                }
+                let cues = t.word_utterances.as_ref().unwrap_or(&t.utterances).clone();
+                *preview.lock().unwrap() = Some(Preview::new(cues));
+                *copies.lock().unwrap() = Some((t.to_txt(), t.to_srt(), t.to_chapters()));
+                anyhow::Ok(audio.with_extension("srt"))
             }
+            .await;
 
-            WHISPER.store(false, Ordering::Relaxed);
+            match events.finish(result, |output| vec![output.clone()]) {
+                Ok(output) => job.succeed(output),
+                Err(e) => {
+                    error!("failed to transcribe {}: {:?}", audio.display(), e);
+                    job.fail(e);
+                }
+            }
         });
+        handle
     }
 
-    pub fn ffmpeg_merge(&self) {
-        let file = self.files.lock().unwrap();
-        let image = file.image.clone();
-        let audio = file.audio.clone();
-        let subtitle = file.subtitle.clone();
+    // the opening seconds from the trim start, shown in the preview without writing subtitles
+    pub fn whisper_preview(&self) -> JobHandle {
+        let (job, handle) = Job::new(JobKind::Whisper);
+        let start = match self.config.trim(self.audio_info().map(|info| info.duration.as_secs_f64())) {
+            Ok((start, _)) => start,
+            Err(e) => {
+                job.fail(e);
+                return handle;
+            }
+        };
+        let glossary = match self.glossary() {
+            Ok(glossary) => glossary,
+            Err(e) => {
+                job.fail(e);
+                return handle;
+            }
+        };
+        let Some(audio) = self.files.lock().unwrap().audio.clone() else {
+            job.fail("未选择音频");
+            return handle;
+        };
+        let (lang, model, seconds) = (self.config.lang, self.config.model, self.config.preview_seconds);
+        let options = TranscribeOptions { offset_ms: start as i32 * 10, glossary, cancel: self.cancel.clone(), events: self.events.clone(), ..self.config.options() };
+        self.load_waveform();
+        let preview = self.preview.clone();
+        let worker = self.worker.clone();
+        job.running(Some(audio.clone()));
         tokio::spawn(async move {
-            MERGE.store(true, Ordering::Relaxed);
-            if let (Some(ref image), Some(ref audio), Some(ref subtitle)) = (image, audio, subtitle) {
-                let current = std::env::current_dir().unwrap();
-                let subtitle_cache = Path::new(&uuid::Uuid::new_v4().to_string()).with_extension(subtitle.extension().unwrap());
-                if !current.join(&subtitle_cache).exists() {
-                    std::fs::copy(subtitle, current.join(&subtitle_cache)).unwrap();
-                }
-                let output = audio.with_extension("mp4");
-
-                if let Ok(child) = merge(
-                    audio.to_str().unwrap(),
-                    image.to_str().unwrap(),
-                    subtitle_cache.to_str().unwrap(),
-                    output.to_str().unwrap(),
-                ).as_mut() {
-                    if child.wait().is_err() {
-                        MERGE.store(false, Ordering::Relaxed);
-                        return;
-                    }
-                } else {
-                    MERGE.store(false, Ordering::Relaxed);
-                    return;
+            let events = options.events.clone();
+            events.send(AppEvent::TranscribeStarted { path: audio.clone() });
+            let result = worker.preview(audio.clone(), lang, model, seconds, options).await;
+            match events.finish(result, |_| vec![]) {
+                Ok(t) => {
+                    info!("previewed {}s of {} with {}: {} segments", seconds, audio.display(), model, t.utterances.len());
+                    let cues = t.word_utterances.unwrap_or(t.utterances);
+                    *preview.lock().unwrap() = Some(Preview::new(cues));
+                    job.succeed(audio);
                 }
-                if std::fs::remove_file(current.join(subtitle_cache)).is_err() {
-                    MERGE.store(false, Ordering::Relaxed);
-                    return;
+                Err(e) => {
+                    error!("failed to preview {}: {:?}", audio.display(), e);
+                    job.fail(e);
+                }
+            }
+        });
+        handle
+    }
+
+    pub fn whisper_playlist(&self) -> JobHandle {
+        let (job, handle) = Job::new(JobKind::Playlist);
+        let glossary = match self.glossary() {
+            Ok(glossary) => glossary,
+            Err(e) => {
+                job.fail(e);
+                return handle;
+            }
+        };
+        let config = BatchConfig {
+            lang: self.config.lang,
+            model: self.config.model,
+            options: TranscribeOptions { glossary, cancel: self.cancel.clone(), events: self.events.clone(), ..self.config.options() },
+            formats: self.config.formats(),
+            policy: self.config.policy,
+            concurrency: match self.config.concurrency {
+                0 => self.config.model.default_concurrency(),
+                n => n,
+            },
+            state: None,
+        };
+        let batch = self.batch.clone();
+        tokio::spawn(async move {
+            if let Some(path) = rfd::FileDialog::new()
+                .add_filter("Playlist", &["txt", "json"])
+                .pick_file() {
+                job.running(None);
+                match load_playlist(&path) {
+                    Ok(items) => {
+                        *batch.lock().unwrap() = Some(Ok(BatchReport::default()));
+                        let config = BatchConfig { state: Some(state_path(&path)), ..config };
+                        let report = run_batch(&items, config, |event| match event {
+                            BatchEvent::Started(audio) => job.running(Some(audio.to_path_buf())),
+                            BatchEvent::Finished(audio, error) => {
+                                if let Some(Ok(ref mut report)) = *batch.lock().unwrap() {
+                                    match error {
+                                        None => report.succeeded.push(audio.to_path_buf()),
+                                        Some(e) => report.failed.push((audio.to_path_buf(), e.to_string())),
+                                    }
+                                }
+                            }
+                        })
+                        .await;
+                        *batch.lock().unwrap() = Some(Ok(report));
+                        job.succeed(path);
+                    }
+                    Err(e) => {
+                        job.fail(&e);
+                        *batch.lock().unwrap() = Some(Err(e));
+                    }
                 }
             } else {
-                MERGE.store(false, Ordering::Relaxed);
-                return;
+                job.fail("未选择列表文件");
             }
+        });
+        handle
+    }
 
-            MERGE.store(false, Ordering::Relaxed);
+    // kept in the current directory and selected as the audio, named after the video so the subtitles are too
+    pub fn fetch_audio(&self) -> JobHandle {
+        let (job, handle) = Job::new(JobKind::Fetch);
+        let Some(url) = ytdlp::parse_url(self.files.lock().unwrap().url.trim()) else {
+            job.fail("无法识别的链接");
+            return handle;
+        };
+        let files = self.files.clone();
+        let cancel = self.cancel.clone();
+        job.running(None);
+        tokio::task::spawn_blocking(move || {
+            let fetched = ytdlp::download(&url, &cancel).and_then(|download| Ok(download.keep(&std::env::current_dir()?)?));
+            match fetched {
+                Ok(audio) => {
+                    files.lock().unwrap().audio = Some(audio.clone());
+                    job.succeed(audio);
+                }
+                Err(e) => {
+                    error!("failed to fetch {}: {}", url, e);
+                    job.fail(e);
+                }
+            }
         });
+        handle
+    }
+
+    // mkv keeps the audio as is with the subtitle as a soft track and chapters at the split gaps instead of rendering a video.
+    // ffmpeg runs on the blocking pool once the future is polled, cancel stops it
+    pub fn merge(&self, mkv: bool) -> impl Future<Output = anyhow::Result<PathBuf>> + Send + 'static {
+        let file = self.files.lock().unwrap();
+        let (audio, subtitle) = (file.audio.clone(), file.subtitle.clone());
+        let background = match file.image {
+            Some(ref image) => Background::Image(image.clone()),
+            None if self.config.waveform_background => Background::Waveform,
+            None => Background::Color(self.config.background_color),
+        };
+        let offset = self.config.subtitle_offset / 10;
+        let style = self.config.subtitle_style;
+        let chapter_gap = self.config.split_gap / 10;
+        let cancel = self.cancel.clone();
+        let events = self.events.clone();
+        async move {
+            let (Some(audio), Some(subtitle)) = (audio, subtitle) else {
+                bail!("需要同时选择音频和字幕");
+            };
+            tokio::task::spawn_blocking(move || {
+                let merge = |subtitle: &Path| match mkv {
+                    true => merge_mkv(&audio, subtitle, chapter_gap, None, &cancel, &events),
+                    false => merge_video(&audio, &background, Some(subtitle), &VideoOptions { style, ..Default::default() }, None, &cancel, &events),
+                };
+                if offset == 0 {
+                    return merge(&subtitle).map_err(anyhow::Error::from);
+                }
+                shift_file(&subtitle, offset).and_then(|shifted| {
+                    let result = merge(&shifted);
+                    let _ = std::fs::remove_file(shifted);
+                    Ok(result?)
+                })
+            })
+            .await?
+        }
+    }
+
+    pub fn ffmpeg_merge(&self, mkv: bool) -> JobHandle {
+        let (job, handle) = Job::new(JobKind::Merge);
+        job.running(self.files.lock().unwrap().audio.clone());
+        let merge = self.merge(mkv);
+        tokio::spawn(async move {
+            match merge.await {
+                Ok(output) => {
+                    info!("merged into {}", output.display());
+                    job.succeed(output);
+                }
+                Err(e) => {
+                    error!("merge failed: {:?}", e);
+                    job.fail(e);
+                }
+            }
+        });
+        handle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::ValueEnum;
+
+    use super::*;
+
+    fn round_trip(config: &Config) {
+        let json = serde_json::to_value(config).unwrap();
+        let back = serde_json::from_value::<Config>(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&back).unwrap(), json);
+    }
+
+    #[test]
+    fn config_round_trip() {
+        let config = Config::new(&ConfigFile::default(), &Args::default());
+        let models = Model::value_variants().iter().cycle();
+        for (lang, model) in Language::value_variants().iter().zip(models) {
+            let config = Config { lang: *lang, model: *model, ..config.clone() };
+            round_trip(&config);
+            assert_eq!(serde_json::from_value::<Config>(serde_json::to_value(&config).unwrap()).unwrap().lang, *lang);
+        }
+        for model in Model::value_variants() {
+            round_trip(&Config { model: *model, ..config.clone() });
+        }
+        for policy in [BatchPolicy::StopOnError, BatchPolicy::SkipAndContinue, BatchPolicy::RetryN(3)] {
+            round_trip(&Config { policy, ..config.clone() });
+        }
+        for vtt_cue in [VttCue::Dash, VttCue::Plain, VttCue::Voice] {
+            round_trip(&Config { vtt_cue, ..config.clone() });
+        }
+        for sampling in [Sampling::default(), Sampling::BeamSearch { beam_size: 5, patience: -1.0 }] {
+            round_trip(&Config { sampling, ..config.clone() });
+        }
+        for bilingual_order in BilingualOrder::value_variants() {
+            round_trip(&Config { bilingual_order: *bilingual_order, resample: Resample::Assert, channel: ChannelSelect::Right, ..config.clone() });
+        }
     }
 }
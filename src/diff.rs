@@ -0,0 +1,111 @@
+use crate::subtitle::is_cjk;
+use crate::whisper::{Transcript, Utterance};
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DiffReport {
+    pub reference_words: usize,
+    pub substitutions: usize,
+    pub deletions: usize,
+    pub insertions: usize,
+    pub matched_segments: usize,
+    pub unmatched_segments: usize,
+    /// share of the time with a cue on screen in either track during which both have one
+    pub overlap: f64,
+}
+
+impl DiffReport {
+    pub fn word_error_rate(&self) -> f64 {
+        (self.substitutions + self.deletions + self.insertions) as f64 / self.reference_words.max(1) as f64
+    }
+}
+
+// lowercase words without punctuation, every CJK character counts as a word of its own
+pub(crate) fn tokens(text: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if is_cjk(c) {
+            words.extend((!word.is_empty()).then(|| std::mem::take(&mut word)));
+            words.push(c.to_string());
+        } else if c.is_alphanumeric() || c == '\'' {
+            word.push(c);
+        } else {
+            words.extend((!word.is_empty()).then(|| std::mem::take(&mut word)));
+        }
+    }
+    words.extend((!word.is_empty()).then_some(word));
+    words
+}
+
+fn words(utterances: &[Utterance]) -> Vec<String> {
+    utterances.iter().flat_map(|u| tokens(&u.text)).collect()
+}
+
+// levenshtein over words keeping (cost, substitutions, deletions, insertions) per cell,
+// only two rows are kept so hour-long transcripts stay cheap on memory
+fn align(reference: &[String], hypothesis: &[String]) -> (usize, usize, usize) {
+    let mut prev = (0..=hypothesis.len()).map(|j| (j, 0, 0, j)).collect::<Vec<_>>();
+    for (i, r) in reference.iter().enumerate() {
+        let mut row = vec![(i + 1, 0, i + 1, 0)];
+        for (j, h) in hypothesis.iter().enumerate() {
+            let diagonal = if r == h { prev[j] } else { (prev[j].0 + 1, prev[j].1 + 1, prev[j].2, prev[j].3) };
+            let deletion = (prev[j + 1].0 + 1, prev[j + 1].1, prev[j + 1].2 + 1, prev[j + 1].3);
+            let insertion = (row[j].0 + 1, row[j].1, row[j].2, row[j].3 + 1);
+            row.push([diagonal, deletion, insertion].into_iter().min_by_key(|c| c.0).unwrap());
+        }
+        prev = row;
+    }
+    let (_, s, d, i) = prev[hypothesis.len()];
+    (s, d, i)
+}
+
+fn overlap(a: &Utterance, b: &Utterance) -> i64 {
+    (a.end.min(b.end) - a.start.max(b.start)).max(0)
+}
+
+// total length of the union of the cues
+fn coverage(utterances: &[Utterance]) -> i64 {
+    let mut spans = utterances.iter().map(|u| (u.start, u.end)).collect::<Vec<_>>();
+    spans.sort();
+    let mut total = 0;
+    let mut covered = i64::MIN;
+    for (start, end) in spans {
+        let start = start.max(covered);
+        if end > start {
+            total += end - start;
+            covered = end;
+        }
+    }
+    total
+}
+
+impl Transcript {
+    /// `other` is the reference, a segment is matched when a reference segment covers
+    /// at least half of it and its words equal the words of the covering segments
+    pub fn diff(&self, other: &Transcript) -> DiffReport {
+        let reference = words(&other.utterances);
+        let (substitutions, deletions, insertions) = align(&reference, &words(&self.utterances));
+
+        let mut matched_segments = 0;
+        for u in &self.utterances {
+            let covering = other.utterances.iter().filter(|r| overlap(u, r) > 0).cloned().collect::<Vec<_>>();
+            let covered = covering.iter().map(|r| overlap(u, r)).sum::<i64>();
+            if covered * 2 >= (u.end - u.start).max(1) && words(&covering) == words(std::slice::from_ref(u)) {
+                matched_segments += 1;
+            }
+        }
+
+        let both = self.utterances.iter().map(|u| other.utterances.iter().map(|r| overlap(u, r)).sum::<i64>()).sum::<i64>();
+        let either = coverage(&self.utterances.iter().chain(&other.utterances).cloned().collect::<Vec<_>>());
+
+        DiffReport {
+            reference_words: reference.len(),
+            substitutions,
+            deletions,
+            insertions,
+            matched_segments,
+            unmatched_segments: self.segment_count() - matched_segments,
+            overlap: both as f64 / either.max(1) as f64,
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+
+use crate::config::Model;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConvError {
+    #[error("下载 {model} 模型失败: {source}")]
+    ModelDownload { model: Model, source: Box<dyn std::error::Error + Send + Sync> },
+    #[error("{0}")]
+    ModelLoad(String),
+    #[error("无法读取音频 {}: {reason}", .path.display())]
+    AudioDecode { path: PathBuf, reason: String },
+    #[error("转换失败: {0}")]
+    Transcribe(String),
+    #[error("无法写入字幕 {}: {source}", .path.display())]
+    SubtitleWrite { path: PathBuf, source: std::io::Error },
+    #[error("未找到 FFmpeg，请安装后加入 PATH")]
+    FfmpegMissing,
+    #[error("FFmpeg 执行失败: {command}\n{stderr}")]
+    FfmpegFailed { command: String, stderr: String },
+    #[error("未找到 yt-dlp，请安装后加入 PATH，或在配置文件中设置 yt_dlp 的路径")]
+    YtDlpMissing,
+    #[error("yt-dlp 执行失败: {0}")]
+    YtDlpFailed(String),
+    #[error("翻译失败: {0}")]
+    Translate(String),
+    #[error("已取消")]
+    Cancelled,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// the source of a ModelDownload when the server answered but not with the model
+#[derive(Debug, thiserror::Error)]
+pub enum DownloadError {
+    #[error("服务器返回 HTTP {0}{}", if *.0 == 404 { "，没有这个模型文件" } else { "" })]
+    Http(u16),
+    #[error("连接提前断开，还差 {0} 字节")]
+    Truncated(u64),
+}
+
+impl ConvError {
+    /// trying the same thing again can help, e.g. a dropped connection, but not a missing ffmpeg or yt-dlp, a broken file or a model the server doesn't have
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::ModelDownload { source, .. } => match source.downcast_ref::<DownloadError>() {
+                Some(DownloadError::Http(status)) => *status >= 500 || *status == 408 || *status == 429,
+                Some(DownloadError::Truncated(_)) => true,
+                None => true,
+            },
+            Self::Transcribe(_) | Self::Io(_) => true,
+            _ => false,
+        }
+    }
+
+    /// stable names for machine readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::ModelDownload { source, .. } if matches!(source.downcast_ref(), Some(DownloadError::Http(404))) => "model_not_found",
+            Self::ModelDownload { .. } => "model_download",
+            Self::ModelLoad(_) => "model_load",
+            Self::AudioDecode { .. } => "audio_decode",
+            Self::Transcribe(_) => "transcribe",
+            Self::SubtitleWrite { .. } => "subtitle_write",
+            Self::FfmpegMissing => "ffmpeg_missing",
+            Self::FfmpegFailed { .. } => "ffmpeg_failed",
+            Self::YtDlpMissing => "yt_dlp_missing",
+            Self::YtDlpFailed(_) => "yt_dlp_failed",
+            Self::Translate(_) => "translate",
+            Self::Cancelled => "cancelled",
+            Self::Io(_) => "io",
+        }
+    }
+
+    pub fn from_spawn(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => Self::FfmpegMissing,
+            _ => Self::Io(e),
+        }
+    }
+}
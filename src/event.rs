@@ -0,0 +1,86 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::config::Model;
+use crate::error::ConvError;
+use crate::whisper::Utterance;
+
+/// serialized as {"event": "transcribe_progress", "percent": 42}, the field names are kept stable for --progress json
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AppEvent {
+    /// {"event": "download", "model": "medium", "stage": "started", "total": 1533763059}
+    Download {
+        model: Model,
+        #[serde(flatten)]
+        stage: DownloadStage,
+    },
+    /// total is None when the server doesn't send a length
+    DownloadProgress { model: Model, done: u64, total: Option<u64> },
+    TranscribeStarted { path: PathBuf },
+    Segment(Utterance),
+    /// percent of the transcribed range
+    TranscribeProgress { percent: u8 },
+    /// 0.0 to 1.0 of the audio duration
+    MergeProgress { fraction: f32 },
+    Completed { outputs: Vec<PathBuf> },
+    Failed { error: String, code: &'static str },
+}
+
+/// the lifecycle of a model download, DownloadProgress carries the bytes in between
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum DownloadStage {
+    Started { total: Option<u64> },
+    Finished { path: PathBuf },
+    Failed { error: String },
+}
+
+pub trait ErrorCode {
+    fn code(&self) -> &'static str;
+}
+
+impl ErrorCode for ConvError {
+    fn code(&self) -> &'static str {
+        ConvError::code(self)
+    }
+}
+
+impl ErrorCode for anyhow::Error {
+    fn code(&self) -> &'static str {
+        self.downcast_ref::<ConvError>().map_or("other", ConvError::code)
+    }
+}
+
+/// where the pipeline reports what it's doing, the default one has no receiver and drops everything
+#[derive(Debug, Clone, Default)]
+pub struct Events(Option<mpsc::UnboundedSender<AppEvent>>);
+
+impl Events {
+    pub fn channel() -> (Self, mpsc::UnboundedReceiver<AppEvent>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (Self(Some(sender)), receiver)
+    }
+
+    pub fn send(&self, event: AppEvent) {
+        if let Some(ref sender) = self.0 {
+            let _ = sender.send(event);
+        }
+    }
+
+    /// Completed or Failed for the result of a whole step, which is passed through
+    pub fn finish<T, E: Display + ErrorCode>(&self, result: Result<T, E>, outputs: impl FnOnce(&T) -> Vec<PathBuf>) -> Result<T, E> {
+        match result {
+            Ok(ref value) => self.send(AppEvent::Completed { outputs: outputs(value) }),
+            Err(ref e) => self.send(AppEvent::Failed { error: e.to_string(), code: e.code() }),
+        }
+        result
+    }
+
+    pub fn is_listening(&self) -> bool {
+        self.0.as_ref().is_some_and(|sender| !sender.is_closed())
+    }
+}
@@ -0,0 +1,1105 @@
+use std::io::Write;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use clap::{CommandFactory, ValueEnum};
+use notify::{RecursiveMode, Watcher};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::align::load_lines;
+use crate::batch::{BatchState, discover, process};
+use crate::cli::{Args, CompletionsArgs, ConfigArgs, ConvertArgs, DownloadModelArgs, MergeArgs, ProgressFormat, ServeArgs, TranscribeArgs, WatchArgs, WebhookArgs};
+use crate::config::{self, Language, Model};
+use crate::config_file::{self, DEFAULT_TEMPLATE};
+use crate::conv::Files;
+use crate::error::ConvError;
+use crate::{inhibit, log, server};
+use crate::server::{JobWebhooks, ServerConfig};
+use crate::settings::Settings;
+use crate::job::{CancelToken, Job, JobKind};
+use crate::naming::Naming;
+use crate::event::{AppEvent, DownloadStage, Events};
+use crate::subtitle::{shift_file, sniff_format};
+use crate::utils::{AUDIO_EXTENSIONS, Background, is_media, merged_path, VIDEO_EXTENSIONS, merge_mkv, merge_video, SubtitleStyle, VideoOptions};
+use crate::translate::CommandTranslator;
+use crate::webhook::{Payload, Webhook};
+use crate::whisper::{Format, load_glossary, TranscribeOptions, Transcript};
+use crate::worker::WhisperWorker;
+use crate::ytdlp;
+
+#[cfg(windows)]
+fn attach_console() {
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    // ATTACH_PARENT_PROCESS
+    unsafe {
+        AttachConsole(u32::MAX);
+    }
+}
+
+static JSON_PROGRESS: AtomicBool = AtomicBool::new(false);
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+// -q: only errors and the results on stdout
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+// 75 (EX_TEMPFAIL) tells scripts that running again may succeed, 1 that it won't
+fn failure(e: &ConvError) -> ExitCode {
+    if e.is_retryable() {
+        ExitCode::from(75)
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+// progress lines on stderr, overwritten in place, or one JSON object per event and line with --progress json
+fn print_progress(format: ProgressFormat) -> Events {
+    JSON_PROGRESS.store(format == ProgressFormat::Json, Ordering::Relaxed);
+    if format == ProgressFormat::Json {
+        log::set_stderr(false);
+    }
+    print_config_warnings();
+    let (events, mut receiver) = Events::channel();
+    tokio::spawn(async move {
+        while let Some(event) = receiver.recv().await {
+            if format == ProgressFormat::Json {
+                let _ = write_json_line(std::io::stderr().lock(), &event);
+                continue;
+            }
+            if QUIET.load(Ordering::Relaxed) {
+                continue;
+            }
+            match event {
+                AppEvent::Download { model, stage: DownloadStage::Started { total } } => eprintln!("下载模型 {}{}", model, size(total)),
+                AppEvent::Download { stage: DownloadStage::Finished { path }, .. } => eprintln!("\r下载完成: {}", path.display()),
+                AppEvent::Download { stage: DownloadStage::Failed { .. }, .. } => continue,
+                AppEvent::DownloadProgress { model, done, total: Some(total) } => eprint!("\r下载模型 {}: {}%", model, done * 100 / total.max(1)),
+                AppEvent::DownloadProgress { model, done, total: None } => eprint!("\r下载模型 {}: {} MB", model, done >> 20),
+                AppEvent::TranscribeProgress { percent } => eprint!("\r转换进度: {}%", percent),
+                AppEvent::MergeProgress { fraction } => eprint!("\r合并进度: {:.0}%", fraction * 100.0),
+                AppEvent::Completed { .. } | AppEvent::Failed { .. } => eprintln!(),
+                AppEvent::TranscribeStarted { .. } | AppEvent::Segment(_) => continue,
+            }
+            let _ = std::io::stderr().flush();
+        }
+    });
+    events
+}
+
+// one object per line and flushed, newlines in text are escaped by serde_json so a line is always a whole event
+fn write_json_line<W: Write>(mut writer: W, value: &impl Serialize) -> std::io::Result<()> {
+    let line = serde_json::to_string(value).map_err(std::io::Error::from)?;
+    writeln!(writer, "{}", line).and_then(|_| writer.flush())
+}
+
+fn message_event(message: &str) -> serde_json::Value {
+    serde_json::json!({ "event": "message", "message": message.trim() })
+}
+
+// " (1.5 GB)", " (142 MB)" or nothing when the server sent no length
+fn size(total: Option<u64>) -> String {
+    match total {
+        Some(total) if total >= 1 << 30 => format!(" ({:.1} GB)", total as f64 / (1u64 << 30) as f64),
+        Some(total) => format!(" ({} MB)", total >> 20),
+        None => String::new(),
+    }
+}
+
+// a line for people on stderr, {"event": "message", "message": ...} when progress is json
+macro_rules! note {
+    ($($arg:tt)*) => {
+        print_note(false, format_args!($($arg)*))
+    };
+}
+
+// the same for errors, which -q keeps
+macro_rules! alert {
+    ($($arg:tt)*) => {
+        print_note(true, format_args!($($arg)*))
+    };
+}
+
+fn print_note(error: bool, message: std::fmt::Arguments) {
+    if !error && QUIET.load(Ordering::Relaxed) {
+        return;
+    }
+    if JSON_PROGRESS.load(Ordering::Relaxed) {
+        let _ = write_json_line(std::io::stderr().lock(), &message_event(&message.to_string()));
+    } else {
+        eprintln!("{}", message);
+    }
+}
+
+// conv --download large
+pub async fn download(model: Model, progress: ProgressFormat) -> ExitCode {
+    #[cfg(windows)]
+    attach_console();
+
+    let events = print_progress(progress);
+    match events.finish(model.preload(&CancelToken::default(), &events).await, |path| vec![path.clone()]) {
+        Ok(path) => {
+            println!("{}", path.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            alert!("下载失败: {}", e);
+            failure(&e)
+        }
+    }
+}
+
+// 12 段 340 词, 速度 3.20x, load 3.1s, decode 1.2s, inference 214.8s (0.31x realtime)
+fn summary(t: &Transcript) -> String {
+    let speed = t.throughput().map(|speed| format!("速度 {:.2}x, ", speed)).unwrap_or_default();
+    format!("{} 段 {} 词, {}{}", t.segment_count(), t.word_count(), speed, t.timings)
+}
+
+// an invalid url is a usage error like any other bad argument
+fn webhook(args: WebhookArgs) -> Result<Option<Webhook>, ExitCode> {
+    config_file::get().webhook(args.webhook, args.webhook_secret).map_err(|e| {
+        alert!("{}", e);
+        ExitCode::from(2)
+    })
+}
+
+// deliveries still retrying are finished before exiting
+async fn flush(webhook: Option<Webhook>) {
+    if let Some(webhook) = webhook {
+        webhook.wait().await;
+    }
+}
+
+fn print_config_warnings() {
+    for warning in &config_file::get().warnings {
+        note!("{}", warning);
+    }
+}
+
+// conv serve --bind 0.0.0.0:8080 --token secret
+pub async fn serve(args: ServeArgs) -> ExitCode {
+    #[cfg(windows)]
+    attach_console();
+
+    let file = config_file::get();
+    let lang = file.lang(args.lang);
+    let model = file.model(args.model);
+    let token = args.token.or_else(|| std::env::var("CONV_SERVE_TOKEN").ok()).filter(|token| !token.is_empty());
+    let webhook = match webhook(args.webhook) {
+        Ok(webhook) => webhook,
+        Err(code) => return code,
+    };
+    if token.is_none() && !args.bind.ip().is_loopback() {
+        note!("未设置 --token，任何能访问 {} 的人都可以提交任务", args.bind);
+    }
+
+    let events = print_progress(ProgressFormat::Text);
+    let worker = Arc::new(WhisperWorker::spawn());
+    if let Err(e) = worker.load(lang, model, &CancelToken::default(), &events).await {
+        alert!("加载模型失败: {}", e);
+        return failure(&e);
+    }
+    let listener = match tokio::net::TcpListener::bind(args.bind).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            alert!("无法监听 {}: {}", args.bind, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let job_webhooks = match (args.no_job_webhooks, args.job_webhook_host) {
+        (true, _) => JobWebhooks::Off,
+        (false, hosts) if hosts.is_empty() => JobWebhooks::Any,
+        (false, hosts) => JobWebhooks::Hosts(hosts),
+    };
+    let config = ServerConfig { lang, model, max_upload: args.max_upload.saturating_mul(1 << 20), queue: args.queue, token, webhook, job_webhooks };
+    note!("监听 http://{}", args.bind);
+    let shutdown = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    match axum::serve(listener, server::router(worker, config)).with_graceful_shutdown(shutdown).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            alert!("服务出错: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// only the script goes to stdout so it can be redirected into the completion directory
+pub fn completions(args: CompletionsArgs) -> ExitCode {
+    #[cfg(windows)]
+    attach_console();
+
+    write_completions(args.shell, &mut std::io::stdout());
+    ExitCode::SUCCESS
+}
+
+fn write_completions<W: Write>(shell: clap_complete::Shell, writer: &mut W) {
+    clap_complete::generate(shell, &mut Args::command(), "conv", writer);
+}
+
+// conv config --print-default > ~/.config/conv/config.toml
+pub fn config(args: ConfigArgs) -> ExitCode {
+    #[cfg(windows)]
+    attach_console();
+
+    if args.print_default {
+        print!("{}", DEFAULT_TEMPLATE);
+        return ExitCode::SUCCESS;
+    }
+    let file = config_file::get();
+    print_config_warnings();
+    match file.path.exists() {
+        true => println!("{}", file.path.display()),
+        false => println!("{} (不存在，使用默认设置)", file.path.display()),
+    }
+    ExitCode::SUCCESS
+}
+
+// conv download-model medium large --dir /models --verify
+pub async fn download_models(args: DownloadModelArgs) -> ExitCode {
+    #[cfg(windows)]
+    attach_console();
+
+    let models = if args.all { Model::value_variants().to_vec() } else { args.models };
+    let dir = args.dir.unwrap_or_else(config::model_dir);
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        alert!("无法创建目录 {}: {}", dir.display(), e);
+        return ExitCode::from(73);
+    }
+
+    let events = print_progress(args.progress);
+    let cancel = CancelToken::default();
+    let mut code = ExitCode::SUCCESS;
+    for model in models {
+        let path = model.path_in(&dir);
+        if path.exists() && !args.force {
+            match args.verify.then(|| model.verify(&path)) {
+                None | Some(Ok(true)) => {
+                    note!("跳过 {} (已存在): {}", model, path.display());
+                    continue;
+                }
+                Some(_) => alert!("{} 校验失败，重新下载", path.display()),
+            }
+        }
+        if path.exists() {
+            if let Err(e) = std::fs::remove_file(&path) {
+                alert!("无法删除 {}: {}", path.display(), e);
+                code = ExitCode::FAILURE;
+                continue;
+            }
+        }
+
+        note!("下载 {}: {}", model, path.display());
+        let result = model.download_to(&path, &cancel, &events).await.and_then(|_| {
+            if args.verify && !model.verify(&path)? {
+                let _ = std::fs::remove_file(&path);
+                return Err(ConvError::ModelDownload { model, source: "SHA-1 校验失败".into() });
+            }
+            Ok(())
+        });
+        if let Err(e) = events.finish(result, |_| vec![path.clone()]) {
+            alert!("下载失败: {}", e);
+            code = failure(&e);
+        }
+    }
+    code
+}
+
+// a.ja.srt next to a.srt, or the template's own name when it has a place for the language
+fn translated_path(naming: &Naming, target: &Path, base: &Path, to: Language) -> PathBuf {
+    if naming.uses_lang() {
+        return naming.base(target, Some(to));
+    }
+    let ext = base.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    base.with_extension(format!("{}.{}", <&str>::from(to), ext))
+}
+
+// trailing newline policy for every format: the file contents unchanged, plus one newline
+// when they don't already end with one (json), so what's piped on never ends mid line.
+// nothing at all when there's nothing to write, an empty transcript stays empty
+fn stdout_bytes(t: &Transcript, format: Format) -> Result<Vec<u8>, ConvError> {
+    let mut subtitle = vec![];
+    t.write_to(&mut subtitle, format)?;
+    if !subtitle.is_empty() && !subtitle.ends_with(b"\n") {
+        subtitle.push(b'\n');
+    }
+    Ok(subtitle)
+}
+
+fn write_stdout(t: &Transcript, format: Format) -> Result<(), ConvError> {
+    let subtitle = stdout_bytes(t, format)?;
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&subtitle).and_then(|_| stdout.flush())?;
+    Ok(())
+}
+
+// conv transcribe a.wav b.mp3 --format srt,vtt --output-dir out/
+pub async fn transcribe(mut args: TranscribeArgs) -> ExitCode {
+    #[cfg(windows)]
+    attach_console();
+
+    let file = config_file::get();
+    args.format = Format::dedup(file.formats(args.format));
+    if args.word_json && !args.format.contains(&Format::Json) {
+        args.format.push(Format::Json);
+    }
+    let naming = file.naming(args.naming.take());
+    let karaoke = args.format.contains(&Format::AssKaraoke);
+    let lang = file.lang(args.lang);
+    let model = file.model(args.model);
+    if let Some(warning) = model.language_warning(lang) {
+        alert!("{}", warning);
+        if !args.force {
+            alert!("请改用 --model {}，或加上 --force 继续", model.multilingual());
+            return ExitCode::from(2);
+        }
+    }
+    if let Some(warning) = model.memory_warning() {
+        note!("{}", warning);
+    }
+    if args.stdout && args.format.len() != 1 {
+        alert!("--stdout 只能输出一种格式，请用 --format 指定");
+        return ExitCode::from(2);
+    }
+    if args.only_lang.is_some() && lang != Language::Auto {
+        alert!("--only-lang 需要自动识别语言，请去掉 --lang 或改为 --lang auto");
+        return ExitCode::from(2);
+    }
+    let webhook = match webhook(args.webhook) {
+        Ok(webhook) => webhook,
+        Err(code) => return code,
+    };
+    if let Some(ref dir) = args.output_dir {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            alert!("无法创建输出目录 {}: {}", dir.display(), e);
+            return ExitCode::from(2);
+        }
+    }
+    // (audio, where it goes under the output directory)
+    let mut inputs = vec![];
+    for input in &args.inputs {
+        if !input.is_dir() {
+            inputs.push((input.clone(), PathBuf::from(input.file_name().unwrap_or_default())));
+            continue;
+        }
+        match discover(input, args.recursive, &[&AUDIO_EXTENSIONS[..], &VIDEO_EXTENSIONS[..]].concat(), &args.include, &args.exclude) {
+            Ok(found) => inputs.extend(found.into_iter().map(|audio| {
+                let relative = audio.strip_prefix(input).unwrap_or(&audio).to_path_buf();
+                (audio, relative)
+            })),
+            Err(e) => {
+                alert!("无法读取目录 {}: {}", input.display(), e);
+                return ExitCode::from(2);
+            }
+        }
+    }
+    if args.stdout && inputs.len() != 1 {
+        alert!("--stdout 只能转换一个音频，找到 {} 个", inputs.len());
+        return ExitCode::from(2);
+    }
+    if args.align.is_some() && inputs.len() != 1 {
+        alert!("--align 只能用于一个音频，找到 {} 个", inputs.len());
+        return ExitCode::from(2);
+    }
+    let lines = match args.align.as_ref().map(load_lines).transpose() {
+        Ok(Some(lines)) if lines.is_empty() => {
+            alert!("歌词为空: {}", args.align.as_ref().unwrap().display());
+            return ExitCode::from(65);
+        }
+        Ok(lines) => lines,
+        Err(e) => {
+            alert!("无法读取歌词: {}", e);
+            return ExitCode::from(66);
+        }
+    };
+
+    inhibit::spawn(Arc::new(Mutex::new(Settings::load())));
+
+    let events = print_progress(args.progress);
+    let worker = WhisperWorker::spawn();
+    if let Err(e) = worker.load(lang, model, &CancelToken::default(), &events).await {
+        alert!("加载模型失败: {}", e);
+        return failure(&e);
+    }
+
+    let mut code = ExitCode::SUCCESS;
+    let (mut succeeded, mut skipped, mut failed) = (vec![], vec![], vec![]);
+    for (i, (input, relative)) in inputs.iter().enumerate() {
+        // a link's audio is fetched first, its subtitles are named after the video in the current or output directory
+        let download = match input.to_str().and_then(ytdlp::parse_url).filter(|_| !input.exists()) {
+            Some(url) => {
+                note!("[{}/{}] 下载中: {}", i + 1, inputs.len(), url);
+                match ytdlp::download(&url, &CancelToken::default()) {
+                    Ok(download) => Some(download),
+                    Err(e) => {
+                        alert!("下载失败: {}: {}", url, e);
+                        code = failure(&e);
+                        failed.push((input, e.to_string()));
+                        continue;
+                    }
+                }
+            }
+            None => None,
+        };
+        // subtitles are named after the audio, in the output directory when there is one
+        let (mut audio, target) = match download {
+            Some(ref download) => {
+                let name = download.audio.file_name().unwrap_or_default();
+                (download.audio.clone(), args.output_dir.as_ref().map_or_else(|| PathBuf::from(name), |dir| dir.join(name)))
+            }
+            None => (input.clone(), args.output_dir.as_ref().map_or_else(|| input.clone(), |dir| dir.join(relative))),
+        };
+        if let (Some(ref download), true) = (&download, args.keep_audio) {
+            match download.keep(target.parent().unwrap_or(Path::new(""))) {
+                Ok(kept) => {
+                    note!("音频已保存: {}", kept.display());
+                    audio = kept;
+                }
+                Err(e) => {
+                    alert!("无法保存音频: {}: {}", target.display(), e);
+                    code = ExitCode::from(73);
+                    failed.push((input, e.to_string()));
+                    continue;
+                }
+            }
+        }
+        let exists = |base: &PathBuf| args.format.iter().all(|format| base.with_extension(format.extension()).exists());
+        if !args.stdout && !args.overwrite && naming.bases(&target, Some(lang)).iter().any(exists) {
+            note!("[{}/{}] 跳过 (字幕已存在): {}", i + 1, inputs.len(), input.display());
+            skipped.push(input);
+            continue;
+        }
+        // with {lang} and auto the name waits for the detected language, so nothing is streamed under a wrong one
+        let detect = naming.uses_lang() && lang == Language::Auto;
+        let mut base = naming.base(&target, Some(lang));
+        if !args.stdout && !detect {
+            if let Some(parent) = base.parent() {
+                let _ = std::fs::create_dir_all(parent);
+            }
+        }
+        let options = TranscribeOptions {
+            translate: args.translate,
+            // the lines are timed from the recognized words, the karaoke file fills word by word
+            word_timestamps: args.word_timestamps || lines.is_some() || karaoke || args.word_json,
+            resample: args.resample,
+            channel: args.channel,
+            max_tokens: args.max_tokens as i32,
+            language_window_ms: args.language_window.map_or(0, |secs| secs as i32 * 1000),
+            stream_to: (!args.stdout && !detect && lines.is_none()).then(|| (base.clone(), args.format.clone())),
+            events: events.clone(),
+            clean_text: true,
+            ..Default::default()
+        };
+        note!("[{}/{}] 转换中: {}", i + 1, inputs.len(), input.display());
+        events.send(AppEvent::TranscribeStarted { path: audio.clone() });
+        let (job, handle) = Job::new(JobKind::Whisper);
+        job.running(Some(audio.clone()));
+        let started = Instant::now();
+        // a translator fills in the translations in one pass, whisper's english takes a second one
+        let transcribed = match args.bilingual && args.translator.is_none() {
+            true => worker.transcribe_bilingual(audio.clone(), lang, model, options).await,
+            false => worker.transcribe(audio.clone(), lang, model, options).await,
+        };
+        let result = transcribed.and_then(|mut t| {
+            if let Some(ref lines) = lines {
+                let (aligned, unmatched) = t.align_lines(lines);
+                for n in unmatched {
+                    note!("第 {} 行没有对上，按前后两行估计时间: {}", n + 1, lines[n]);
+                }
+                t = aligned;
+            }
+            if let Some(only) = args.only_lang {
+                t.filter_language(only);
+            }
+            if let (Some(to), Some(ref command)) = (args.translate_to, &args.translator) {
+                let from = (lang != Language::Auto).then(|| <&str>::from(lang));
+                t.translate_with(&mut CommandTranslator::new(command), from, to.into(), args.translate_batch)?;
+            }
+            if args.lang_tags {
+                t.tag_languages();
+            }
+            if args.stdout {
+                write_stdout(&t, args.format[0])?;
+                return Ok(t);
+            }
+            if detect {
+                base = naming.base(&target, t.language());
+                if let Some(parent) = base.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+            }
+            let bilingual = args.bilingual.then(|| t.bilingual(args.bilingual_order));
+            // word cues fetched only for the karaoke file or the word level json stay out of the others
+            let segments = ((karaoke || args.word_json) && !args.word_timestamps).then(|| Transcript { word_utterances: None, ..t.clone() });
+            for format in &args.format {
+                match format {
+                    Format::AssKaraoke => t.write_file(&base, *format)?,
+                    Format::Json if args.word_json => t.write_file(&base, *format)?,
+                    _ => bilingual.as_ref().or(segments.as_ref()).unwrap_or(&t).write_file(&base, *format)?,
+                }
+            }
+            if let Some(to) = args.translate_to {
+                let translated = t.translated();
+                for format in &args.format {
+                    translated.write_file(translated_path(&naming, &target, &base, to), *format)?;
+                }
+            }
+            Ok(t)
+        });
+        let outputs = match args.stdout {
+            true => vec![],
+            false => args
+                .format
+                .iter()
+                .flat_map(|format| {
+                    let translated = args.translate_to.map(|to| translated_path(&naming, &target, &base, to).with_extension(format.extension()));
+                    std::iter::once(base.with_extension(format.extension())).chain(translated)
+                })
+                .collect(),
+        };
+        let result = events.finish(result, |_| outputs.clone());
+        if let Some(ref webhook) = webhook {
+            webhook.notify(&Payload::new(handle.id, input.clone(), started, result.as_ref().map(|_| outputs.clone())));
+        }
+        match result {
+            Ok(t) if args.stdout => {
+                note!("{}: {}", input.display(), summary(&t));
+                job.succeed(audio.clone());
+                succeeded.push(input);
+            }
+            Ok(t) => {
+                note!("{}: {}", input.display(), summary(&t));
+                for output in &outputs {
+                    println!("{}", output.display());
+                }
+                job.succeed(base.with_extension(args.format[0].extension()));
+                succeeded.push(input);
+            }
+            Err(e) => {
+                alert!("转换失败: {}: {}", input.display(), e);
+                code = failure(&e);
+                failed.push((input, e.to_string()));
+                job.fail(e);
+            }
+        }
+    }
+
+    if inputs.len() > 1 {
+        alert!("\n成功 {}  跳过 {}  失败 {}", succeeded.len(), skipped.len(), failed.len());
+        for (audio, e) in &failed {
+            alert!("  失败  {}: {}", audio.display(), e);
+        }
+    }
+    flush(webhook).await;
+    code
+}
+
+// conv watch ~/Recordings --format srt
+pub async fn watch(mut args: WatchArgs) -> ExitCode {
+    #[cfg(windows)]
+    attach_console();
+
+    let file = config_file::get();
+    args.format = Format::dedup(file.formats(args.format));
+    let naming = file.naming(args.naming.take());
+    let lang = file.lang(args.lang);
+    let model = file.model(args.model);
+    if let Some(warning) = model.language_warning(lang) {
+        note!("{}", warning);
+    }
+    let webhook = match webhook(args.webhook) {
+        Ok(webhook) => webhook,
+        Err(code) => return code,
+    };
+    let (found, changes) = mpsc::unbounded_channel();
+    let mut watcher = match notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            if event.kind.is_create() || event.kind.is_modify() {
+                for path in event.paths {
+                    let _ = found.send(path);
+                }
+            }
+        }
+    }) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            alert!("无法监视目录: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mode = if args.recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+    if let Err(e) = watcher.watch(&args.dir, mode) {
+        alert!("无法监视目录 {}: {}", args.dir.display(), e);
+        return ExitCode::from(2);
+    }
+    inhibit::spawn(Arc::new(Mutex::new(Settings::load())));
+
+    let stop = CancelToken::default();
+    let interrupt = stop.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            note!("\n收到中断，当前文件完成后退出");
+            interrupt.cancel();
+        }
+    });
+
+    let state_path = args.dir.join(".conv-watch.json");
+    let mut state = BatchState::load(&state_path);
+    let events = print_progress(args.progress);
+    let worker = WhisperWorker::spawn();
+    note!("监视中: {}", args.dir.display());
+
+    // files already there when the watcher starts are picked up like new ones
+    let mut pending = discover(&args.dir, args.recursive, &[&AUDIO_EXTENSIONS[..], &VIDEO_EXTENSIONS[..]].concat(), &[], &[])
+        .unwrap_or_default()
+        .into_iter()
+        .map(|path| (path, (None, Instant::now())))
+        .collect::<HashMap<PathBuf, (Option<u64>, Instant)>>();
+    let mut changes = changes;
+    let settle = Duration::from_secs(args.settle);
+    while !stop.is_cancelled() {
+        tokio::select! {
+            Some(path) = changes.recv() => {
+                if is_media(&path) {
+                    pending.insert(path, (None, Instant::now()));
+                }
+                continue;
+            }
+            _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+        }
+
+        // a file is ready once its size stayed the same for the settle time
+        let mut ready = vec![];
+        pending.retain(|path, (size, since)| {
+            let Ok(current) = std::fs::metadata(path).map(|m| m.len()) else {
+                return false;
+            };
+            if *size != Some(current) {
+                *size = Some(current);
+                *since = Instant::now();
+                return true;
+            }
+            if since.elapsed() < settle {
+                return true;
+            }
+            ready.push(path.clone());
+            false
+        });
+        ready.sort();
+
+        for audio in ready {
+            if stop.is_cancelled() {
+                break;
+            }
+            if state.done.contains(&audio) {
+                continue;
+            }
+            note!("转换中: {}", audio.display());
+            events.send(AppEvent::TranscribeStarted { path: audio.clone() });
+            // as in transcribe, {lang} with auto names the files once the language is detected
+            let detect = naming.uses_lang() && lang == Language::Auto;
+            let mut base = naming.base(&audio, Some(lang));
+            if let (false, Some(parent)) = (detect, base.parent()) {
+                let _ = std::fs::create_dir_all(parent);
+            }
+            let options = TranscribeOptions {
+                stream_to: (!detect).then(|| (base.clone(), args.format.clone())),
+                events: events.clone(),
+                clean_text: true,
+                ..Default::default()
+            };
+            let (job, handle) = Job::new(JobKind::Whisper);
+            job.running(Some(audio.clone()));
+            let started = Instant::now();
+            let result = worker.transcribe(audio.clone(), lang, model, options).await.and_then(|t| {
+                if detect {
+                    base = naming.base(&audio, t.language());
+                    if let Some(parent) = base.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                }
+                for format in &args.format {
+                    t.write_file(&base, *format)?;
+                }
+                Ok(t)
+            });
+            let outputs: Vec<_> = args.format.iter().map(|format| base.with_extension(format.extension())).collect();
+            let result = events.finish(result, |_| outputs.clone());
+            if let Some(ref webhook) = webhook {
+                webhook.notify(&Payload::new(handle.id, audio.clone(), started, result.as_ref().map(|_| outputs.clone())));
+            }
+            match result {
+                Ok(t) => {
+                    note!("{}: {}", audio.display(), summary(&t));
+                    job.succeed(outputs[0].clone());
+                }
+                Err(e) => {
+                    alert!("转换失败: {}: {}", audio.display(), e);
+                    job.fail(e);
+                    // retried when the file changes again
+                    continue;
+                }
+            }
+            state.done.insert(audio);
+            state.save(&state_path);
+        }
+    }
+    flush(webhook).await;
+    ExitCode::SUCCESS
+}
+
+// conv convert input.srt --to vtt,lrc --shift-ms -700 --out-dir subs/
+// exits with 66 when an input can't be read, 65 when it can't be parsed and 73 when an output can't be written
+pub fn convert(mut args: ConvertArgs) -> ExitCode {
+    #[cfg(windows)]
+    attach_console();
+
+    print_config_warnings();
+    args.to = Format::dedup(args.to);
+    if let Some(ref dir) = args.out_dir {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            alert!("无法创建输出目录 {}: {}", dir.display(), e);
+            return ExitCode::from(73);
+        }
+    }
+    let mut code = ExitCode::SUCCESS;
+    for input in &args.inputs {
+        let text = match std::fs::read_to_string(input) {
+            Ok(text) => text,
+            Err(e) => {
+                alert!("无法读取 {}: {}", input.display(), e);
+                code = ExitCode::from(66);
+                continue;
+            }
+        };
+        let format = input.extension().and_then(|e| e.to_str()).and_then(Format::from_extension).or_else(|| sniff_format(&text));
+        let parsed = format.ok_or_else(|| anyhow::anyhow!("unsupported subtitle format")).and_then(|format| Transcript::parse(&text, format));
+        let mut t = match parsed {
+            Ok(t) => t,
+            Err(e) => {
+                alert!("无法解析 {}: {}", input.display(), e);
+                code = ExitCode::from(65);
+                continue;
+            }
+        };
+
+        t.shift(args.shift_ms / 10);
+        if let Some(gap) = args.merge_gap {
+            t.merge_utterances(gap / 10, args.max_chars);
+        }
+        if args.clean {
+            t.clean_text();
+        }
+        if let Some(width) = args.wrap {
+            t.wrap_lines(width);
+        }
+
+        let base = match args.out_dir {
+            Some(ref dir) => dir.join(input.file_name().unwrap_or_default()),
+            None => input.clone(),
+        };
+        for format in &args.to {
+            let output = base.with_extension(format.extension());
+            if output == *input {
+                alert!("跳过 {}: 会覆盖输入文件，请用 --out-dir 指定其他目录", output.display());
+                code = ExitCode::from(73);
+                continue;
+            }
+            match t.write_file(&base, *format) {
+                Ok(()) => println!("{}", output.display()),
+                Err(e) => {
+                    alert!("{}", e);
+                    code = ExitCode::from(73);
+                }
+            }
+        }
+        if let Some(fps) = args.markers {
+            let output = base.with_extension("fcpxml");
+            match std::fs::write(&output, t.to_fcpxml_markers(fps)) {
+                Ok(()) => println!("{}", output.display()),
+                Err(e) => {
+                    alert!("无法写入 {}: {}", output.display(), e);
+                    code = ExitCode::from(73);
+                }
+            }
+        }
+    }
+    code
+}
+
+// conv merge --audio a.flac --image cover.png --subtitle a.srt --output a.mp4 --resolution 1080p --burn
+pub async fn merge(args: MergeArgs) -> ExitCode {
+    #[cfg(windows)]
+    attach_console();
+
+    let output = args.output.clone().unwrap_or_else(|| merged_path(&args.audio, if args.mkv { "mkv" } else { "mp4" }));
+    if output.exists() && !args.overwrite {
+        alert!("{} 已存在，加上 --overwrite 覆盖", output.display());
+        return ExitCode::from(2);
+    }
+    let webhook = match webhook(args.webhook) {
+        Ok(webhook) => webhook,
+        Err(code) => return code,
+    };
+    // the offset is applied to a shifted copy, removed once merged
+    let shifted = match args.subtitle {
+        Some(ref subtitle) if args.offset != 0 => match shift_file(subtitle, args.offset / 10) {
+            Ok(shifted) => Some(shifted),
+            Err(e) => {
+                alert!("无法读取字幕 {}: {}", subtitle.display(), e);
+                return ExitCode::from(2);
+            }
+        },
+        _ => None,
+    };
+    let subtitle = shifted.as_ref().or(args.subtitle.as_ref());
+    let background = match (args.image, args.video, args.color) {
+        (Some(image), _, _) => Background::Image(image),
+        (_, Some(video), _) => Background::Video(video),
+        (_, _, Some(color)) => Background::Color(color),
+        _ if args.waveform => Background::Waveform,
+        _ => Background::Color([0, 0, 0]),
+    };
+    let style = config_file::get().style();
+    let options = VideoOptions {
+        style: SubtitleStyle {
+            alignment: args.alignment.unwrap_or(style.alignment),
+            margin_v: args.margin_v.unwrap_or(style.margin_v),
+        },
+        resolution: args.resolution,
+        soft_subtitle: !args.burn,
+    };
+
+    let events = print_progress(args.progress);
+    let (job, handle) = Job::new(JobKind::Merge);
+    job.running(Some(args.audio.clone()));
+    let started = Instant::now();
+    let cancel = CancelToken::default();
+    let merged = match subtitle {
+        Some(subtitle) if args.mkv => merge_mkv(&args.audio, subtitle, 500, Some(&output), &cancel, &events),
+        subtitle => merge_video(&args.audio, &background, subtitle.map(PathBuf::as_path), &options, Some(&output), &cancel, &events),
+    };
+    if let Some(shifted) = shifted {
+        let _ = std::fs::remove_file(shifted);
+    }
+    if let Some(ref webhook) = webhook {
+        webhook.notify(&Payload::new(handle.id, args.audio.clone(), started, merged.as_ref().map(|output| vec![output.clone()])));
+    }
+    let code = match merged {
+        Ok(output) => {
+            println!("{}", output.display());
+            job.succeed(output);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            alert!("合并失败: {}", e);
+            let code = failure(&e);
+            job.fail(e);
+            code
+        }
+    };
+    flush(webhook).await;
+    code
+}
+
+pub async fn run(args: Args) -> ExitCode {
+    #[cfg(windows)]
+    attach_console();
+
+    let mut files = Files::default();
+    for path in args.paths {
+        if let Err(e) = files.insert(path) {
+            note!("{}", e);
+        }
+    }
+    let Some(audio) = files.audio else {
+        match files.url.is_empty() {
+            true => alert!("未指定音频"),
+            false => alert!("--no-gui 不下载视频链接，请改用 conv transcribe {}", files.url),
+        }
+        return ExitCode::from(2);
+    };
+    inhibit::spawn(Arc::new(Mutex::new(Settings::load())));
+    let file = config_file::get();
+    let lang = file.lang(args.lang);
+    let model = file.model(args.model);
+
+    if let Some(warning) = model.language_warning(lang) {
+        alert!("{}", warning);
+        if !args.force {
+            alert!("请改用 --model {}，或加上 --force 继续", model.multilingual());
+            return ExitCode::from(2);
+        }
+    }
+    if let Some(warning) = model.memory_warning() {
+        note!("{}", warning);
+    }
+
+    let events = print_progress(args.progress);
+    let worker = WhisperWorker::spawn();
+    if let Err(e) = worker.load(lang, model, &CancelToken::default(), &events).await {
+        alert!("\n加载模型失败: {}", e);
+        return failure(&e);
+    }
+
+    note!("转换中: {}", audio.display());
+    let (job, _) = Job::new(JobKind::Whisper);
+    job.running(Some(audio.clone()));
+    let mut formats = vec![Format::Lrc, Format::Srt, Format::Vtt];
+    if args.json {
+        formats.push(Format::Json);
+    }
+    let glossary = match args.glossary.as_ref().map(load_glossary).transpose() {
+        Ok(glossary) => glossary.unwrap_or_default(),
+        Err(e) => {
+            alert!("无法读取术语表: {}", e);
+            return ExitCode::from(2);
+        }
+    };
+    let options = TranscribeOptions {
+        resample: args.resample,
+        channel: args.channel,
+        glossary,
+        language_window_ms: args.language_window.map_or(0, |secs| secs as i32 * 1000),
+        events: events.clone(),
+        clean_text: true,
+        ..Default::default()
+    };
+    let t = match process(&worker, &audio, lang, model, &options, &formats).await {
+        Ok(t) => t,
+        Err(e) => {
+            alert!("转换失败: {}", e);
+            let code = e.downcast_ref::<ConvError>().map_or(ExitCode::FAILURE, failure);
+            job.fail(e);
+            return code;
+        }
+    };
+    note!("转换结束: {}, 用时 {:.1}s", summary(&t), t.processing_time.as_secs_f32());
+    job.succeed(audio.with_extension("srt"));
+
+    if let Some(ref reference) = args.reference {
+        match Transcript::from_file(reference) {
+            Ok(reference) => {
+                let report = t.diff(&reference);
+                note!(
+                    "词错误率: {:.2}% (替换 {} 删除 {} 插入 {} / {} 词), 匹配段落 {}/{}, 时间重合 {:.1}%",
+                    report.word_error_rate() * 100.0,
+                    report.substitutions,
+                    report.deletions,
+                    report.insertions,
+                    report.reference_words,
+                    report.matched_segments,
+                    report.matched_segments + report.unmatched_segments,
+                    report.overlap * 100.0,
+                );
+            }
+            Err(e) => alert!("无法读取参考字幕: {}", e),
+        }
+    }
+
+    if args.merge {
+        let background = files.image.map(Background::Image).unwrap_or(Background::Color([0, 0, 0]));
+        let subtitle = files.subtitle.unwrap_or_else(|| audio.with_extension("srt"));
+        note!("合并中");
+        let (job, _) = Job::new(JobKind::Merge);
+        job.running(Some(audio.clone()));
+        let merged = match args.mkv {
+            true => merge_mkv(&audio, &subtitle, 500, None, &CancelToken::default(), &events),
+            false => merge_video(&audio, &background, Some(&subtitle), &VideoOptions::default(), None, &CancelToken::default(), &events),
+        };
+        match merged {
+            Ok(output) => {
+                note!("合并结束: {}", output.display());
+                job.succeed(output);
+            }
+            Err(e) => {
+                alert!("合并失败: {}", e);
+                let code = failure(&e);
+                job.fail(e);
+                return code;
+            }
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::whisper::{Timings, Utterance};
+
+    use super::*;
+
+    #[test]
+    fn json_progress_lines_parse() {
+        let segment = Utterance { start: 0, end: 150, text: " two\nlines \"quoted\"".to_string(), lang: Some(Language::Japanese), avg_logprob: Some(-0.25), no_speech_prob: None, speaker: None, translation: None };
+        let events = [
+            AppEvent::Download { model: Model::Base, stage: DownloadStage::Started { total: Some(147951465) } },
+            AppEvent::DownloadProgress { model: Model::Base, done: 1 << 20, total: None },
+            AppEvent::Download { model: Model::Base, stage: DownloadStage::Finished { path: PathBuf::from("ggml-base.bin") } },
+            AppEvent::Download { model: Model::Base, stage: DownloadStage::Failed { error: "网络错误".to_string() } },
+            AppEvent::TranscribeStarted { path: PathBuf::from("a b.wav") },
+            AppEvent::Segment(segment),
+            AppEvent::TranscribeProgress { percent: 42 },
+            AppEvent::MergeProgress { fraction: 0.5 },
+            AppEvent::Completed { outputs: vec![PathBuf::from("a.srt")] },
+            AppEvent::Failed { error: "解码失败".to_string(), code: "audio_decode" },
+        ];
+        let mut out = vec![];
+        for event in &events {
+            write_json_line(&mut out, event).unwrap();
+        }
+        write_json_line(&mut out, &message_event("  已完成 \n")).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()).collect::<Vec<_>>();
+        let names = lines.iter().map(|line| line["event"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["download", "download_progress", "download", "download", "transcribe_started", "segment", "transcribe_progress", "merge_progress", "completed", "failed", "message"]
+        );
+        assert_eq!(lines[0]["stage"], "started");
+        assert_eq!(lines[5]["text"], " two\nlines \"quoted\"");
+        assert_eq!(lines[6]["percent"], 42);
+        assert_eq!(lines[10]["message"], "已完成");
+    }
+
+    #[test]
+    fn bash_completions_list_subcommands() {
+        let mut out = vec![];
+        write_completions(clap_complete::Shell::Bash, &mut out);
+        let out = String::from_utf8(out).unwrap();
+        for subcommand in ["transcribe", "watch", "convert", "merge", "serve"] {
+            assert!(out.contains(&format!("conv,{})", subcommand)), "{}", subcommand);
+        }
+    }
+
+    #[test]
+    fn stdout_ends_with_one_newline() {
+        let utterance = |start, end, text: &str| Utterance { start, end, text: text.to_string(), lang: None, avg_logprob: None, no_speech_prob: None, speaker: None, translation: None };
+        let t = Transcript {
+            processing_time: Duration::ZERO,
+            utterances: vec![utterance(0, 150, " Hello"), utterance(200, 350, " world")],
+            word_utterances: None,
+            timings: Timings::default(),
+        };
+        for &format in Format::value_variants() {
+            let mut file = vec![];
+            t.write_to(&mut file, format).unwrap();
+            let out = stdout_bytes(&t, format).unwrap();
+            assert!(out.ends_with(b"\n"), "{:?}", format);
+            assert!(out.starts_with(&file), "{:?}", format);
+            assert!(out.len() - file.len() <= 1, "{:?}", format);
+            let empty = Transcript { utterances: vec![], ..t.clone() };
+            let out = stdout_bytes(&empty, format).unwrap();
+            assert!(out.is_empty() || out.ends_with(b"\n"), "{:?}", format);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use crate::job;
+use crate::settings::Settings;
+use crate::utils::DOWNLOADING;
+
+#[cfg(windows)]
+mod platform {
+    extern "system" {
+        fn SetThreadExecutionState(flags: u32) -> u32;
+    }
+
+    const ES_CONTINUOUS: u32 = 0x80000000;
+    const ES_SYSTEM_REQUIRED: u32 = 0x00000001;
+
+    // the state belongs to the calling thread, acquire and release happen on the watcher thread
+    pub struct Lock;
+
+    impl Lock {
+        pub fn acquire() -> Option<Self> {
+            match unsafe { SetThreadExecutionState(ES_CONTINUOUS | ES_SYSTEM_REQUIRED) } {
+                0 => None,
+                _ => Some(Self),
+            }
+        }
+    }
+
+    impl Drop for Lock {
+        fn drop(&mut self) {
+            unsafe {
+                SetThreadExecutionState(ES_CONTINUOUS);
+            }
+        }
+    }
+}
+
+#[cfg(not(windows))]
+mod platform {
+    use std::process::{Child, Command, Stdio};
+
+    // caffeinate holds an IOPMAssertion, systemd-inhibit a logind inhibitor lock over D-Bus,
+    // both are released when the child is killed or, with panic = "abort", when conv itself dies
+    // (caffeinate -w watches our pid, cat exits once our end of its stdin is closed)
+    pub struct Lock(Child);
+
+    impl Lock {
+        pub fn acquire() -> Option<Self> {
+            let mut command = if cfg!(target_os = "macos") {
+                let mut command = Command::new("caffeinate");
+                command.args(["-i", "-w", &std::process::id().to_string()]);
+                command
+            } else {
+                let mut command = Command::new("systemd-inhibit");
+                command.args(["--what=sleep:idle", "--who=conv", "--why=正在转换", "--mode=block", "cat"]);
+                command
+            };
+            command
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()
+                .map(Self)
+        }
+    }
+
+    impl Drop for Lock {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+}
+
+fn busy() -> bool {
+    job::any_running() || DOWNLOADING.load(Ordering::Relaxed)
+}
+
+pub fn spawn(settings: Arc<Mutex<Settings>>) {
+    std::thread::spawn(move || {
+        let mut _lock = None;
+        let mut attempted = false;
+        loop {
+            let wanted = busy() && !settings.lock().unwrap().allow_sleep;
+            if wanted && !attempted {
+                _lock = platform::Lock::acquire();
+                attempted = true;
+            } else if !wanted {
+                _lock = None;
+                attempted = false;
+            }
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    });
+}
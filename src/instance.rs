@@ -0,0 +1,68 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::conv::Files;
+use crate::ytdlp;
+
+const HANDSHAKE: &str = "conv";
+
+pub struct Lock(PathBuf);
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn get_path() -> PathBuf {
+    let current = std::env::current_dir().unwrap();
+    current.join("conv.lock")
+}
+
+fn hand_off(port: u16, paths: &[PathBuf]) -> std::io::Result<()> {
+    let mut stream = TcpStream::connect_timeout(&([127, 0, 0, 1], port).into(), Duration::from_secs(1))?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    if line.trim() != HANDSHAKE {
+        return Err(std::io::Error::other("not a conv instance"));
+    }
+    for path in paths {
+        // a link goes over as it is, the other instance has another working directory
+        match path.to_str().and_then(ytdlp::parse_url).filter(|_| !path.exists()) {
+            Some(url) => writeln!(stream, "{}", url)?,
+            None => writeln!(stream, "{}", std::path::absolute(path)?.display())?,
+        }
+    }
+    Ok(())
+}
+
+// None when the paths were handed to an already running instance,
+// a lock file whose port no longer answers is left over from a crash and gets reclaimed
+pub fn acquire(paths: &[PathBuf]) -> std::io::Result<Option<(Lock, TcpListener)>> {
+    let path = get_path();
+    if let Some(port) = std::fs::read_to_string(&path).ok().and_then(|s| s.trim().parse().ok()) {
+        if hand_off(port, paths).is_ok() {
+            return Ok(None);
+        }
+    }
+    let listener = TcpListener::bind(("127.0.0.1", 0))?;
+    std::fs::write(&path, listener.local_addr()?.port().to_string())?;
+    Ok(Some((Lock(path), listener)))
+}
+
+pub fn serve(listener: TcpListener, files: Arc<Mutex<Files>>) {
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            if writeln!(stream, "{}", HANDSHAKE).is_err() {
+                continue;
+            }
+            for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                let _ = files.lock().unwrap().insert(PathBuf::from(line));
+            }
+        }
+    });
+}
@@ -0,0 +1,190 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use tokio::sync::watch;
+use tracing::debug;
+
+#[allow(deprecated)]
+use crate::utils::{MERGE, WHISPER};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+// running jobs behind each of the old flags, WHISPER then MERGE. a flag only clears when the last of its jobs is done,
+// batch workers and the server queue run several at once
+static SHIM_RUNNING: Mutex<[usize; 2]> = Mutex::new([0; 2]);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobStatus {
+    Queued,
+    Running { file: Option<PathBuf> },
+    Succeeded(PathBuf),
+    Failed(String),
+}
+
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Succeeded(_) | Self::Failed(_))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    Whisper,
+    Playlist,
+    Merge,
+    /// a video's audio fetched with yt-dlp
+    Fetch,
+}
+
+#[derive(Debug, Clone)]
+pub struct JobHandle {
+    pub id: u64,
+    pub kind: JobKind,
+    pub status: watch::Receiver<JobStatus>,
+}
+
+impl JobHandle {
+    pub fn status(&self) -> JobStatus {
+        self.status.borrow().clone()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.status.borrow().is_finished()
+    }
+}
+
+/// the sending half, kept by the task doing the work.
+/// Queued -> Running -> Succeeded | Failed, dropping it unfinished (early return, panic) fails the job
+pub struct Job {
+    id: u64,
+    kind: JobKind,
+    sender: watch::Sender<JobStatus>,
+}
+
+/// shared by everything a job spawns, cancelling it stops downloads, transcription and ffmpeg alike
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn flag(&self) -> &AtomicBool {
+        &self.0
+    }
+}
+
+pub fn any_running() -> bool {
+    RUNNING.load(Ordering::Relaxed) > 0
+}
+
+impl Job {
+    pub fn new(kind: JobKind) -> (Self, JobHandle) {
+        let (sender, status) = watch::channel(JobStatus::Queued);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        (Self { id, kind, sender }, JobHandle { id, kind, status })
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    #[allow(deprecated)]
+    fn shim(&self) -> (usize, &'static AtomicBool) {
+        match self.kind {
+            JobKind::Whisper | JobKind::Playlist | JobKind::Fetch => (0, &WHISPER),
+            JobKind::Merge => (1, &MERGE),
+        }
+    }
+
+    fn set(&self, status: JobStatus) {
+        let was_running = matches!(*self.sender.borrow(), JobStatus::Running { .. });
+        let running = matches!(status, JobStatus::Running { .. });
+        if was_running != running {
+            match running {
+                true => RUNNING.fetch_add(1, Ordering::Relaxed),
+                false => RUNNING.fetch_sub(1, Ordering::Relaxed),
+            };
+            let mut shims = SHIM_RUNNING.lock().unwrap();
+            let (shim, flag) = self.shim();
+            shims[shim] = if running { shims[shim] + 1 } else { shims[shim] - 1 };
+            flag.store(shims[shim] > 0, Ordering::Relaxed);
+        }
+        debug!("job {} ({:?}): {:?}", self.id, self.kind, status);
+        self.sender.send_replace(status);
+    }
+
+    pub fn running(&self, file: Option<PathBuf>) {
+        self.set(JobStatus::Running { file });
+    }
+
+    pub fn succeed(self, output: PathBuf) {
+        self.set(JobStatus::Succeeded(output));
+    }
+
+    pub fn fail(self, reason: impl Display) {
+        self.set(JobStatus::Failed(reason.to_string()));
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        if !self.sender.borrow().is_finished() {
+            self.set(JobStatus::Failed("任务意外中断".to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn succeeds() {
+        let (job, handle) = Job::new(JobKind::Whisper);
+        assert_eq!(handle.status(), JobStatus::Queued);
+        job.running(Some(PathBuf::from("a.wav")));
+        assert_eq!(handle.status(), JobStatus::Running { file: Some(PathBuf::from("a.wav")) });
+        assert!(!handle.is_finished());
+        job.succeed(PathBuf::from("a.srt"));
+        assert_eq!(handle.status(), JobStatus::Succeeded(PathBuf::from("a.srt")));
+        assert!(handle.is_finished());
+    }
+
+    #[test]
+    fn fails() {
+        let (job, handle) = Job::new(JobKind::Playlist);
+        job.running(None);
+        job.fail("解码失败");
+        assert_eq!(handle.status(), JobStatus::Failed("解码失败".to_string()));
+    }
+
+    #[test]
+    fn dropped_unfinished_fails() {
+        let (job, handle) = Job::new(JobKind::Fetch);
+        job.running(None);
+        drop(job);
+        assert_eq!(handle.status(), JobStatus::Failed("任务意外中断".to_string()));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn shim_stays_set_while_any_job_runs() {
+        // the only test with merge jobs, the whisper flag is shared with the others
+        let (first, _) = Job::new(JobKind::Merge);
+        let (second, _) = Job::new(JobKind::Merge);
+        first.running(None);
+        second.running(None);
+        first.succeed(PathBuf::from("a.mp4"));
+        assert!(MERGE.load(Ordering::Relaxed));
+        second.fail("ffmpeg");
+        assert!(!MERGE.load(Ordering::Relaxed));
+    }
+}
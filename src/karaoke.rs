@@ -0,0 +1,110 @@
+use crate::subtitle::is_cjk;
+use crate::whisper::{Transcript, Utterance};
+
+// 1080p bottom centre, white text filled with gold as it's sung (colours are &HAABBGGRR), libass scales it to the video
+const HEADER: &str = "[Script Info]
+ScriptType: v4.00+
+PlayResX: 1920
+PlayResY: 1080
+WrapStyle: 0
+ScaledBorderAndShadow: yes
+
+[V4+ Styles]
+Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding
+Style: Karaoke,Arial,72,&H0000D7FF,&H00FFFFFF,&H00000000,&H80000000,-1,0,0,0,100,100,0,0,1,3,1,2,60,60,60,1
+
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+";
+
+// 0:01:02.34
+fn ass_time(time: i64) -> String {
+    let time = time.max(0);
+    format!("{}:{:02}:{:02}.{:02}", time / 360000, time / 6000 % 60, time / 100 % 60, time % 100)
+}
+
+// a brace would open an override block and a backslash a tag, the full width forms read the same
+fn escape(text: &str) -> String {
+    text.replace('\\', "＼").replace('{', "｛").replace('}', "｝").replace('\n', "\\N")
+}
+
+// the pieces the fill advances by: a word, or a single character of CJK text which has no spaces to find words by.
+// punctuation stays on the piece before it unless a space comes first, spaces go with the piece after
+fn syllables(text: &str) -> Vec<String> {
+    let mut pieces: Vec<String> = vec![];
+    let mut space = String::new();
+    for c in text.chars() {
+        if c.is_whitespace() {
+            space.push(c);
+            continue;
+        }
+        let after_cjk = pieces.last().and_then(|p| p.chars().last()).is_some_and(is_cjk);
+        match pieces.last_mut() {
+            Some(last) if space.is_empty() && !(c.is_alphanumeric() && (is_cjk(c) || after_cjk)) => {
+                last.push_str(&std::mem::take(&mut space));
+                last.push(c);
+            }
+            _ => pieces.push(std::mem::take(&mut space) + c.encode_utf8(&mut [0; 4])),
+        }
+    }
+    pieces
+}
+
+// one Dialogue line, each piece's \k lasts until the next one starts so the gaps between words are absorbed
+fn dialogue(segment: &Utterance, cues: &[&Utterance]) -> String {
+    // a cue split into several pieces shares its time by their letters, punctuation isn't sung
+    let mut pieces = vec![];
+    for cue in cues {
+        let parts = syllables(&cue.text);
+        let weights = parts.iter().map(|p| p.chars().filter(|c| c.is_alphanumeric()).count() as i64).collect::<Vec<_>>();
+        let total = weights.iter().sum::<i64>().max(1);
+        let mut done = 0;
+        for (part, weight) in parts.into_iter().zip(weights) {
+            pieces.push((cue.start + (cue.end - cue.start) * done / total, part));
+            done += weight;
+        }
+    }
+    let Some(first) = pieces.first() else {
+        return String::new();
+    };
+    let start = segment.start.min(first.0);
+    let end = cues.iter().map(|c| c.end).fold(segment.end, i64::max);
+
+    // a pause before the first word is an empty piece, so the fill starts with the singing
+    let mut text = match first.0 > start {
+        true => format!("{{\\k{}}}", first.0 - start),
+        false => String::new(),
+    };
+    let mut at = start;
+    for (i, (piece_start, piece)) in pieces.iter().enumerate() {
+        let from = (*piece_start).max(at);
+        let until = pieces.get(i + 1).map_or(end, |next| next.0).max(from);
+        let piece = if i == 0 { piece.trim_start() } else { piece };
+        text += &format!("{{\\k{}}}{}", until - from, escape(piece));
+        at = until;
+    }
+    format!("Dialogue: 0,{},{},Karaoke,,0,0,0,,{}\n", ass_time(start), ass_time(end), text)
+}
+
+impl Transcript {
+    /// word cues are put back under the segment they were said in, by their midpoint against where the next segment starts.
+    /// without word timestamps a segment's words share its time by their length
+    pub fn to_ass_karaoke(&self) -> String {
+        let words = self.word_utterances.as_deref().unwrap_or_default();
+        let mut next = 0;
+        let mut ass = String::from(HEADER);
+        for (i, segment) in self.utterances.iter().enumerate() {
+            let until = self.utterances.get(i + 1).map_or(i64::MAX, |u| u.start);
+            let mut cues = vec![];
+            while let Some(word) = words.get(next).filter(|w| (w.start + w.end) / 2 < until) {
+                cues.push(word);
+                next += 1;
+            }
+            if cues.is_empty() {
+                cues.push(segment);
+            }
+            ass += &dialogue(segment, &cues);
+        }
+        ass
+    }
+}
@@ -0,0 +1,72 @@
+//! The transcription and subtitle pipeline behind the conv app, usable without the GUI
+//! (`default-features = false`): download and load a whisper model, transcribe audio,
+//! write lrc/srt/vtt/json subtitles and merge them with ffmpeg.
+//!
+//! ```no_run
+//! use conv::config::{Language, Model};
+//! use conv::event::Events;
+//! use conv::job::CancelToken;
+//! use conv::utils::{read_file, ChannelSelect, Resample};
+//! use conv::whisper::{TranscribeOptions, Whisper};
+//!
+//! # async fn run() -> Result<(), conv::error::ConvError> {
+//! let mut whisper = Whisper::new(Language::English, Model::Base, &CancelToken::default(), &Events::default()).await?;
+//! let samples = read_file("speech.wav", Resample::Auto, ChannelSelect::Mix)?;
+//! let transcript = whisper.transcribe(&samples, &TranscribeOptions::default())?;
+//! print!("{}", transcript.to_srt());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Anything implementing [`transcriber::Transcriber`] can stand in for the model, here one that
+//! puts a single cue over the whole of a short μ-law recording:
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use conv::config::Language;
+//! use conv::error::ConvError;
+//! use conv::transcriber::Transcriber;
+//! use conv::utils::{read_file, ChannelSelect, Resample};
+//! use conv::whisper::{Timings, TranscribeOptions, Transcript, Utterance};
+//!
+//! struct OneCue;
+//!
+//! impl Transcriber for OneCue {
+//!     fn set_language(&mut self, _lang: Language) {}
+//!
+//!     fn transcribe(&mut self, audio: &[f32], _options: &TranscribeOptions) -> Result<Transcript, ConvError> {
+//!         let end = audio.len() as i64 / 160;
+//!         let cue = Utterance { start: 0, end, text: "Hello".to_string(), lang: None, avg_logprob: None, no_speech_prob: None, speaker: None, translation: None };
+//!         Ok(Transcript { processing_time: Duration::ZERO, utterances: vec![cue], word_utterances: None, timings: Timings::default() })
+//!     }
+//! }
+//!
+//! # fn main() -> Result<(), ConvError> {
+//! let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/mulaw-8k.wav");
+//! let samples = read_file(fixture, Resample::Auto, ChannelSelect::Mix)?;
+//! let transcript = OneCue.transcribe(&samples, &TranscribeOptions::default())?;
+//! assert_eq!(transcript.to_srt(), "1\n00:00:00,000 --> 00:00:00,100\nHello\n\n");
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod align;
+pub mod batch;
+pub mod chapters;
+pub mod config;
+pub mod diff;
+pub mod error;
+pub mod event;
+pub mod job;
+pub mod karaoke;
+pub mod markers;
+pub mod naming;
+pub mod subtitle;
+pub mod transcriber;
+pub mod translate;
+pub mod utils;
+pub mod webhook;
+pub mod whisper;
+pub mod worker;
+pub mod ytdlp;
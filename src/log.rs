@@ -0,0 +1,173 @@
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::layer::{Layer, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+
+const MAX_FILE_SIZE: u64 = 4 * 1024 * 1024;
+const MAX_FILES: usize = 5;
+const MAX_LINES: usize = 500;
+
+pub static LINES: Lazy<Mutex<VecDeque<String>>> = Lazy::new(Default::default);
+static STDERR: AtomicBool = AtomicBool::new(false);
+
+// %LOCALAPPDATA%\conv, ~/Library/Application Support/conv, $XDG_DATA_HOME/conv or ~/.local/share/conv
+pub fn log_dir() -> PathBuf {
+    let var = |key: &str| std::env::var_os(key).filter(|v| !v.is_empty()).map(PathBuf::from);
+    let base = if cfg!(windows) {
+        var("LOCALAPPDATA")
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| home.join("Library/Application Support"))
+    } else {
+        var("XDG_DATA_HOME").or_else(|| var("HOME").map(|home| home.join(".local/share")))
+    };
+    base.unwrap_or_else(std::env::temp_dir).join("conv").join("logs")
+}
+
+// conv.log -> conv.log.1 -> ... -> conv.log.4, the oldest one is dropped
+struct RotatingFile {
+    dir: PathBuf,
+    file: Option<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn new(dir: PathBuf) -> Self {
+        let _ = std::fs::create_dir_all(&dir);
+        let file = File::options().create(true).append(true).open(dir.join("conv.log")).ok();
+        let size = file.as_ref().and_then(|f| f.metadata().ok()).map(|m| m.len()).unwrap_or_default();
+        Self { dir, file, size }
+    }
+
+    fn rotate(&mut self) {
+        self.file = None;
+        let path = |i: usize| match i {
+            0 => self.dir.join("conv.log"),
+            i => self.dir.join(format!("conv.log.{}", i)),
+        };
+        let _ = std::fs::remove_file(path(MAX_FILES - 1));
+        for i in (0..MAX_FILES - 1).rev() {
+            let _ = std::fs::rename(path(i), path(i + 1));
+        }
+        self.file = File::create(path(0)).ok();
+        self.size = 0;
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.size + buf.len() as u64 > MAX_FILE_SIZE {
+            self.rotate();
+        }
+        self.size += buf.len() as u64;
+        match self.file {
+            Some(ref mut file) => file.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.file {
+            Some(ref mut file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+// feeds the log panel in the GUI
+struct Panel;
+
+impl Write for Panel {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut lines = LINES.lock().unwrap();
+        for line in String::from_utf8_lossy(buf).lines() {
+            lines.push_back(line.to_string());
+        }
+        while lines.len() > MAX_LINES {
+            lines.pop_front();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// diagnostics for the command line, each line clears the terminal line first so a progress line
+// that is overwritten in place is not mixed into it and gets drawn again below on its next update
+struct Stderr;
+
+impl Write for Stderr {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if !STDERR.load(Ordering::Relaxed) {
+            return Ok(buf.len());
+        }
+        let mut stderr = std::io::stderr().lock();
+        if stderr.is_terminal() {
+            stderr.write_all(b"\r\x1b[2K")?;
+        }
+        stderr.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
+// off in the GUI and with --progress json, where stderr is one JSON object per line
+pub fn set_stderr(enabled: bool) {
+    STDERR.store(enabled, Ordering::Relaxed);
+}
+
+// the file and the log panel get `level`, stderr `stderr_level`, RUST_LOG overrides both
+pub fn init(level: LevelFilter, stderr_level: LevelFilter) {
+    let filter = |level: LevelFilter| EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level.to_string()));
+    let file = tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .with_writer(Mutex::new(RotatingFile::new(log_dir())))
+        .with_filter(filter(level));
+    let panel = tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .with_target(false)
+        .with_writer(|| Panel)
+        .with_filter(filter(level));
+    let stderr = tracing_subscriber::fmt::layer()
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_target(false)
+        .without_time()
+        .with_writer(|| Stderr)
+        .with_filter(filter(stderr_level));
+    let _ = tracing_subscriber::registry()
+        .with(file)
+        .with(panel)
+        .with(stderr)
+        .try_init();
+
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        tracing::error!("{}\n{}", info, Backtrace::force_capture());
+        default(info);
+    }));
+}
+
+pub fn open_log_dir() {
+    let dir = log_dir();
+    let program = if cfg!(windows) {
+        "explorer"
+    } else if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    let _ = Command::new(program).arg(dir).spawn();
+}
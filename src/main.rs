@@ -1,26 +1,94 @@
 #![windows_subsystem = "windows"]
 
+use std::process::ExitCode;
+
+use clap::Parser;
+use tracing::level_filters::LevelFilter;
 use eframe::NativeOptions;
 use egui::{Vec2, ViewportBuilder};
 
+use ::conv::{align, batch, config, error, event, job, markers, naming, subtitle, translate, utils, webhook, whisper, worker, ytdlp};
+
+use crate::cli::{Args, Command};
 use crate::conv::Conv;
+use crate::whisper::SYSTEM_INFO;
 
 mod ui;
 mod font;
-mod utils;
-mod whisper;
-mod config;
 mod conv;
+mod player;
+mod preview;
+mod waveform;
+mod settings;
+mod cli;
+mod headless;
+mod inhibit;
+mod instance;
+mod update;
+mod log;
+mod config_file;
+mod server;
 
 #[tokio::main]
-async fn main() {
-    run().await;
+async fn main() -> ExitCode {
+    let mut args = Args::parse();
+    if args.version {
+        if args.verbose > 0 {
+            println!("{}", *SYSTEM_INFO);
+        } else {
+            println!("conv {}", env!("CARGO_PKG_VERSION"));
+        }
+        return ExitCode::SUCCESS;
+    }
+    let stderr_level = match (args.quiet, args.verbose) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => LevelFilter::WARN,
+        (false, 1) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+    log::init(args.log_level.map_or(LevelFilter::INFO, LevelFilter::from_level).max(stderr_level), stderr_level);
+    log::set_stderr(args.command.is_some() || args.no_gui || args.download.is_some());
+    headless::set_quiet(args.quiet);
+    config_file::init(args.config.as_deref());
+    if let Some(connections) = args.connections {
+        config::set_connections(connections as usize);
+    }
+    match args.command.take() {
+        Some(Command::Transcribe(args)) => return headless::transcribe(args).await,
+        Some(Command::Watch(args)) => return headless::watch(args).await,
+        Some(Command::Merge(args)) => return headless::merge(args).await,
+        Some(Command::Convert(args)) => return headless::convert(args),
+        Some(Command::DownloadModel(args)) => return headless::download_models(args).await,
+        Some(Command::Config(args)) => return headless::config(args),
+        Some(Command::Completions(args)) => return headless::completions(args),
+        Some(Command::Serve(args)) => return headless::serve(args).await,
+        None => {}
+    }
+    if let Some(model) = args.download {
+        return headless::download(model, args.progress).await;
+    }
+    if args.no_gui {
+        return headless::run(args).await;
+    }
+    run(args).await;
+    ExitCode::SUCCESS
 }
 
-async fn run() {
+async fn run(args: Args) {
+    let instance = if args.new_instance {
+        None
+    } else {
+        match instance::acquire(&args.paths) {
+            Ok(None) => return,
+            Ok(instance) => instance,
+            Err(_) => None,
+        }
+    };
+    let (lock, listener) = instance.unzip();
+
     let viewport = ViewportBuilder {
         resizable: Some(false),
-        inner_size: Some(Vec2::new(400.0, 500.0)),
+        inner_size: Some(Vec2::new(400.0, 640.0)),
         maximize_button: Some(false),
         ..Default::default()
     };
@@ -29,6 +97,7 @@ async fn run() {
         viewport,
         ..NativeOptions::default()
     };
-    eframe::run_native("Conv", option, Box::new(|cc| Conv::new(cc)))
+    eframe::run_native("Conv", option, Box::new(|cc| Conv::new(cc, args, listener)))
         .unwrap();
+    drop(lock);
 }
@@ -0,0 +1,130 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::whisper::Transcript;
+
+/// frames per second as a fraction, 30000/1001 for 29.97
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRate {
+    pub num: u32,
+    pub den: u32,
+}
+
+impl FrameRate {
+    /// the whole frames a timecode counts per second, 30 for 29.97
+    pub fn nominal(&self) -> u32 {
+        self.num.div_ceil(self.den)
+    }
+
+    /// the frame nearest to a time in centiseconds
+    pub fn frame(&self, time: i64) -> i64 {
+        let (num, den) = (self.num as i64, 100 * self.den as i64);
+        (time.max(0) * num + den / 2) / den
+    }
+
+    // FCPXML rational time of a frame count, 1001/30000s for one frame at 29.97
+    fn rational(&self, frames: i64) -> String {
+        match self.den {
+            1 => format!("{}/{}s", frames, self.num),
+            den => format!("{}/{}s", frames * den as i64, self.num),
+        }
+    }
+
+    /// non-drop frame HH:MM:SS:FF, at 29.97 it runs behind the clock by 3.6s an hour like any NDF timecode
+    pub fn timecode(&self, time: i64) -> String {
+        let nominal = self.nominal() as i64;
+        let frame = self.frame(time);
+        let seconds = frame / nominal;
+        format!("{:02}:{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60, frame % nominal)
+    }
+}
+
+// 25, 29.97, 23.976 or 30000/1001
+impl FromStr for FrameRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("无效的帧率 \"{}\"，应为 25、29.97 或 30000/1001", s);
+        let rate = match s.split_once('/') {
+            Some((num, den)) => Self { num: num.trim().parse().map_err(|_| invalid())?, den: den.trim().parse().map_err(|_| invalid())? },
+            None => match s.trim() {
+                "23.976" | "23.98" => Self { num: 24000, den: 1001 },
+                "29.97" => Self { num: 30000, den: 1001 },
+                "47.952" => Self { num: 48000, den: 1001 },
+                "59.94" => Self { num: 60000, den: 1001 },
+                fps => Self { num: fps.parse().map_err(|_| invalid())?, den: 1 },
+            },
+        };
+        if rate.num == 0 || rate.den == 0 {
+            return Err(invalid());
+        }
+        Ok(rate)
+    }
+}
+
+impl Display for FrameRate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.den {
+            1 => write!(f, "{}", self.num),
+            den => write!(f, "{:.3}", self.num as f64 / den as f64),
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+impl Transcript {
+    /// a project with one gap as long as the transcript carrying a marker per segment,
+    /// Final Cut Pro and DaVinci Resolve import it as timeline markers
+    pub fn to_fcpxml_markers(&self, fps: FrameRate) -> String {
+        let end = self.utterances.iter().map(|u| fps.frame(u.end) + 1).max().unwrap_or(1);
+        let markers: String = self
+            .utterances
+            .iter()
+            .map(|u| {
+                let start = fps.frame(u.start);
+                let duration = (fps.frame(u.end) - start).max(1);
+                format!(
+                    "                            <marker start=\"{}\" duration=\"{}\" value=\"{}\"/>\n",
+                    fps.rational(start),
+                    fps.rational(duration),
+                    escape(u.text.trim())
+                )
+            })
+            .collect();
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE fcpxml>
+<fcpxml version="1.9">
+    <resources>
+        <format id="r1" frameDuration="{frame}"/>
+    </resources>
+    <library>
+        <event name="conv">
+            <project name="conv">
+                <sequence format="r1" duration="{duration}" tcStart="0s" tcFormat="NDF">
+                    <spine>
+                        <gap name="Gap" offset="0s" start="0s" duration="{duration}">
+{markers}                        </gap>
+                    </spine>
+                </sequence>
+            </project>
+        </event>
+    </library>
+</fcpxml>
+"#,
+            frame = fps.rational(1),
+            duration = fps.rational(end),
+        )
+    }
+
+    /// Start,End,Text with non-drop frame timecodes, for editors that take markers from a spreadsheet
+    pub fn to_marker_csv(&self, fps: FrameRate) -> String {
+        let quote = |text: &str| format!("\"{}\"", text.trim().replace('"', "\"\""));
+        self.utterances.iter().fold(String::from("Start,End,Text\n"), |csv, u| {
+            csv + &format!("{},{},{}\n", fps.timecode(u.start), fps.timecode(u.end), quote(&u.text))
+        })
+    }
+}
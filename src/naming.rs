@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use clap::ValueEnum;
+
+use crate::config::Language;
+
+/// (name, template) for --naming, what media servers look for next to the video
+pub const PRESETS: [(&str, &str); 3] = [
+    ("plain", "{stem}.{ext}"),
+    // Jellyfin, Plex, Emby and Kodi all take Movie.en.srt as English subtitles of Movie.mkv
+    ("media-server", "{stem}.{lang}.{ext}"),
+    ("subs-dir", "Subs/{stem}.{lang}.{ext}"),
+];
+
+/// how a subtitle is named after its audio, {stem}.{lang}.{ext} turns Movie.mkv into Movie.en.srt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Naming(String);
+
+impl Default for Naming {
+    fn default() -> Self {
+        Self(PRESETS[0].1.to_string())
+    }
+}
+
+impl Naming {
+    pub fn uses_lang(&self) -> bool {
+        self.0.contains("{lang}")
+    }
+
+    /// the path the subtitle writers take, they swap its extension for the format's like they do with the audio itself.
+    /// an unknown language is written as und, the ISO 639 code for undetermined
+    pub fn base(&self, audio: &Path, lang: Option<Language>) -> PathBuf {
+        let stem = audio.file_stem().unwrap_or_default().to_string_lossy();
+        let ext = audio.extension().unwrap_or_default().to_string_lossy();
+        let lang = lang.filter(|lang| *lang != Language::Auto).map_or("und", <&str>::from);
+        let name = self.0.trim_end_matches("{ext}").replace("{stem}", &stem).replace("{lang}", lang);
+        audio.with_file_name(format!("{}{}", name, ext))
+    }
+
+    /// every base a subtitle of this audio could already have, one per language when it's only known after transcribing
+    pub fn bases(&self, audio: &Path, lang: Option<Language>) -> Vec<PathBuf> {
+        match lang.filter(|lang| *lang != Language::Auto) {
+            None if self.uses_lang() => Language::value_variants().iter().filter(|lang| **lang != Language::Auto).map(|lang| self.base(audio, Some(*lang))).collect(),
+            lang => vec![self.base(audio, lang)],
+        }
+    }
+}
+
+// a preset name or a template ending in .{ext}
+impl FromStr for Naming {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, template)) = PRESETS.iter().find(|(name, _)| *name == s) {
+            return Ok(Self(template.to_string()));
+        }
+        let presets = PRESETS.iter().map(|(name, _)| *name).collect::<Vec<_>>().join("、");
+        let invalid = |reason: &str| format!("无效的命名模板 \"{}\": {}，可用 {{stem}} {{lang}} {{ext}} 或预设 {}", s, reason, presets);
+        let Some(name) = s.strip_suffix(".{ext}") else {
+            return Err(invalid("应以 .{ext} 结尾"));
+        };
+        if !name.contains("{stem}") {
+            return Err(invalid("缺少 {stem}，不同的音频会写到同一个文件"));
+        }
+        if name.replace("{stem}", "").replace("{lang}", "").contains(['{', '}']) {
+            return Err(invalid("有未知的占位符"));
+        }
+        let path = Path::new(name);
+        if path.has_root() || path.components().any(|c| !matches!(c, std::path::Component::Normal(_))) {
+            return Err(invalid("只能是音频所在目录下的相对路径"));
+        }
+        Ok(Self(s.to_string()))
+    }
+}
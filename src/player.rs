@@ -0,0 +1,67 @@
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::Instant;
+
+use crate::utils::ffmpeg_tool;
+
+#[derive(Default)]
+pub struct Player {
+    child: Option<Child>,
+    started: Option<Instant>,
+    offset: i64,
+}
+
+impl Player {
+    // ffplay -nodisp -autoexit -ss 12.34 input.mp3
+    pub fn play<P: AsRef<Path>>(&mut self, audio: P, from: i64) -> std::io::Result<()> {
+        self.stop();
+        let child = Command::new(ffmpeg_tool("ffplay"))
+            .args([
+                "-nodisp",
+                "-autoexit",
+                "-loglevel",
+                "error",
+                "-ss",
+                &format!("{}.{:02}", from / 100, from % 100),
+            ])
+            .arg(audio.as_ref())
+            .stdin(Stdio::null())
+            .spawn()?;
+        self.child = Some(child);
+        self.started = Some(Instant::now());
+        self.offset = from;
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        self.started = None;
+    }
+
+    pub fn is_playing(&mut self) -> bool {
+        match self.child.as_mut().map(|c| c.try_wait()) {
+            Some(Ok(None)) => true,
+            Some(_) => {
+                self.stop();
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn position(&mut self) -> Option<i64> {
+        if !self.is_playing() {
+            return None;
+        }
+        self.started.map(|st| self.offset + st.elapsed().as_millis() as i64 / 10)
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,41 @@
+use crate::whisper::Utterance;
+
+pub struct Preview {
+    pub cues: Vec<Utterance>,
+    max_end: Vec<i64>,
+    pub followed: Option<usize>,
+    pub selected: Option<usize>,
+    pub scroll_to: Option<usize>,
+}
+
+impl Preview {
+    pub fn new(mut cues: Vec<Utterance>) -> Self {
+        cues.sort_by_key(|c| c.start);
+        let max_end = cues
+            .iter()
+            .scan(i64::MIN, |max, c| {
+                *max = (*max).max(c.end);
+                Some(*max)
+            })
+            .collect();
+        Self { cues, max_end, followed: None, selected: None, scroll_to: None }
+    }
+
+    pub fn select_at(&mut self, pos: i64) {
+        if let Some(&i) = self.active(pos).last() {
+            self.selected = Some(i);
+            self.scroll_to = Some(i);
+        }
+    }
+
+    pub fn active(&self, pos: i64) -> Vec<usize> {
+        let upper = self.cues.partition_point(|c| c.start <= pos);
+        let mut active = (0..upper)
+            .rev()
+            .take_while(|&i| self.max_end[i] > pos)
+            .filter(|&i| self.cues[i].end > pos)
+            .collect::<Vec<_>>();
+        active.reverse();
+        active
+    }
+}
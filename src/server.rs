@@ -0,0 +1,971 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::body::Body;
+use axum::extract::multipart::MultipartError;
+use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{DefaultBodyLimit, Multipart, Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{info, warn};
+
+use crate::config::{Language, Model};
+use crate::event::{AppEvent, Events};
+use crate::job::{CancelToken, Job, JobHandle, JobKind, JobStatus};
+use crate::webhook::{Payload, Webhook};
+use crate::whisper::{Format, TranscribeOptions, Transcript};
+use crate::worker::WhisperWorker;
+
+// finished jobs are dropped this long after they were submitted unless deleted earlier
+const RETAIN: Duration = Duration::from_secs(24 * 60 * 60);
+// sockets are pinged this often and closed when nothing came back for three pings
+const PING: Duration = Duration::from_secs(20);
+// events a socket may fall behind by before it misses some
+const BACKLOG: usize = 1024;
+
+pub struct ServerConfig {
+    pub lang: Language,
+    pub model: Model,
+    pub max_upload: usize,
+    pub queue: usize,
+    pub token: Option<String>,
+    // for jobs that don't name their own
+    pub webhook: Option<Webhook>,
+    pub job_webhooks: JobWebhooks,
+}
+
+/// which addresses the `webhook` form field of /transcribe may name. a job's own webhook is signed
+/// with the job's `webhook_secret` field or not at all, never with the server's secret
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum JobWebhooks {
+    #[default]
+    Any,
+    Hosts(Vec<String>),
+    Off,
+}
+
+impl JobWebhooks {
+    fn webhook(&self, url: &str, secret: Option<String>) -> Result<Webhook, String> {
+        if *self == Self::Off {
+            return Err("per-job webhooks are disabled".to_string());
+        }
+        let webhook = Webhook::new(url, secret)?;
+        if let Self::Hosts(hosts) = self {
+            let host = webhook.host().unwrap_or_default();
+            if !hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(host)) {
+                return Err(format!("host {} is not allowed", host));
+            }
+        }
+        Ok(webhook)
+    }
+}
+
+// filled in by run once the job succeeds, taken by whichever request serves the result
+type Slot = Arc<Mutex<Option<Transcript>>>;
+
+struct Entry {
+    handle: JobHandle,
+    cancel: CancelToken,
+    progress: Arc<AtomicU8>,
+    transcript: Slot,
+    // taken when the transcription stops sending events, so subscribers see the stream close
+    events: Arc<Mutex<Option<broadcast::Sender<AppEvent>>>>,
+    submitted: Instant,
+}
+
+struct Queued {
+    job: Job,
+    audio: PathBuf,
+    // the uploaded file name, for the webhook
+    input: PathBuf,
+    webhook: Option<Webhook>,
+    lang: Language,
+    model: Model,
+    options: TranscribeOptions,
+    transcript: Slot,
+}
+
+struct Server {
+    jobs: Mutex<HashMap<u64, Entry>>,
+    queue: mpsc::Sender<Queued>,
+    default_lang: Language,
+    default_model: Model,
+    max_upload: usize,
+    token: Option<String>,
+    webhook: Option<Webhook>,
+    job_webhooks: JobWebhooks,
+}
+
+type Shared = Arc<Server>;
+
+// POST /transcribe, GET /jobs/{id}, GET /jobs/{id}/result?format=srt, DELETE /jobs/{id}
+// OpenAI's POST /v1/audio/transcriptions and GET /ws/transcribe
+pub fn router(worker: Arc<WhisperWorker>, config: ServerConfig) -> Router {
+    let (queue, receiver) = mpsc::channel(config.queue.max(1));
+    tokio::spawn(run(worker, receiver));
+    let server = Arc::new(Server { jobs: Default::default(), queue, default_lang: config.lang, default_model: config.model, max_upload: config.max_upload,
+        token: config.token,
+        webhook: config.webhook,
+        job_webhooks: config.job_webhooks,
+    });
+    Router::new()
+        .route("/transcribe", post(submit))
+        .route("/v1/audio/transcriptions", post(openai))
+        .route("/ws/transcribe", get(socket))
+        .route("/jobs/:id", get(status).delete(cancel))
+        .route("/jobs/:id/result", get(result))
+        .layer(DefaultBodyLimit::max(config.max_upload))
+        .layer(middleware::from_fn_with_state(server.clone(), authorize))
+        .with_state(server)
+}
+
+// one file at a time, in the order they were submitted, the model is only reloaded when a job asks for another one
+async fn run(worker: Arc<WhisperWorker>, mut receiver: mpsc::Receiver<Queued>) {
+    while let Some(Queued { job, audio, input, webhook, lang, model, options, transcript }) = receiver.recv().await {
+        let (id, started) = (job.id(), Instant::now());
+        let notify = |result: Result<Vec<PathBuf>, String>| {
+            if let Some(ref webhook) = webhook {
+                webhook.notify(&Payload::new(id, input.clone(), started, result));
+            }
+        };
+        if options.cancel.is_cancelled() {
+            notify(Err("已取消".to_string()));
+            job.fail("已取消");
+            let _ = std::fs::remove_file(&audio);
+            continue;
+        }
+        job.running(Some(audio.clone()));
+        let result = worker.transcribe(audio.clone(), lang, model, options).await;
+        let _ = std::fs::remove_file(&audio);
+        notify(result.as_ref().map(|_| vec![]).map_err(ToString::to_string));
+        match result {
+            Ok(t) => {
+                *transcript.lock().unwrap() = Some(t);
+                job.succeed(audio);
+            }
+            Err(e) => {
+                warn!("transcription failed: {}", e);
+                job.fail(e);
+            }
+        }
+    }
+}
+
+// a bearer token, or ?token= for browsers that can't set headers on a WebSocket
+async fn authorize(State(server): State<Shared>, request: Request, next: Next) -> Response {
+    if let Some(ref token) = server.token {
+        let bearer = request.headers().get(header::AUTHORIZATION).and_then(|value| value.to_str().ok()).and_then(|value| value.strip_prefix("Bearer "));
+        let query = request.uri().query().into_iter().flat_map(|query| query.split('&')).find_map(|pair| pair.strip_prefix("token="));
+        if bearer != Some(token.as_str()) && query != Some(token.as_str()) {
+            return error(StatusCode::UNAUTHORIZED, "missing or wrong bearer token");
+        }
+    }
+    next.run(request).await
+}
+
+fn error(status: StatusCode, message: impl std::fmt::Display) -> Response {
+    (status, Json(json!({ "error": message.to_string() }))).into_response()
+}
+
+// multipart with the audio in `file`, optional `lang`, `translate` and `word_timestamps`
+async fn submit(State(server): State<Shared>, multipart: Multipart) -> Response {
+    let (audio, fields) = match read_form(multipart).await {
+        Ok(form) => form,
+        Err((status, message)) => return error(status, message),
+    };
+    let mut options = TranscribeOptions::default();
+    let lang = match fields.get("lang") {
+        None => server.default_lang,
+        Some(value) => match Language::from_str(value, true) {
+            Ok(lang) => lang,
+            Err(_) => {
+                let _ = tokio::fs::remove_file(audio).await;
+                return error(StatusCode::BAD_REQUEST, format!("unknown lang {}", value));
+            }
+        },
+    };
+    options.translate = fields.get("translate").is_some_and(|value| value == "true");
+    options.word_timestamps = fields.get("word_timestamps").is_some_and(|value| value == "true");
+    let webhook = match fields.get("webhook") {
+        None => server.webhook.clone(),
+        Some(url) => match server.job_webhooks.webhook(url, fields.get("webhook_secret").cloned()) {
+            Ok(webhook) => Some(webhook),
+            Err(e) => {
+                let _ = tokio::fs::remove_file(audio).await;
+                return error(StatusCode::BAD_REQUEST, format!("invalid webhook {}: {}", url, e));
+            }
+        },
+    };
+    let input = upload_name(&fields, &audio);
+    let id = match register(&server, audio.clone(), input, lang, options, webhook) {
+        Ok((id, _)) => id,
+        Err((status, message)) => return error(status, message),
+    };
+    info!("job {} queued: {}", id, audio.display());
+    (StatusCode::ACCEPTED, Json(json!({ "id": id }))).into_response()
+}
+
+// queues a job on the default model that can be polled and followed over a socket, the receiver is subscribed before it starts
+fn register(server: &Server, audio: PathBuf, input: PathBuf, lang: Language, mut options: TranscribeOptions, webhook: Option<Webhook>) -> Result<(u64, broadcast::Receiver<AppEvent>), (StatusCode, &'static str)> {
+    let progress = Arc::new(AtomicU8::new(0));
+    let (sender, subscriber) = broadcast::channel(BACKLOG);
+    let events = Arc::new(Mutex::new(Some(sender)));
+    let (pipeline, mut receiver) = Events::channel();
+    let (counter, forward) = (progress.clone(), events.clone());
+    tokio::spawn(async move {
+        while let Some(event) = receiver.recv().await {
+            if let AppEvent::TranscribeProgress { percent } = event {
+                counter.store(percent, Ordering::Relaxed);
+            }
+            if let Some(ref sender) = *forward.lock().unwrap() {
+                let _ = sender.send(event);
+            }
+        }
+        forward.lock().unwrap().take();
+    });
+    options.events = pipeline;
+    let cancel = options.cancel.clone();
+    let (handle, transcript) = enqueue(server, audio, input, lang, server.default_model, options, webhook)?;
+
+    let id = handle.id;
+    let mut jobs = server.jobs.lock().unwrap();
+    jobs.retain(|_, entry| !(entry.handle.is_finished() && entry.submitted.elapsed() > RETAIN));
+    jobs.insert(id, Entry { handle, cancel, progress, transcript, events, submitted: Instant::now() });
+    Ok((id, subscriber))
+}
+
+// removes the audio when the queue can't take it
+fn enqueue(server: &Server, audio: PathBuf, input: PathBuf, lang: Language, model: Model, options: TranscribeOptions, webhook: Option<Webhook>) -> Result<(JobHandle, Slot), (StatusCode, &'static str)> {
+    let (job, handle) = Job::new(JobKind::Whisper);
+    let transcript = Arc::new(Mutex::new(None));
+    let queued = Queued { job, audio: audio.clone(), input, webhook, lang, model, options, transcript: transcript.clone() };
+    match server.queue.try_send(queued) {
+        Ok(()) => Ok((handle, transcript)),
+        Err(e) => {
+            let _ = std::fs::remove_file(&audio);
+            Err(match e {
+                mpsc::error::TrySendError::Full(_) => (StatusCode::SERVICE_UNAVAILABLE, "queue is full"),
+                mpsc::error::TrySendError::Closed(_) => (StatusCode::SERVICE_UNAVAILABLE, "worker stopped"),
+            })
+        }
+    }
+}
+
+// the uploaded `file` and the other fields as trimmed text, the file is removed again when the form is invalid
+async fn read_form(mut multipart: Multipart) -> Result<(PathBuf, HashMap<String, String>), (StatusCode, String)> {
+    let mut audio = None;
+    let result = read_fields(&mut multipart, &mut audio).await;
+    match (result, audio) {
+        (Ok(fields), Some(audio)) => Ok((audio, fields)),
+        (Ok(_), None) => Err((StatusCode::BAD_REQUEST, "missing file field".to_string())),
+        (Err(e), audio) => {
+            if let Some(audio) = audio {
+                let _ = tokio::fs::remove_file(audio).await;
+            }
+            Err(e)
+        }
+    }
+}
+
+// the name the client gave the file, the temporary path without one
+fn upload_name(fields: &HashMap<String, String>, audio: &std::path::Path) -> PathBuf {
+    fields.get("filename").map_or_else(|| audio.to_path_buf(), PathBuf::from)
+}
+
+async fn read_fields(multipart: &mut Multipart, audio: &mut Option<PathBuf>) -> Result<HashMap<String, String>, (StatusCode, String)> {
+    let multipart_error = |e: MultipartError| (e.status(), e.body_text());
+    let io_error = |e: std::io::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    let mut fields = HashMap::new();
+    while let Some(mut field) = multipart.next_field().await.map_err(multipart_error)? {
+        let name = field.name().unwrap_or_default().to_string();
+        match name.as_str() {
+            "file" if audio.is_some() => return Err((StatusCode::BAD_REQUEST, "only one file per request".to_string())),
+            "file" => {
+                // the extension lets 16kHz mono wav skip ffmpeg
+                let extension = field.file_name().and_then(|name| std::path::Path::new(name).extension()).and_then(|e| e.to_str()).unwrap_or("bin").to_string();
+                if let Some(name) = field.file_name() {
+                    fields.insert("filename".to_string(), name.to_string());
+                }
+                let path = audio.insert(std::env::temp_dir().join(format!("conv-serve-{}.{}", uuid::Uuid::new_v4(), extension)));
+                let mut file = tokio::fs::File::create(&path).await.map_err(io_error)?;
+                while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+                    file.write_all(&chunk).await.map_err(io_error)?;
+                }
+                file.flush().await.map_err(io_error)?;
+            }
+            _ => {
+                let value = field.text().await.map_err(multipart_error)?;
+                fields.insert(name, value.trim().to_string());
+            }
+        }
+    }
+    Ok(fields)
+}
+
+async fn status(State(server): State<Shared>, Path(id): Path<u64>) -> Response {
+    let jobs = server.jobs.lock().unwrap();
+    let Some(entry) = jobs.get(&id) else {
+        return error(StatusCode::NOT_FOUND, "no such job");
+    };
+    let (status, error) = match entry.handle.status() {
+        JobStatus::Failed(_) if entry.cancel.is_cancelled() => ("cancelled", None),
+        JobStatus::Queued => ("queued", None),
+        JobStatus::Running { .. } => ("running", None),
+        JobStatus::Succeeded(_) => ("succeeded", None),
+        JobStatus::Failed(reason) => ("failed", Some(reason)),
+    };
+    let progress = match status {
+        "succeeded" => 100,
+        _ => entry.progress.load(Ordering::Relaxed),
+    };
+    Json(json!({ "id": id, "status": status, "progress": progress, "error": error })).into_response()
+}
+
+#[derive(Deserialize)]
+struct ResultQuery {
+    format: Option<String>,
+}
+
+async fn result(State(server): State<Shared>, Path(id): Path<u64>, Query(query): Query<ResultQuery>) -> Response {
+    let format = match query.format.as_deref().map(|format| Format::from_str(format, true)) {
+        None => Format::Srt,
+        Some(Ok(format)) => format,
+        Some(Err(_)) => return error(StatusCode::BAD_REQUEST, format!("unknown format {}", query.format.unwrap_or_default())),
+    };
+    let transcript = match server.jobs.lock().unwrap().get(&id) {
+        Some(entry) => entry.transcript.clone(),
+        None => return error(StatusCode::NOT_FOUND, "no such job"),
+    };
+    let mut body = vec![];
+    match *transcript.lock().unwrap() {
+        Some(ref t) => {
+            if let Err(e) = t.write_to(&mut body, format) {
+                return error(StatusCode::INTERNAL_SERVER_ERROR, e);
+            }
+        }
+        None => return error(StatusCode::CONFLICT, "job has not succeeded"),
+    }
+    let content_type = match format {
+        Format::Srt | Format::SrtCrlf => "application/x-subrip; charset=utf-8",
+        Format::Vtt | Format::VttPlain | Format::VttVoice => "text/vtt; charset=utf-8",
+        Format::Json => "application/json",
+        Format::AssKaraoke => "text/x-ssa; charset=utf-8",
+        Format::Lrc | Format::LrcMillis | Format::Txt | Format::Chapters => "text/plain; charset=utf-8",
+    };
+    ([(header::CONTENT_TYPE, content_type)], Body::from(body)).into_response()
+}
+
+// cancels a queued or running job, a finished one is forgotten
+async fn cancel(State(server): State<Shared>, Path(id): Path<u64>) -> Response {
+    let mut jobs = server.jobs.lock().unwrap();
+    match jobs.get(&id) {
+        Some(entry) if entry.handle.is_finished() => {
+            jobs.remove(&id);
+        }
+        Some(entry) => {
+            info!("job {} cancelled", id);
+            entry.cancel.cancel();
+        }
+        None => return error(StatusCode::NOT_FOUND, "no such job"),
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[derive(Deserialize)]
+struct SocketQuery {
+    job: Option<u64>,
+    lang: Option<String>,
+    #[serde(default)]
+    translate: bool,
+    #[serde(default)]
+    word_timestamps: bool,
+}
+
+// follows a job from /transcribe with ?job=id, or takes 16kHz mono s16le pcm as binary messages until a text message `end`.
+// segments and progress are sent as they come in the --progress json shape followed by a summary, closing the socket early cancels the job
+async fn socket(State(server): State<Shared>, Query(query): Query<SocketQuery>, upgrade: WebSocketUpgrade) -> Response {
+    let lang = match query.lang.as_deref().map(|lang| Language::from_str(lang, true)) {
+        None => server.default_lang,
+        Some(Ok(lang)) => lang,
+        Some(Err(_)) => return error(StatusCode::BAD_REQUEST, format!("unknown lang {}", query.lang.unwrap_or_default())),
+    };
+    if query.job.is_some_and(|id| !server.jobs.lock().unwrap().contains_key(&id)) {
+        return error(StatusCode::NOT_FOUND, "no such job");
+    }
+    upgrade.on_upgrade(move |mut socket| async move {
+        let mut keepalive = Keepalive::new();
+        let (id, events) = match query.job {
+            Some(id) => (id, subscribe(&server, id)),
+            None => {
+                let Some(audio) = receive_pcm(&mut socket, &mut keepalive, server.max_upload).await else {
+                    return;
+                };
+                let options = TranscribeOptions { translate: query.translate, word_timestamps: query.word_timestamps, ..Default::default() };
+                match register(&server, audio.clone(), audio.clone(), lang, options, server.webhook.clone()) {
+                    Ok((id, events)) => {
+                        info!("job {} queued from a socket: {}", id, audio.display());
+                        (id, Some(events))
+                    }
+                    Err((_, message)) => return close(&mut socket, 1013, message).await,
+                }
+            }
+        };
+        follow(socket, keepalive, &server, id, events).await;
+    })
+}
+
+// None once the job stopped sending events
+fn subscribe(server: &Server, id: u64) -> Option<broadcast::Receiver<AppEvent>> {
+    let jobs = server.jobs.lock().unwrap();
+    let events = jobs.get(&id)?.events.lock().unwrap();
+    events.as_ref().map(broadcast::Sender::subscribe)
+}
+
+struct Keepalive {
+    ping: tokio::time::Interval,
+    seen: Instant,
+}
+
+impl Keepalive {
+    fn new() -> Self {
+        Self { ping: tokio::time::interval_at(tokio::time::Instant::now() + PING, PING), seen: Instant::now() }
+    }
+
+    // None when the client closed the socket or it broke
+    fn received(&mut self, message: Option<Result<Message, axum::Error>>) -> Option<Message> {
+        match message {
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => None,
+            Some(Ok(message)) => {
+                self.seen = Instant::now();
+                Some(message)
+            }
+        }
+    }
+
+    // false when the client stopped answering
+    async fn alive(&mut self, socket: &mut WebSocket) -> bool {
+        self.seen.elapsed() < PING * 3 && socket.send(Message::Ping(vec![])).await.is_ok()
+    }
+}
+
+async fn close(socket: &mut WebSocket, code: u16, reason: &str) {
+    let frame = CloseFrame { code, reason: reason.to_string().into() };
+    let _ = socket.send(Message::Close(Some(frame))).await;
+}
+
+async fn send(socket: &mut WebSocket, value: serde_json::Value) -> bool {
+    socket.send(Message::Text(value.to_string())).await.is_ok()
+}
+
+// written to a wav next to the uploads so it skips ffmpeg, None when the client left or sent too much
+async fn receive_pcm(socket: &mut WebSocket, keepalive: &mut Keepalive, limit: usize) -> Option<PathBuf> {
+    let limit = limit.min(u32::MAX as usize - 36);
+    let mut pcm = vec![];
+    loop {
+        tokio::select! {
+            message = socket.recv() => match keepalive.received(message)? {
+                Message::Binary(data) if pcm.len() + data.len() > limit => {
+                    close(socket, 1009, "audio is larger than --max-upload").await;
+                    return None;
+                }
+                Message::Binary(data) => pcm.extend_from_slice(&data),
+                Message::Text(text) if text.trim() == "end" => break,
+                _ => {}
+            },
+            _ = keepalive.ping.tick() => if !keepalive.alive(socket).await {
+                return None;
+            },
+        }
+    }
+    pcm.truncate(pcm.len() / 2 * 2);
+    let path = std::env::temp_dir().join(format!("conv-serve-{}.wav", uuid::Uuid::new_v4()));
+    if let Err(e) = tokio::fs::write(&path, wav(pcm)).await {
+        warn!("failed to write {}: {}", path.display(), e);
+        close(socket, 1011, "failed to store the audio").await;
+        return None;
+    }
+    Some(path)
+}
+
+fn wav(pcm: Vec<u8>) -> Vec<u8> {
+    let len = pcm.len() as u32;
+    let mut wav = Vec::with_capacity(44 + pcm.len());
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    // pcm, mono, 16000 Hz, 32000 bytes per second, 2 bytes per frame, 16 bits
+    for field in [16u32.to_le_bytes().as_slice(), &1u16.to_le_bytes(), &1u16.to_le_bytes(), &16000u32.to_le_bytes(), &32000u32.to_le_bytes(), &2u16.to_le_bytes(), &16u16.to_le_bytes()] {
+        wav.extend_from_slice(field);
+    }
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&len.to_le_bytes());
+    wav.extend(pcm);
+    wav
+}
+
+async fn follow(mut socket: WebSocket, mut keepalive: Keepalive, server: &Server, id: u64, events: Option<broadcast::Receiver<AppEvent>>) {
+    let entry = server.jobs.lock().unwrap().get(&id).map(|entry| (entry.handle.clone(), entry.cancel.clone(), entry.transcript.clone()));
+    let Some((mut handle, cancel, transcript)) = entry else {
+        return close(&mut socket, 1000, "no such job").await;
+    };
+    let gone = |cancel: &CancelToken| {
+        if !handle.is_finished() {
+            info!("job {} cancelled, its socket closed", id);
+            cancel.cancel();
+        }
+    };
+    let live = events.is_some();
+    if let Some(mut events) = events {
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    let event = match event {
+                        // a newer one is already waiting while the socket is behind
+                        Ok(AppEvent::TranscribeProgress { .. }) if !events.is_empty() => continue,
+                        Ok(event) => serde_json::to_value(event).unwrap(),
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!("socket of job {} fell behind by {} events", id, skipped);
+                            json!({ "event": "lagged", "skipped": skipped })
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    if !send(&mut socket, event).await {
+                        return gone(&cancel);
+                    }
+                }
+                message = socket.recv() => if keepalive.received(message).is_none() {
+                    return gone(&cancel);
+                },
+                _ = keepalive.ping.tick() => if !keepalive.alive(&mut socket).await {
+                    return gone(&cancel);
+                },
+            }
+        }
+    }
+
+    // the events end just before the job does
+    let status = match handle.status.wait_for(JobStatus::is_finished).await {
+        Ok(status) => status.clone(),
+        Err(_) => JobStatus::Failed("worker stopped".to_string()),
+    };
+    let summary = match status {
+        JobStatus::Failed(_) if cancel.is_cancelled() => json!({ "event": "summary", "id": id, "status": "cancelled" }),
+        JobStatus::Failed(reason) => json!({ "event": "summary", "id": id, "status": "failed", "error": reason }),
+        _ => {
+            let Some(t) = transcript.lock().unwrap().clone() else {
+                return close(&mut socket, 1011, "job has not succeeded").await;
+            };
+            // a job that was done before the socket came gets its segments in one go
+            if !live {
+                for u in &t.utterances {
+                    if !send(&mut socket, serde_json::to_value(AppEvent::Segment(u.clone())).unwrap()).await {
+                        return;
+                    }
+                }
+            }
+            json!({
+                "event": "summary",
+                "id": id,
+                "status": "succeeded",
+                "segments": t.segment_count(),
+                "audio_seconds": t.timings.audio.as_secs_f64(),
+                "processing_time": t.processing_time.as_secs_f64(),
+                "throughput": t.throughput(),
+            })
+        }
+    };
+    if send(&mut socket, summary).await {
+        close(&mut socket, 1000, "done").await;
+    }
+}
+
+fn openai_error(status: StatusCode, message: impl std::fmt::Display) -> Response {
+    let kind = match status.is_server_error() {
+        true => "server_error",
+        false => "invalid_request_error",
+    };
+    (status, Json(json!({ "error": { "message": message.to_string(), "type": kind, "param": null, "code": null } }))).into_response()
+}
+
+// whisper-1 is the model loaded at startup, other names go to the closest model that is already downloaded
+fn openai_model(name: &str, default: Model) -> Result<Model, String> {
+    let name = name.to_lowercase();
+    if name.is_empty() || name == "whisper-1" {
+        return Ok(default);
+    }
+    let local = name.strip_prefix("whisper-").unwrap_or(&name);
+    let model = match Model::from_str(local, true) {
+        Ok(model) => model,
+        // large-v2, large-v3-turbo and the like
+        Err(_) => match local.split(['-', '.', '_']).next() {
+            Some("tiny") => Model::Tiny,
+            Some("base") => Model::Base,
+            Some("small") => Model::Small,
+            Some("medium") => Model::Medium,
+            Some("large") => Model::Large,
+            _ => return Err(format!("unknown model {}, use whisper-1 for the server's model ({}) or one of tiny, base, small, medium, large", name, default)),
+        },
+    };
+    if model != default && !model.get_path().exists() {
+        return Err(format!("model {} is not downloaded on this server, use whisper-1 for the server's model ({})", model, default));
+    }
+    Ok(model)
+}
+
+// cancels the job when the client hangs up before it is done
+struct CancelOnDrop(CancelToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+// multipart with `file`, `model`, `language`, `prompt` and `response_format`, answered once the file is transcribed
+async fn openai(State(server): State<Shared>, multipart: Multipart) -> Response {
+    let (audio, fields) = match read_form(multipart).await {
+        Ok(form) => form,
+        Err((status, message)) => return openai_error(status, message),
+    };
+    let request = async {
+        let model = openai_model(fields.get("model").map_or("", String::as_str), server.default_model)?;
+        let lang = match fields.get("language").filter(|value| !value.is_empty()) {
+            None => server.default_lang,
+            Some(value) => Language::from_str(value, true).map_err(|_| format!("unknown language {}, use an ISO-639-1 code", value))?,
+        };
+        let format = fields.get("response_format").map_or("json", String::as_str).to_string();
+        if !["json", "text", "srt", "verbose_json", "vtt"].contains(&format.as_str()) {
+            return Err(format!("unknown response_format {}, use json, text, srt, verbose_json or vtt", format));
+        }
+        Ok((model, lang, format))
+    };
+    let (model, lang, format) = match request.await {
+        Ok(request) => request,
+        Err(message) => {
+            let _ = tokio::fs::remove_file(audio).await;
+            return openai_error(StatusCode::BAD_REQUEST, message);
+        }
+    };
+    let options = TranscribeOptions { glossary: fields.get("prompt").filter(|prompt| !prompt.is_empty()).cloned().into_iter().collect(), ..Default::default() };
+    let _guard = CancelOnDrop(options.cancel.clone());
+    let input = upload_name(&fields, &audio);
+    let (mut handle, transcript) = match enqueue(&server, audio.clone(), input, lang, model, options, server.webhook.clone()) {
+        Ok(queued) => queued,
+        Err((status, message)) => return openai_error(status, message),
+    };
+    info!("job {} queued from the OpenAI endpoint: {}", handle.id, audio.display());
+    let status = match handle.status.wait_for(JobStatus::is_finished).await {
+        Ok(status) => status.clone(),
+        Err(_) => JobStatus::Failed("worker stopped".to_string()),
+    };
+    if let JobStatus::Failed(reason) = status {
+        return openai_error(StatusCode::INTERNAL_SERVER_ERROR, reason);
+    }
+    let Some(transcript) = transcript.lock().unwrap().take() else {
+        return openai_error(StatusCode::INTERNAL_SERVER_ERROR, "job has not succeeded");
+    };
+
+    let text = transcript.utterances.iter().map(|u| u.text.as_str()).collect::<String>().trim().to_string();
+    match format.as_str() {
+        "text" => ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], text).into_response(),
+        "srt" => ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], transcript.to_srt()).into_response(),
+        "vtt" => ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], transcript.to_vtt()).into_response(),
+        "verbose_json" => {
+            let language = match lang {
+                Language::Auto => transcript.utterances.iter().find_map(|u| u.lang).map(|lang| <&str>::from(lang).to_string()),
+                lang => Some(<&str>::from(lang).to_string()),
+            };
+            // conv doesn't keep token ids or decoder statistics, they are filled with neutral values for clients that require them
+            let segments: Vec<_> = transcript
+                .utterances
+                .iter()
+                .enumerate()
+                .map(|(id, u)| {
+                    json!({
+                        "id": id,
+                        "seek": 0,
+                        "start": u.start as f64 / 100.0,
+                        "end": u.end as f64 / 100.0,
+                        "text": u.text,
+                        "tokens": [],
+                        "temperature": 0.0,
+                        "avg_logprob": 0.0,
+                        "compression_ratio": 0.0,
+                        "no_speech_prob": 0.0,
+                    })
+                })
+                .collect();
+            Json(json!({
+                "task": "transcribe",
+                "language": language,
+                "duration": transcript.timings.audio.as_secs_f64(),
+                "text": text,
+                "segments": segments,
+            }))
+            .into_response()
+        }
+        _ => Json(json!({ "text": text })).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request as HttpRequest;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+    use tower::ServiceExt;
+
+    use crate::error::ConvError;
+    use ::conv::transcriber::Transcriber;
+    use crate::utils::{read_file, ChannelSelect, Resample};
+    use crate::whisper::{Timings, Utterance};
+
+    use super::*;
+
+    const BOUNDARY: &str = "conv-test-boundary";
+
+    // two english cues over whatever it's given, or with `hold` nothing until the job is cancelled
+    struct Fake {
+        hold: bool,
+    }
+
+    impl Transcriber for Fake {
+        fn set_language(&mut self, _lang: Language) {}
+
+        fn transcribe(&mut self, audio: &[f32], options: &TranscribeOptions) -> Result<Transcript, ConvError> {
+            if self.hold {
+                let started = Instant::now();
+                while !options.cancel.is_cancelled() && started.elapsed() < Duration::from_secs(10) {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                return Err(ConvError::Cancelled);
+            }
+            let end = audio.len() as i64 / 160;
+            let cue = |start, end, text: &str| Utterance { start, end, text: text.to_string(), lang: Some(Language::English), avg_logprob: None, no_speech_prob: None, speaker: None, translation: None };
+            let utterances = vec![cue(0, end / 2, " Hello"), cue(end / 2, end, " world")];
+            for u in &utterances {
+                options.events.send(AppEvent::Segment(u.clone()));
+            }
+            options.events.send(AppEvent::TranscribeProgress { percent: 100 });
+            Ok(Transcript { processing_time: Duration::ZERO, utterances, word_utterances: None, timings: Timings::default() })
+        }
+    }
+
+    fn config(max_upload: usize) -> ServerConfig {
+        ServerConfig { lang: Language::English, model: Model::Base, max_upload, queue: 4, token: None, webhook: None, job_webhooks: JobWebhooks::Any }
+    }
+
+    fn app(hold: bool, config: ServerConfig) -> Router {
+        router(Arc::new(WhisperWorker::with_transcriber(Model::Base, Box::new(Fake { hold }))), config)
+    }
+
+    // a second of silence as 16kHz mono s16le
+    fn second() -> Vec<u8> {
+        vec![0; 32000]
+    }
+
+    fn form(uri: &str, fields: &[(&str, &str)]) -> HttpRequest<Body> {
+        let mut body = vec![];
+        for (name, value) in fields {
+            body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", BOUNDARY, name, value).into_bytes());
+        }
+        body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.wav\"\r\nContent-Type: audio/wav\r\n\r\n", BOUNDARY).into_bytes());
+        body.extend(wav(second()));
+        body.extend(format!("\r\n--{}--\r\n", BOUNDARY).into_bytes());
+        HttpRequest::post(uri).header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY)).body(Body::from(body)).unwrap()
+    }
+
+    async fn call(app: &Router, request: HttpRequest<Body>) -> (StatusCode, Vec<u8>) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        (status, axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec())
+    }
+
+    async fn get_json(app: &Router, uri: &str) -> serde_json::Value {
+        let (_, body) = call(app, HttpRequest::get(uri).body(Body::empty()).unwrap()).await;
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    async fn wait_for(app: &Router, id: u64, status: &str) -> serde_json::Value {
+        for _ in 0..500 {
+            let job = get_json(app, &format!("/jobs/{}", id)).await;
+            if job["status"] == status {
+                return job;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("job {} never became {}", id, status);
+    }
+
+    async fn listen(app: Router) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        addr
+    }
+
+    // the text frames as json up to the close frame, and its code
+    async fn frames<S>(socket: &mut S) -> (Vec<serde_json::Value>, Option<u16>)
+    where
+        S: futures_util::Stream<Item = Result<WsMessage, tokio_tungstenite::tungstenite::Error>> + Unpin,
+    {
+        let mut frames = vec![];
+        while let Some(Ok(message)) = socket.next().await {
+            match message {
+                WsMessage::Text(text) => frames.push(serde_json::from_str(&text).unwrap()),
+                WsMessage::Close(frame) => return (frames, frame.map(|frame| u16::from(frame.code))),
+                _ => {}
+            }
+        }
+        (frames, None)
+    }
+
+    #[test]
+    fn wav_header() {
+        let wav = wav(vec![1, 0, 2, 0, 3, 0]);
+        assert_eq!(wav.len(), 44 + 6);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 36 + 6);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u16::from_le_bytes(wav[22..24].try_into().unwrap()), 1);
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 16000);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 6);
+        let path = std::env::temp_dir().join(format!("conv-{}.wav", uuid::Uuid::new_v4()));
+        std::fs::write(&path, &wav).unwrap();
+        let samples = read_file(&path, Resample::Assert, ChannelSelect::Mix);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(samples.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn submit_poll_and_fetch() {
+        let app = app(false, config(1 << 20));
+        let (status, body) = call(&app, form("/transcribe", &[("lang", "en")])).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let id = serde_json::from_slice::<serde_json::Value>(&body).unwrap()["id"].as_u64().unwrap();
+        assert_eq!(wait_for(&app, id, "succeeded").await["progress"], 100);
+        let (status, body) = call(&app, HttpRequest::get(format!("/jobs/{}/result?format=srt", id)).body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(String::from_utf8(body).unwrap(), "1\n00:00:00,000 --> 00:00:00,500\nHello\n\n2\n00:00:00,500 --> 00:00:01,000\nworld\n\n");
+        let (status, _) = call(&app, HttpRequest::get("/jobs/999/result").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call(&app, form("/transcribe", &[("lang", "xx")])).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn token_is_required() {
+        let app = app(false, ServerConfig { token: Some("secret".to_string()), ..config(1 << 20) });
+        let (status, _) = call(&app, HttpRequest::get("/jobs/0").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = call(&app, HttpRequest::get("/jobs/0").header(header::AUTHORIZATION, "Bearer secret").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn socket_streams_segments_then_a_summary() {
+        let addr = listen(app(false, config(1 << 20))).await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws/transcribe", addr)).await.unwrap();
+        socket.send(WsMessage::Binary(second())).await.unwrap();
+        socket.send(WsMessage::Text("end".to_string())).await.unwrap();
+        let (frames, close) = frames(&mut socket).await;
+        let names = frames.iter().map(|frame| frame["event"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(names.first(), Some(&"segment"));
+        assert_eq!(names.iter().filter(|name| **name == "segment").count(), 2);
+        let summary = frames.last().unwrap();
+        assert_eq!(summary["event"], "summary");
+        assert_eq!(summary["status"], "succeeded");
+        assert_eq!(summary["segments"], 2);
+        assert_eq!(summary["audio_seconds"], 1.0);
+        assert_eq!(close, Some(1000));
+    }
+
+    #[tokio::test]
+    async fn socket_closes_past_max_upload() {
+        let addr = listen(app(false, config(1000))).await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws/transcribe", addr)).await.unwrap();
+        socket.send(WsMessage::Binary(vec![0; 600])).await.unwrap();
+        socket.send(WsMessage::Binary(vec![0; 600])).await.unwrap();
+        let (frames, close) = frames(&mut socket).await;
+        assert!(frames.is_empty());
+        assert_eq!(close, Some(1009));
+    }
+
+    #[tokio::test]
+    async fn closing_the_socket_cancels_the_job() {
+        let app = app(true, config(1 << 20));
+        let addr = listen(app.clone()).await;
+        let (_, body) = call(&app, form("/transcribe", &[])).await;
+        let id = serde_json::from_slice::<serde_json::Value>(&body).unwrap()["id"].as_u64().unwrap();
+        wait_for(&app, id, "running").await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws/transcribe?job={}", addr, id)).await.unwrap();
+        socket.close(None).await.unwrap();
+        assert_eq!(wait_for(&app, id, "cancelled").await["error"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn job_webhooks() {
+        assert!(JobWebhooks::Any.webhook("http://10.0.0.1/hook", None).is_ok());
+        assert!(JobWebhooks::Off.webhook("https://example.com/hook", None).is_err());
+        let hosts = JobWebhooks::Hosts(vec!["example.com".to_string()]);
+        assert!(hosts.webhook("https://EXAMPLE.com/hook", None).is_ok());
+        assert!(hosts.webhook("http://127.0.0.1:9000/hook", None).is_err());
+        assert!(hosts.webhook("https://example.com.evil.test/hook", None).is_err());
+    }
+
+    #[test]
+    fn openai_models() {
+        assert_eq!(openai_model("whisper-1", Model::Base), Ok(Model::Base));
+        assert_eq!(openai_model("", Model::Small), Ok(Model::Small));
+        assert_eq!(openai_model("large-v3-turbo", Model::Large), Ok(Model::Large));
+        assert_eq!(openai_model("whisper-large-v3-turbo", Model::Large), Ok(Model::Large));
+        assert_eq!(openai_model("Base", Model::Base), Ok(Model::Base));
+        let e = openai_model("gpt-4o-transcribe", Model::Base).unwrap_err();
+        assert!(e.starts_with("unknown model gpt-4o-transcribe"), "{}", e);
+    }
+
+    #[tokio::test]
+    async fn openai_response_formats() {
+        let app = app(false, config(1 << 20));
+        let uri = "/v1/audio/transcriptions";
+        let (status, body) = call(&app, form(uri, &[("model", "whisper-1"), ("response_format", "verbose_json")])).await;
+        assert_eq!(status, StatusCode::OK);
+        let body = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+        assert_eq!(body["task"], "transcribe");
+        assert_eq!(body["language"], "en");
+        assert_eq!(body["duration"], 1.0);
+        assert_eq!(body["text"], "Hello world");
+        let segments = body["segments"].as_array().unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1]["id"], 1);
+        assert_eq!(segments[1]["start"], 0.5);
+        assert_eq!(segments[1]["end"], 1.0);
+        assert_eq!(segments[1]["text"], " world");
+        for key in ["seek", "tokens", "temperature", "avg_logprob", "compression_ratio", "no_speech_prob"] {
+            assert!(segments[0].get(key).is_some(), "{}", key);
+        }
+
+        let (_, body) = call(&app, form(uri, &[("model", "whisper-1")])).await;
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap(), json!({ "text": "Hello world" }));
+        let (_, body) = call(&app, form(uri, &[("response_format", "text")])).await;
+        assert_eq!(body, b"Hello world");
+        let (_, body) = call(&app, form(uri, &[("response_format", "srt")])).await;
+        assert!(body.starts_with(b"1\n00:00:00,000 --> 00:00:00,500\nHello\n"));
+
+        let (status, body) = call(&app, form(uri, &[("response_format", "xml")])).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()["error"]["type"], "invalid_request_error");
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use clap_builder::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Language, Model};
+use crate::update::Release;
+
+const RECENT_LANGUAGES: usize = 5;
+const REALTIME_FACTORS: usize = 10;
+const MIN_CONFIDENT_RUNS: usize = 3;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub recent_languages: Vec<String>,
+    pub realtime_factors: HashMap<String, Vec<f64>>,
+    pub allow_sleep: bool,
+    pub skip_update_check: bool,
+    pub last_update_check: u64,
+    pub latest_release: Option<Release>,
+    pub dismissed_release: Option<String>,
+}
+
+impl Settings {
+    pub fn get_path() -> PathBuf {
+        let current = std::env::current_dir().unwrap();
+        current.join("settings.json")
+    }
+
+    pub fn load() -> Self {
+        std::fs::read_to_string(Self::get_path())
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        if let Ok(s) = serde_json::to_string_pretty(self) {
+            let _ = std::fs::write(Self::get_path(), s);
+        }
+    }
+
+    pub fn recent_languages(&self) -> Vec<Language> {
+        self.recent_languages
+            .iter()
+            .filter_map(|code| Language::from_str(code, true).ok())
+            .collect()
+    }
+
+    pub fn push_recent_language(&mut self, lang: Language) {
+        let code = <&str>::from(lang).to_string();
+        self.recent_languages.retain(|c| *c != code);
+        self.recent_languages.insert(0, code);
+        self.recent_languages.truncate(RECENT_LANGUAGES);
+    }
+
+    pub fn push_realtime_factor(&mut self, model: Model, processing: f64, audio: f64) {
+        if audio <= 0.0 {
+            return;
+        }
+        let factors = self.realtime_factors.entry(model.to_string()).or_default();
+        factors.push(processing / audio);
+        if factors.len() > REALTIME_FACTORS {
+            factors.remove(0);
+        }
+    }
+
+    // (low, high) seconds, wide around the seed until enough runs were recorded
+    pub fn estimate(&self, model: Model, audio: f64) -> (f64, f64) {
+        let factors = self.realtime_factors.get(&model.to_string()).map(Vec::as_slice).unwrap_or_default();
+        if factors.len() < MIN_CONFIDENT_RUNS {
+            let rtf = factors.iter().chain([model.realtime_factor()].iter()).sum::<f64>() / (factors.len() + 1) as f64;
+            return (rtf * audio / 2.0, rtf * audio * 2.0);
+        }
+        let mean = factors.iter().sum::<f64>() / factors.len() as f64;
+        let deviation = (factors.iter().map(|f| (f - mean).powi(2)).sum::<f64>() / factors.len() as f64).sqrt();
+        (((mean - deviation) * audio).max(0.0), (mean + deviation) * audio)
+    }
+}
@@ -0,0 +1,647 @@
+use std::env::temp_dir;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Language;
+use crate::whisper::{Format, JsonTimings, Timings, Transcript, Utterance};
+
+/// 00:01:02,345 / 01:02.345 / 01:02.34 -> centiseconds
+pub fn parse_timestamp(s: &str) -> Option<i64> {
+    let s = s.trim().replace(',', ".");
+    let (clock, fraction) = match s.split_once('.') {
+        Some((clock, fraction)) => (clock.to_string(), fraction.to_string()),
+        None => (s, String::new()),
+    };
+    let mut seconds = 0;
+    for part in clock.split(':') {
+        seconds = seconds * 60 + part.parse::<i64>().ok()?;
+    }
+    // digits only, a stray character would otherwise be sliced through or read as a sign
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let centis = match fraction.len() {
+        0 => 0,
+        1 => fraction.parse::<i64>().ok()? * 10,
+        _ => fraction.chars().take(2).collect::<String>().parse::<i64>().ok()?,
+    };
+    Some(seconds * 100 + centis)
+}
+
+/// shift into a temp file of the same format, the caller removes it when done
+pub fn shift_file<P: AsRef<Path>>(path: P, offset: i64) -> Result<PathBuf> {
+    let path = path.as_ref();
+    let format = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(Format::from_extension)
+        .ok_or_else(|| anyhow!("unsupported subtitle format"))?;
+    let mut t = Transcript::from_file(path)?;
+    t.shift(offset);
+    let temp = temp_dir().join(uuid::Uuid::new_v4().to_string()).with_extension(path.extension().unwrap());
+    t.write_file(&temp, format)?;
+    Ok(temp)
+}
+
+// cues are "start --> end [settings]" followed by text lines up to a blank line,
+// anything else outside of a cue (indices, WEBVTT header, NOTE blocks) is skipped
+fn parse_cues(s: &str, vtt: bool) -> Result<Vec<Utterance>> {
+    let lines = s.trim_start_matches('\u{feff}').lines().map(str::trim).collect::<Vec<_>>();
+    let mut utterances = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
+        let Some((start, end)) = line.split_once("-->") else {
+            continue;
+        };
+        let end = end.split_whitespace().next().unwrap_or_default();
+        let start = parse_timestamp(start).ok_or_else(|| anyhow!("line {}: invalid timestamp: {}", i, start.trim()))?;
+        let end = parse_timestamp(end).ok_or_else(|| anyhow!("line {}: invalid timestamp: {}", i, end))?;
+
+        let mut text = vec![];
+        let mut speaker = None;
+        while i < lines.len() && !lines[i].is_empty() && !lines[i].contains("-->") {
+            // an index line directly followed by the next cue
+            if lines.get(i + 1).is_some_and(|l| l.contains("-->")) && lines[i].parse::<u64>().is_ok() {
+                break;
+            }
+            let line = match vtt {
+                true => {
+                    let (name, line) = voice(lines[i].strip_prefix("- ").unwrap_or(lines[i]));
+                    speaker = speaker.or(name);
+                    line
+                }
+                false => lines[i],
+            };
+            text.push(line);
+            i += 1;
+        }
+        utterances.push(Utterance { start, end, text: text.join("\n"), lang: None, avg_logprob: None, no_speech_prob: None, speaker, translation: None });
+    }
+    Ok(utterances)
+}
+
+// <v Name>text or <v.loud Name>text</v>, the speaker and the text without the span
+fn voice(line: &str) -> (Option<String>, &str) {
+    let Some((tag, text)) = line.strip_prefix("<v").and_then(|rest| rest.split_once('>')) else {
+        return (None, line);
+    };
+    if !tag.starts_with([' ', '.']) {
+        return (None, line);
+    }
+    let name = tag.split_once(' ').map_or("", |(_, name)| name.trim());
+    let text = text.strip_suffix("</v>").unwrap_or(text);
+    ((!name.is_empty()).then(|| name.to_string()), text)
+}
+
+/// WEBVTT header, a json object, srt arrows or lrc [mm:ss.xx] tags
+pub fn sniff_format(text: &str) -> Option<Format> {
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    if text.starts_with("WEBVTT") {
+        return Some(Format::Vtt);
+    }
+    if text.starts_with('{') {
+        return Some(Format::Json);
+    }
+    let lines = text.lines().map(str::trim).take(50).collect::<Vec<_>>();
+    if lines.iter().any(|l| l.contains("-->")) {
+        return Some(Format::Srt);
+    }
+    let is_lrc = |l: &&str| l.strip_prefix('[').and_then(|r| r.split_once(']')).is_some_and(|(tag, _)| parse_timestamp(tag).is_some());
+    lines.iter().any(is_lrc).then_some(Format::Lrc)
+}
+
+pub fn is_cjk(c: char) -> bool {
+    matches!(c, '\u{3040}'..='\u{30ff}' | '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' | '\u{ac00}'..='\u{d7af}' | '\u{f900}'..='\u{faff}' | '\u{ff00}'..='\u{ffef}')
+}
+
+pub(crate) fn join_text(left: &str, right: &str) -> String {
+    let (left, right) = (left.trim(), right.trim());
+    match (left.chars().last(), right.chars().next()) {
+        (Some(l), Some(r)) if is_cjk(l) || is_cjk(r) => format!("{}{}", left, right),
+        (Some(_), Some(_)) => format!("{} {}", left, right),
+        _ => format!("{}{}", left, right),
+    }
+}
+
+// cjk text doesn't space its words or its full width punctuation
+fn is_unspaced(c: char) -> bool {
+    is_cjk(c) || ('\u{3000}'..='\u{303f}').contains(&c)
+}
+
+// collapses runs of spaces and drops the ones whisper puts before punctuation,
+// french keeps its space before ; : ! ?
+fn clean_text(text: &str, lang: Option<&str>) -> String {
+    let closing = if lang == Some("fr") { ",.)]}%" } else { ",.;:!?)]}%" };
+    text.lines()
+        .map(|line| {
+            let mut cleaned = String::new();
+            for word in line.split_whitespace() {
+                let (Some(last), Some(first)) = (cleaned.chars().last(), word.chars().next()) else {
+                    cleaned.push_str(word);
+                    continue;
+                };
+                let joined = (is_unspaced(last) && is_unspaced(first)) || closing.contains(first) || "([{".contains(last);
+                if !joined {
+                    cleaned.push(' ');
+                }
+                cleaned.push_str(word);
+            }
+            cleaned
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn wrap_text(text: &str, width: usize) -> String {
+    let mut lines = vec![];
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let chars = word.chars().collect::<Vec<_>>();
+        for (i, chunk) in chars.chunks(width.max(1)).enumerate() {
+            let chunk = chunk.iter().collect::<String>();
+            if !line.is_empty() && (i > 0 || line.chars().count() + 1 + chunk.chars().count() > width) {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&chunk);
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+// words, or single characters for cjk text written without spaces
+fn split_words(text: &str) -> Vec<String> {
+    let text = text.replace('\n', " ");
+    let text = text.trim();
+    if !text.contains(' ') && text.chars().any(is_cjk) {
+        text.chars().map(String::from).collect()
+    } else {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+}
+
+// n pieces of roughly equal length, timed by where their text falls in the cue
+fn split_cue(u: &Utterance, max_duration: i64) -> Vec<Utterance> {
+    let duration = u.end - u.start;
+    let words = split_words(&u.text);
+    let pieces = ((duration + max_duration - 1) / max_duration.max(1)).min(words.len() as i64);
+    if duration <= max_duration || pieces < 2 {
+        return vec![u.clone()];
+    }
+    let total = words.iter().map(|w| w.chars().count()).sum::<usize>().max(1) as i64;
+    let time_at = |offset: i64| u.start + duration * offset / total;
+    let mut cues = vec![];
+    let (mut text, mut start, mut offset) = (String::new(), 0, 0);
+    for (i, word) in words.iter().enumerate() {
+        text = join_text(&text, word);
+        offset += word.chars().count() as i64;
+        let last = i + 1 == words.len();
+        if last || offset * pieces >= total * (cues.len() as i64 + 1) {
+            cues.push(Utterance { start: time_at(start), end: if last { u.end } else { time_at(offset) }, text: std::mem::take(&mut text), lang: u.lang, avg_logprob: u.avg_logprob, no_speech_prob: u.no_speech_prob, speaker: u.speaker.clone(), translation: None });
+            start = offset;
+        }
+    }
+    cues
+}
+
+// byte offsets just past each sentence in a cue, `. ! ? …` only count before a space or the end so 3.14 and e.g. stay whole.
+// closing quotes and brackets stay with the sentence they close
+fn sentence_ends(text: &str) -> Vec<usize> {
+    let (western, cjk, closing) = (".!?…", "。！？", "\"')]”’」』");
+    let chars = text.char_indices().collect::<Vec<_>>();
+    let mut ends = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i].1;
+        i += 1;
+        if !western.contains(c) && !cjk.contains(c) {
+            continue;
+        }
+        while i < chars.len() && (western.contains(chars[i].1) || cjk.contains(chars[i].1) || closing.contains(chars[i].1)) {
+            i += 1;
+        }
+        match chars.get(i) {
+            None => ends.push(text.len()),
+            Some(&(at, next)) if cjk.contains(c) || next.is_whitespace() => ends.push(at),
+            Some(_) => {}
+        }
+    }
+    ends
+}
+
+pub fn reflow_file<P: AsRef<Path>>(path: P, max_gap: i64, width: usize, max_duration: i64) -> Result<PathBuf> {
+    let path = path.as_ref();
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    let format = Format::from_extension(ext).ok_or_else(|| anyhow!("unsupported subtitle format"))?;
+    let mut t = Transcript::from_file(path)?;
+    t.merge_utterances(max_gap, width * 2);
+    if max_duration > 0 {
+        t.split_long_cues(max_duration);
+    }
+    t.wrap_lines(width);
+    let output = path.with_extension(format!("reflow.{}", ext));
+    t.write_file(&output, format)?;
+    Ok(output)
+}
+
+/// name.mp3 -> name.part01.srt, name.part02.srt, ...
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Numbering {
+    pub prefix: String,
+    pub digits: usize,
+}
+
+impl Default for Numbering {
+    fn default() -> Self {
+        Self { prefix: "part".to_string(), digits: 2 }
+    }
+}
+
+impl Numbering {
+    pub fn path<P: AsRef<Path>>(&self, audio: P, n: usize) -> PathBuf {
+        let audio = audio.as_ref();
+        let ext = audio.extension().and_then(|e| e.to_str()).unwrap_or_default();
+        audio.with_extension(format!("{}{:0digits$}.{}", self.prefix, n, ext, digits = self.digits))
+    }
+}
+
+fn shift_utterances(utterances: &mut Vec<Utterance>, offset: i64) {
+    utterances.retain_mut(|u| {
+        u.start = (u.start + offset).max(0);
+        u.end += offset;
+        u.end > 0
+    });
+}
+
+impl Transcript {
+    /// offset in centiseconds, cues ending before zero are dropped and the rest clamped to zero
+    pub fn shift(&mut self, offset: i64) {
+        shift_utterances(&mut self.utterances, offset);
+        if let Some(ref mut words) = self.word_utterances {
+            shift_utterances(words, offset);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.utterances.iter().all(|u| u.text.trim().is_empty())
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.utterances.len()
+    }
+
+    /// timed words when there are any, otherwise words (or cjk characters) in the segment text
+    pub fn word_count(&self) -> usize {
+        match self.word_utterances {
+            Some(ref words) => words.iter().filter(|w| !w.text.trim().is_empty()).count(),
+            None => self.utterances.iter().map(|u| split_words(&u.text).len()).sum(),
+        }
+    }
+
+    /// seconds of audio transcribed per second of processing, large at 0.3 is slower than realtime, base at 3.0 faster
+    pub fn throughput(&self) -> Option<f64> {
+        let processing = self.processing_time.as_secs_f64();
+        (processing > 0.0 && !self.timings.audio.is_zero()).then(|| self.timings.audio.as_secs_f64() / processing)
+    }
+
+    fn cues_mut(&mut self) -> &mut Vec<Utterance> {
+        self.word_utterances.as_mut().unwrap_or(&mut self.utterances)
+    }
+
+    /// join neighbouring cues at most max_gap centiseconds apart while the text stays within max_chars
+    pub fn merge_utterances(&mut self, max_gap: i64, max_chars: usize) {
+        let mut merged: Vec<Utterance> = vec![];
+        for u in self.cues_mut().drain(..) {
+            if let Some(last) = merged.last_mut() {
+                let text = join_text(&last.text.replace('\n', " "), &u.text.replace('\n', " "));
+                if u.start - last.end <= max_gap && text.chars().count() <= max_chars {
+                    last.end = last.end.max(u.end);
+                    last.text = text;
+                    last.translation = match (last.translation.take(), &u.translation) {
+                        (Some(left), Some(right)) => Some(join_text(&left, right)),
+                        (left, right) => left.or(right.clone()),
+                    };
+                    continue;
+                }
+            }
+            merged.push(u);
+        }
+        *self.cues_mut() = merged;
+    }
+
+    /// cues longer than max_duration centiseconds are cut at word boundaries, the text shared out by length
+    pub fn split_long_cues(&mut self, max_duration: i64) {
+        let cues = self.cues_mut().iter().flat_map(|u| split_cue(u, max_duration)).collect();
+        *self.cues_mut() = cues;
+    }
+
+    /// re-cut at sentence-ending punctuation, timed by the words when there are any and otherwise by where the
+    /// text falls in its segment. Text without such punctuation (thai, unpunctuated output) keeps its segments
+    pub fn sentences(&self) -> Vec<Utterance> {
+        let cues = self.word_utterances.as_ref().unwrap_or(&self.utterances);
+        if cues.iter().all(|u| sentence_ends(&u.text).is_empty()) {
+            return self.utterances.clone();
+        }
+        // word cues are whisper tokens that carry their own spaces
+        let words = self.word_utterances.is_some();
+        let mut sentences = vec![];
+        let mut current: Option<Utterance> = None;
+        for u in cues {
+            let total = u.text.chars().count().max(1) as i64;
+            let time_at = |at: usize| u.start + (u.end - u.start) * u.text[..at].chars().count() as i64 / total;
+            let ends = sentence_ends(&u.text);
+            let rest = (ends.last() != Some(&u.text.len())).then_some((u.text.len(), false));
+            let mut from = 0;
+            for (end, closes) in ends.iter().map(|&end| (end, true)).chain(rest) {
+                let piece = &u.text[from..end];
+                if !piece.trim().is_empty() {
+                    let start = if from == 0 { u.start } else { time_at(from + piece.len() - piece.trim_start().len()) };
+                    let sentence = current.get_or_insert_with(|| Utterance { start, end: start, text: String::new(), lang: u.lang, avg_logprob: None, no_speech_prob: None, speaker: u.speaker.clone(), translation: None });
+                    sentence.text = if words { sentence.text.clone() + piece } else { join_text(&sentence.text, piece) };
+                    sentence.end = time_at(end);
+                }
+                if closes {
+                    sentences.extend(current.take());
+                }
+                from = end;
+            }
+        }
+        sentences.extend(current);
+        for sentence in &mut sentences {
+            sentence.text = sentence.text.trim().to_string();
+        }
+        sentences
+    }
+
+    /// cues starting before `at` go to the first half
+    pub fn split_at(&self, at: i64) -> (Self, Self) {
+        let split = |cues: &[Utterance]| cues.iter().cloned().partition::<Vec<_>, _>(|u| u.start < at);
+        let (left, right) = split(&self.utterances);
+        let (left_words, right_words) = match self.word_utterances {
+            Some(ref words) => {
+                let (left, right) = split(words);
+                (Some(left), Some(right))
+            }
+            None => (None, None),
+        };
+        (
+            Self { processing_time: self.processing_time, utterances: left, word_utterances: left_words, timings: self.timings },
+            Self { processing_time: self.processing_time, utterances: right, word_utterances: right_words, timings: self.timings },
+        )
+    }
+
+    /// split wherever the silence between two segments is at least min_gap centiseconds
+    pub fn split_at_gaps(&self, min_gap: i64) -> Vec<Self> {
+        let cuts = self
+            .utterances
+            .windows(2)
+            .filter(|w| w[1].start - w[0].end >= min_gap)
+            .map(|w| w[1].start)
+            .collect::<Vec<_>>();
+        let mut parts = vec![];
+        let mut rest = self.clone();
+        for cut in cuts {
+            let (left, right) = rest.split_at(cut);
+            parts.push(left);
+            rest = right;
+        }
+        parts.push(rest);
+        parts
+    }
+
+    pub fn write_parts<P: AsRef<Path>>(&self, audio: P, formats: &[Format], min_gap: i64, numbering: &Numbering) -> Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        for (i, part) in self.split_at_gaps(min_gap).iter().enumerate() {
+            let path = numbering.path(&audio, i + 1);
+            for format in formats {
+                part.write_file(&path, *format)?;
+            }
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    /// ;FFMETADATA1 with one chapter per part of split_at_gaps, titled after its first line.
+    /// end is where the last chapter stops, the end of its last cue if unknown
+    pub fn to_ffmetadata(&self, min_gap: i64, end: Option<i64>) -> String {
+        let escape = |s: &str| s.chars().fold(String::new(), |mut escaped, c| {
+            if "=;#\\\n".contains(c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+            escaped
+        });
+        let parts = self.split_at_gaps(min_gap);
+        let starts = parts.iter().filter_map(|part| part.utterances.first()).collect::<Vec<_>>();
+        let last = self.utterances.last().map_or(0, |u| u.end);
+        let mut metadata = String::from(";FFMETADATA1\n");
+        for (i, first) in starts.iter().enumerate() {
+            let start = if i == 0 { 0 } else { first.start };
+            let stop = starts.get(i + 1).map_or(end.unwrap_or(last).max(last), |next| next.start);
+            let title = first.text.trim().lines().next().unwrap_or_default().chars().take(40).collect::<String>();
+            metadata += &format!("\n[CHAPTER]\nTIMEBASE=1/100\nSTART={}\nEND={}\ntitle={}\n", start, stop, escape(&title));
+        }
+        metadata
+    }
+
+    /// word cues keep their leading spaces, they are how words are told apart from the pieces of one
+    pub fn clean_text(&mut self) {
+        for u in &mut self.utterances {
+            u.text = clean_text(&u.text, u.lang.map(<&str>::from));
+        }
+    }
+
+    /// the tagged language spoken for the longest, what a single language code for the whole file should say
+    pub fn language(&self) -> Option<Language> {
+        let mut spoken = std::collections::BTreeMap::new();
+        for u in &self.utterances {
+            if let Some(lang) = u.lang {
+                *spoken.entry(lang).or_insert(0) += (u.end - u.start).max(1);
+            }
+        }
+        spoken.into_iter().max_by_key(|(_, time)| *time).map(|(lang, _)| lang)
+    }
+
+    /// cues without a language are dropped too, they were never tagged so can't be told to match
+    pub fn filter_language(&mut self, lang: Language) {
+        let keep = |u: &Utterance| u.lang == Some(lang);
+        self.utterances.retain(keep);
+        if let Some(ref mut words) = self.word_utterances {
+            words.retain(keep);
+        }
+    }
+
+    /// `[zh] text`, only on segments as a tag before every word would bury the words
+    pub fn tag_languages(&mut self) {
+        for u in &mut self.utterances {
+            if let Some(lang) = u.lang {
+                u.text = format!("[{}] {}", <&str>::from(lang), u.text.trim_start());
+            }
+        }
+    }
+
+    pub fn wrap_lines(&mut self, width: usize) {
+        for u in self.cues_mut() {
+            u.text = wrap_text(&u.text, width);
+        }
+    }
+
+    /// by extension, or by content when the extension isn't a subtitle one
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let format = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .and_then(Format::from_extension)
+            .or_else(|| sniff_format(&text))
+            .ok_or_else(|| anyhow!("unsupported subtitle format"))?;
+        Self::parse(&text, format)
+    }
+
+    pub fn parse(text: &str, format: Format) -> Result<Self> {
+        match format {
+            Format::Srt | Format::SrtCrlf => Self::from_srt(text),
+            Format::Vtt | Format::VttPlain | Format::VttVoice => Self::from_vtt(text),
+            Format::Lrc | Format::LrcMillis => Self::from_lrc(text),
+            Format::Json => Self::from_json(text),
+            Format::Txt => Err(anyhow!("plain text has no timestamps")),
+            Format::AssKaraoke => Err(anyhow!("karaoke ASS can't be read back")),
+            Format::Chapters => Err(anyhow!("chapters can't be read back")),
+        }
+    }
+
+    pub fn from_json(s: &str) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Json {
+            #[serde(default)]
+            processing_time: f64,
+            utterances: Vec<Utterance>,
+            word_utterances: Option<Vec<Utterance>>,
+            timings: Option<JsonTimings>,
+        }
+
+        let json: Json = serde_json::from_str(s)?;
+        Ok(Self {
+            processing_time: Duration::from_secs_f64(json.processing_time.max(0.0)),
+            utterances: json.utterances,
+            word_utterances: json.word_utterances,
+            timings: json.timings.map(Timings::from).unwrap_or_default(),
+        })
+    }
+
+    pub fn from_srt(s: &str) -> Result<Self> {
+        Ok(Self {
+            processing_time: Duration::ZERO,
+            utterances: parse_cues(s, false)?,
+            word_utterances: None,
+            timings: Timings::default(),
+        })
+    }
+
+    pub fn from_vtt(s: &str) -> Result<Self> {
+        if !s.trim_start_matches('\u{feff}').starts_with("WEBVTT") {
+            return Err(anyhow!("line 1: missing WEBVTT header"));
+        }
+        Ok(Self {
+            processing_time: Duration::ZERO,
+            utterances: parse_cues(s, true)?,
+            word_utterances: None,
+            timings: Timings::default(),
+        })
+    }
+
+    pub fn from_lrc(s: &str) -> Result<Self> {
+        let mut entries = vec![];
+        for line in s.lines() {
+            let mut rest = line.trim();
+            let mut times = vec![];
+            while let Some(tag) = rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+                match parse_timestamp(tag.0) {
+                    Some(time) => times.push(time),
+                    None => break,
+                }
+                rest = tag.1;
+            }
+            for time in times {
+                entries.push((time, rest.trim().to_string()));
+            }
+        }
+        entries.sort_by_key(|(time, _)| *time);
+
+        let utterances = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, text))| !text.is_empty())
+            .map(|(i, (start, text))| Utterance {
+                start: *start,
+                end: entries.get(i + 1).map(|(end, _)| *end).unwrap_or(start + 500),
+                text: text.clone(),
+                lang: None,
+                avg_logprob: None,
+                no_speech_prob: None,
+                speaker: None,
+                translation: None,
+            })
+            .collect();
+
+        Ok(Self {
+            processing_time: Duration::ZERO,
+            utterances,
+            word_utterances: None,
+            timings: Timings::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("00:01:02,345"), Some(6234));
+        assert_eq!(parse_timestamp("01:02.34"), Some(6234));
+        assert_eq!(parse_timestamp("0:01.5"), Some(150));
+        assert_eq!(parse_timestamp("12"), Some(1200));
+    }
+
+    #[test]
+    fn timestamp_fraction_must_be_digits() {
+        assert_eq!(parse_timestamp("0:01.5é"), None);
+        assert_eq!(parse_timestamp("0:01.é5"), None);
+        assert_eq!(parse_timestamp("0:01.５０"), None);
+        assert_eq!(parse_timestamp("0:01.x"), None);
+        assert_eq!(parse_timestamp("0:01.+5"), None);
+        assert_eq!(parse_timestamp("0:01.5 "), Some(150));
+    }
+
+    #[test]
+    fn clean_text_english() {
+        assert_eq!(clean_text("  Hello ,  world .", Some("en")), "Hello, world.");
+        assert_eq!(clean_text("Well , ( maybe ) not !", Some("en")), "Well, (maybe) not!");
+        assert_eq!(clean_text(" One line .\n  \nTwo ", Some("en")), "One line.\nTwo");
+    }
+
+    #[test]
+    fn clean_text_chinese() {
+        assert_eq!(clean_text(" 你好 ， 世界 。", Some("zh")), "你好，世界。");
+        assert_eq!(clean_text("我 用 Rust 写 。", Some("zh")), "我用 Rust 写。");
+    }
+
+    #[test]
+    fn clean_text_french_keeps_space_before_marks() {
+        assert_eq!(clean_text("Bonjour , ça va ?", Some("fr")), "Bonjour, ça va ?");
+    }
+}
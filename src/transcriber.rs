@@ -0,0 +1,30 @@
+use crate::config::Language;
+use crate::error::ConvError;
+use crate::whisper::{TranscribeOptions, Transcript, Whisper};
+
+/// a loaded model that turns 16kHz mono samples into a transcript, object safe so backends can be
+/// swapped at runtime behind a `Box<dyn Transcriber>`
+pub trait Transcriber: Send {
+    fn set_language(&mut self, lang: Language);
+
+    fn transcribe(&mut self, audio: &[f32], options: &TranscribeOptions) -> Result<Transcript, ConvError>;
+
+    /// (start in centiseconds, language code) for each language_window_ms of the transcribed range
+    fn detect_languages(&self, _audio: &[f32], _options: &TranscribeOptions) -> Result<Vec<(i64, &'static str)>, ConvError> {
+        Err(ConvError::Transcribe("language detection is not supported by this backend".to_string()))
+    }
+}
+
+impl Transcriber for Whisper {
+    fn set_language(&mut self, lang: Language) {
+        Whisper::set_language(self, lang)
+    }
+
+    fn transcribe(&mut self, audio: &[f32], options: &TranscribeOptions) -> Result<Transcript, ConvError> {
+        Whisper::transcribe(self, audio, options)
+    }
+
+    fn detect_languages(&self, audio: &[f32], options: &TranscribeOptions) -> Result<Vec<(i64, &'static str)>, ConvError> {
+        Whisper::detect_languages(self, audio, options)
+    }
+}
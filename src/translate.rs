@@ -0,0 +1,143 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::error::ConvError;
+use crate::subtitle::join_text;
+use crate::whisper::{Transcript, Utterance};
+
+/// which line of a bilingual cue comes first
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BilingualOrder {
+    #[default]
+    OriginalFirst,
+    TranslationFirst,
+}
+
+/// whisper only translates into english, anything else goes through one of these after transcription.
+/// gets the texts of one batch and returns as many translations in the same order
+pub trait Translator: Send {
+    fn translate(&mut self, texts: &[String], from: Option<&str>, to: &str) -> Result<Vec<String>, ConvError>;
+}
+
+impl<F> Translator for F
+where
+    F: FnMut(&[String], Option<&str>, &str) -> Result<Vec<String>, ConvError> + Send,
+{
+    fn translate(&mut self, texts: &[String], from: Option<&str>, to: &str) -> Result<Vec<String>, ConvError> {
+        self(texts, from, to)
+    }
+}
+
+/// a shell command run once per batch, one text per line on stdin and one translated line per text
+/// expected on stdout. CONV_FROM and CONV_TO hold the language codes, CONV_FROM is empty when unknown
+pub struct CommandTranslator {
+    command: String,
+}
+
+impl CommandTranslator {
+    pub fn new(command: impl Into<String>) -> Self {
+        Self { command: command.into() }
+    }
+}
+
+impl Translator for CommandTranslator {
+    fn translate(&mut self, texts: &[String], from: Option<&str>, to: &str) -> Result<Vec<String>, ConvError> {
+        let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+        let mut child = Command::new(shell)
+            .args([flag, &self.command])
+            .env("CONV_FROM", from.unwrap_or_default())
+            .env("CONV_TO", to)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let input = texts.iter().map(|text| text.replace('\n', " ") + "\n").collect::<String>();
+        // written from another thread so a command that answers line by line can't deadlock on a full pipe
+        let mut stdin = child.stdin.take();
+        let writer = std::thread::spawn(move || stdin.as_mut().map(|stdin| stdin.write_all(input.as_bytes())));
+        let output = child.wait_with_output()?;
+        let _ = writer.join();
+        if !output.status.success() {
+            return Err(ConvError::Translate(format!("{} exited with {}: {}", self.command, output.status, String::from_utf8_lossy(&output.stderr).trim())));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect())
+    }
+}
+
+impl Transcript {
+    /// fills in the translation of every segment, batch_size segments per call
+    pub fn translate_with(&mut self, translator: &mut dyn Translator, from: Option<&str>, to: &str, batch_size: usize) -> Result<(), ConvError> {
+        for batch in self.utterances.chunks_mut(batch_size.max(1)) {
+            let texts = batch.iter().map(|u| u.text.trim().to_string()).collect::<Vec<_>>();
+            let translations = translator.translate(&texts, from, to)?;
+            if translations.len() != texts.len() {
+                return Err(ConvError::Translate(format!("expected {} translations, got {}", texts.len(), translations.len())));
+            }
+            for (u, translation) in batch.iter_mut().zip(translations) {
+                u.translation = Some(translation);
+            }
+        }
+        info!("translated {} segments to {}", self.utterances.len(), to);
+        Ok(())
+    }
+
+    /// a second pass over the same audio, like whisper's english, cuts its segments elsewhere. each of its segments goes to the
+    /// original it overlaps most, or the nearest one when it overlaps none, and several landing on one are joined
+    pub fn align_translation(&mut self, translated: &Transcript) {
+        let mut matched = vec![vec![]; self.utterances.len()];
+        for t in translated.utterances.iter().filter(|t| !t.text.trim().is_empty()) {
+            let overlap = |u: &Utterance| (t.end.min(u.end) - t.start.max(u.start)).max(0);
+            let distance = |u: &Utterance| ((t.start + t.end) - (u.start + u.end)).abs();
+            let best = self.utterances.iter().enumerate().max_by_key(|(_, u)| (overlap(u), -distance(u))).map(|(i, _)| i);
+            if let Some(i) = best {
+                matched[i].push(t.text.trim());
+            }
+        }
+        let mut aligned = 0;
+        for (u, texts) in self.utterances.iter_mut().zip(matched) {
+            aligned += !texts.is_empty() as usize;
+            u.translation = (!texts.is_empty()).then(|| texts.into_iter().fold(String::new(), |joined, text| join_text(&joined, text)));
+        }
+        info!("aligned {} translated segments to {} of {} segments", translated.utterances.len(), aligned, self.utterances.len());
+    }
+
+    /// the original and its translation as two lines of one cue, segments without a translation keep one line.
+    /// word timings don't carry over
+    pub fn bilingual(&self, order: BilingualOrder) -> Transcript {
+        let utterances = self
+            .utterances
+            .iter()
+            .map(|u| {
+                let mut u = u.clone();
+                if let Some(translation) = u.translation.take() {
+                    u.text = match order {
+                        BilingualOrder::OriginalFirst => format!("{}\n{}", u.text.trim(), translation.trim()),
+                        BilingualOrder::TranslationFirst => format!("{}\n{}", translation.trim(), u.text.trim()),
+                    };
+                }
+                u
+            })
+            .collect();
+        Transcript { processing_time: self.processing_time, utterances, word_utterances: None, timings: self.timings }
+    }
+
+    /// the translations as the text, segments without one keep the original. word timings don't carry over
+    pub fn translated(&self) -> Transcript {
+        let utterances = self
+            .utterances
+            .iter()
+            .map(|u| {
+                let mut u = u.clone();
+                if let Some(translation) = u.translation.take() {
+                    u.text = translation;
+                }
+                u
+            })
+            .collect();
+        Transcript { processing_time: self.processing_time, utterances, word_utterances: None, timings: self.timings }
+    }
+}
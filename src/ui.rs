@@ -2,25 +2,77 @@ use std::sync::atomic::Ordering;
 
 use clap_builder::ValueEnum;
 use eframe::Frame;
-use egui::{ComboBox, Context, ProgressBar};
+use once_cell::sync::Lazy;
+use egui::{Align, Area, Button, Checkbox, Color32, ComboBox, DragValue, Context, Frame as PopupFrame, Key, Order, Pos2, ProgressBar, Rect, RichText, ScrollArea, Sense, Stroke, TextEdit, Ui, Vec2};
 
-use crate::config::{DOWNLOADED, FILE_SIZE, Language, Model};
-use crate::conv::Conv;
-use crate::utils::{DOWNLOADING, MERGE, WHISPER};
+use crate::batch::BatchPolicy;
+use crate::config::{Language, Model};
+use crate::conv::{Conv, LanguagePicker};
+use crate::log::{LINES, open_log_dir};
+use crate::preview::Preview;
+use crate::job;
+use crate::job::{JobKind, JobStatus};
+use crate::translate::BilingualOrder;
+use crate::utils::{ChannelSelect, DOWNLOADING, Resample};
+use crate::waveform::Envelope;
+use crate::whisper;
+use crate::whisper::{AutoSampling, Sampling, SYSTEM_INFO, VttCue};
+
+static LANGUAGES: Lazy<Vec<Language>> = Lazy::new(|| {
+    let mut languages = Language::all().to_vec();
+    // Auto has no group and comes first
+    languages.sort_by_key(|(l, _, name)| (l.group(), *name));
+    languages.into_iter().map(|(l, _, _)| l).collect()
+});
 
 impl eframe::App for Conv {
     fn update(&mut self, ctx: &Context, _: &mut Frame) {
         ctx.request_repaint();
+        self.handle_events();
 
         egui::CentralPanel::default().show(ctx, |ui| {
+            let update = self.update.lock().unwrap().clone();
+            if let Some(release) = update {
+                ui.horizontal(|ui| {
+                    ui.label(format!("发现新版本 {}", release.tag));
+                    ui.hyperlink_to("更新说明", &release.url);
+                    if ui.small_button("关闭").clicked() {
+                        *self.update.lock().unwrap() = None;
+                        let mut settings = self.settings.lock().unwrap();
+                        settings.dismissed_release = Some(release.tag);
+                        settings.save();
+                    }
+                });
+            }
+            for warning in &self.warnings {
+                ui.colored_label(ui.visuals().warn_fg_color, warning);
+            }
+
             if ui.button("选择音频").clicked() {
                 self.open_audio(self.files.clone());
             }
-            ui.label(format!("音频: {}", if let Some(ref p) = self.files.lock().unwrap().audio {
+            let fetch = self.latest_job(&[JobKind::Fetch]).map(|job| job.status());
+            ui.horizontal(|ui| {
+                let mut files = self.files.lock().unwrap();
+                ui.add(TextEdit::singleline(&mut files.url).hint_text("视频链接 (YouTube、Bilibili 等)").desired_width(220.0));
+                let link = !files.url.trim().is_empty();
+                drop(files);
+                let idle = fetch.as_ref().is_none_or(JobStatus::is_finished);
+                if ui.add_enabled(link && idle, Button::new("下载音频")).on_hover_text("用 yt-dlp 下载音频到当前目录，以视频标题命名").clicked() {
+                    let job = self.fetch_audio();
+                    self.push_job(job);
+                }
+            });
+            if let Some(ref fetch) = fetch {
+                status_ui(ui, fetch, "下载");
+            }
+            let info = self.audio_info();
+            let duration = info.map(|info| info.duration.as_secs_f64());
+            ui.label(format!("音频: {}{}", if let Some(ref p) = self.files.lock().unwrap().audio {
                 p.file_name().unwrap().to_str().unwrap()
             } else {
                 "None"
-            }));
+            }, info.map(|info| format!(" ({}, {} Hz, {} 声道)", clock((info.duration.as_secs_f64() * 100.0) as i64), info.sample_rate, info.channels)).unwrap_or_default()));
 
             if ui.button("选择背景图片").clicked() {
                 self.open_image(self.files.clone());
@@ -31,9 +83,22 @@ impl eframe::App for Conv {
                 "None"
             }));
 
-            if ui.button("选择字幕").clicked() {
-                self.open_subtitle(self.files.clone());
-            }
+            ui.horizontal(|ui| {
+                if ui.button("选择字幕").clicked() {
+                    self.open_subtitle(self.files.clone());
+                }
+                if ui.button("预览字幕").clicked() {
+                    self.load_preview();
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("重排字幕").clicked() {
+                    self.reflow_subtitle();
+                }
+                ui.add(DragValue::new(&mut self.config.reflow_width).clamp_range(8..=200).suffix(" 字/行"));
+                ui.add(DragValue::new(&mut self.config.reflow_gap).clamp_range(0..=10000).speed(10).suffix(" ms 合并间隔"));
+                ui.add(DragValue::new(&mut self.config.reflow_max_duration).clamp_range(0..=60000).speed(100).suffix(" ms 最长显示"));
+            });
             ui.label(format!("字幕: {}", if let Some(ref p) = self.files.lock().unwrap().subtitle {
                 p.file_name().unwrap().to_str().unwrap()
             } else {
@@ -43,57 +108,614 @@ impl eframe::App for Conv {
 
             ui.separator();
 
-            if ui.button("合并音频/图片/字幕").clicked() {
-                if !MERGE.load(Ordering::Relaxed) {
-                    self.ffmpeg_merge();
+            ui.horizontal(|ui| {
+                ui.add(DragValue::new(&mut self.config.subtitle_offset).speed(10).suffix(" ms"));
+                ui.label("字幕偏移 (ms)");
+            });
+            ui.horizontal(|ui| {
+                let style = &mut self.config.subtitle_style;
+                ComboBox::from_id_source("subtitle_alignment")
+                    .selected_text(alignment_label(style.alignment))
+                    .show_ui(ui, |ui| {
+                        for alignment in [2, 5, 8] {
+                            ui.selectable_value(&mut style.alignment, alignment, alignment_label(alignment));
+                        }
+                    });
+                ui.add(DragValue::new(&mut style.margin_v).clamp_range(0..=200));
+                ui.label("字幕位置/边距");
+            });
+            if self.files.lock().unwrap().image.is_none() {
+                ui.horizontal(|ui| {
+                    ui.label("无背景图片时使用");
+                    ui.add_enabled_ui(!self.config.waveform_background, |ui| {
+                        ui.color_edit_button_srgb(&mut self.config.background_color);
+                    });
+                    ui.checkbox(&mut self.config.waveform_background, "波形");
+                });
+            }
+            let merge = self.latest_job(&[JobKind::Merge]).map(|job| job.status());
+            ui.horizontal(|ui| {
+                let idle = merge.as_ref().is_none_or(JobStatus::is_finished);
+                if ui.button("合并音频/图片/字幕").clicked() && idle {
+                    let job = self.ffmpeg_merge(false);
+                    self.push_job(job);
+                }
+                if ui.button("合并为MKV").on_hover_text("不生成画面，音频不重新编码，字幕作为软字幕，按静音间隔生成章节").clicked() && idle {
+                    let job = self.ffmpeg_merge(true);
+                    self.push_job(job);
+                }
+            });
+            if let Some(ref merge) = merge {
+                status_ui(ui, merge, "合并");
+                if let (JobStatus::Running { .. }, Some(fraction)) = (merge, self.progress.merge) {
+                    ui.add(ProgressBar::new(fraction).desired_width(200.0).show_percentage());
                 }
             }
-            ui.label(if MERGE.load(Ordering::Relaxed) { "合并中" } else { "合并结束" });
 
             ui.separator();
 
             ui.label("Whisper");
-            ComboBox::from_label("语言")
-                .selected_text(<&str>::from(self.config.lang))
-                .show_ui(ui, |ui| {
-                    ui.style_mut().wrap = Some(false);
-                    for i in Language::value_variants() {
-                        ui.selectable_value(&mut self.config.lang, *i, <&str>::from(*i));
-                    }
-                });
+            ui.horizontal(|ui| {
+                self.language_picker(ui);
+                ui.label("语言");
+            });
             ui.horizontal(|ui| {
                 ComboBox::from_label("模型")
                     .selected_text(format!("{}", self.config.model))
                     .show_ui(ui, |ui| {
                         ui.style_mut().wrap = Some(false);
                         for i in Model::value_variants() {
-                            ui.selectable_value(&mut self.config.model, *i, format!("{}", *i));
+                            if ui.selectable_value(&mut self.config.model, *i, format!("{}", *i)).changed() {
+                                self.worker.unload();
+                            }
                         }
                     });
                 if ui.button("下载模型").clicked() {
+                    self.worker.unload();
                     DOWNLOADING.store(false, Ordering::Relaxed);
                     let model = self.config.model;
                     if std::fs::remove_file(model.get_path()).is_err() {}
+                    let cancel = self.cancel.clone();
+                    let events = self.events.clone();
                     tokio::spawn(async move {
-                        if model.download().await.is_err() {
+                        if model.download(&cancel, &events).await.is_err() {
                             DOWNLOADING.store(false, Ordering::Relaxed);
                         }
                     });
                 }
+                if let Some(model) = whisper::loaded() {
+                    if ui.button("卸载模型").on_hover_text(format!("已加载 {}", model)).clicked() {
+                        self.worker.unload();
+                    }
+                }
             });
 
-            if ui.button("音频 -> 字幕").clicked() {
-                if !WHISPER.load(Ordering::Relaxed) && !DOWNLOADING.load(Ordering::Relaxed) {
-                    self.whisper();
+            if let Some(warning) = self.config.model.language_warning(self.config.lang) {
+                ui.horizontal_wrapped(|ui| {
+                    ui.colored_label(ui.visuals().warn_fg_color, warning);
+                    let multilingual = self.config.model.multilingual();
+                    if ui.small_button(format!("切换到 {}", multilingual)).clicked() {
+                        self.config.model = multilingual;
+                    }
+                });
+            }
+            if let Some(warning) = self.config.model.memory_warning() {
+                ui.colored_label(ui.visuals().warn_fg_color, warning);
+            }
+            if let Some((start, end)) = duration.and_then(|d| self.config.trim(Some(d)).ok().map(|(start, end)| (start, end.unwrap_or((d * 100.0) as i64)))) {
+                let (low, high) = self.settings.lock().unwrap().estimate(self.config.model, (end - start) as f64 / 100.0);
+                ui.label(format!("预计转换时间: {}", estimate_label(low, high)));
+            }
+
+            ui.horizontal(|ui| {
+                if ui.button("选择术语表").clicked() {
+                    self.open_glossary(self.files.clone());
+                }
+                let glossary = self.files.lock().unwrap().glossary.clone();
+                if let Some(ref p) = glossary {
+                    ui.label(p.file_name().unwrap().to_str().unwrap());
+                    if ui.small_button("清除").clicked() {
+                        self.files.lock().unwrap().glossary = None;
+                    }
+                } else {
+                    ui.label("术语表: None");
+                }
+            });
+
+            ui.horizontal(|ui| {
+                if ui.button("选择歌词").on_hover_text("每行一条字幕，只用音频确定时间").clicked() {
+                    self.open_lyrics(self.files.clone());
+                }
+                let lyrics = self.files.lock().unwrap().lyrics.clone();
+                if let Some(ref p) = lyrics {
+                    ui.label(p.file_name().unwrap().to_str().unwrap());
+                    if ui.small_button("清除").clicked() {
+                        self.files.lock().unwrap().lyrics = None;
+                    }
+                } else {
+                    ui.label("歌词: None");
                 }
+            });
+
+            ComboBox::from_label("采样策略")
+                .selected_text(sampling_label(self.config.sampling))
+                .show_ui(ui, |ui| {
+                    for sampling in [Sampling::default(), Sampling::BeamSearch { beam_size: 5, patience: -1.0 }, Sampling::Auto(AutoSampling::default())] {
+                        ui.selectable_value(&mut self.config.sampling, sampling, sampling_label(sampling));
+                    }
+                });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.config.json, "同时导出JSON");
+                ui.add_enabled(self.config.json, Checkbox::new(&mut self.config.word_json, "JSON按词")).on_hover_text("JSON按词记录时间，其他字幕仍按段落，只需识别一遍");
+                ui.checkbox(&mut self.config.txt, "同时导出纯文本");
+                ui.checkbox(&mut self.config.ass_karaoke, "同时导出卡拉OK字幕(ASS)");
+                ui.checkbox(&mut self.config.crlf, "SRT使用CRLF换行");
+                ui.checkbox(&mut self.config.lrc_millis, "LRC时间精确到毫秒");
+                ui.checkbox(&mut self.config.verbose, "输出Whisper调试信息");
+                ui.checkbox(&mut self.config.detect_languages, "逐段检测语言");
+                ui.checkbox(&mut self.config.clean_text, "整理标点空格");
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.config.bilingual, "双语字幕").on_hover_text("再识别一遍英文翻译，每条字幕上下两行显示原文和译文，耗时约为两倍");
+                ui.add_enabled_ui(self.config.bilingual, |ui| {
+                    let mut translation_first = self.config.bilingual_order == BilingualOrder::TranslationFirst;
+                    if ui.checkbox(&mut translation_first, "译文在上").changed() {
+                        self.config.bilingual_order = if translation_first { BilingualOrder::TranslationFirst } else { BilingualOrder::OriginalFirst };
+                    }
+                });
+            });
+            let mut assert = self.config.resample == Resample::Assert;
+            if ui.checkbox(&mut assert, "不重采样 (仅接受16kHz单声道WAV)").changed() {
+                self.config.resample = if assert { Resample::Assert } else { Resample::Auto };
+            }
+            ComboBox::from_label("VTT字幕行开头")
+                .selected_text(vtt_cue_label(self.config.vtt_cue))
+                .show_ui(ui, |ui| {
+                    for cue in [VttCue::Dash, VttCue::Plain, VttCue::Voice] {
+                        ui.selectable_value(&mut self.config.vtt_cue, cue, vtt_cue_label(cue));
+                    }
+                });
+            ComboBox::from_label("声道")
+                .selected_text(channel_label(self.config.channel))
+                .show_ui(ui, |ui| {
+                    for channel in [ChannelSelect::Mix, ChannelSelect::Left, ChannelSelect::Right] {
+                        ui.selectable_value(&mut self.config.channel, channel, channel_label(channel));
+                    }
+                });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.config.split, "按静音拆分字幕");
+                ui.add_enabled_ui(self.config.split, |ui| {
+                    ui.add(DragValue::new(&mut self.config.split_gap).clamp_range(500..=600000).speed(100).suffix(" ms"));
+                    ui.add(TextEdit::singleline(&mut self.config.numbering.prefix).hint_text("part").desired_width(50.0));
+                    ui.add(DragValue::new(&mut self.config.numbering.digits).clamp_range(1..=4).suffix(" 位"));
+                });
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("开始");
+                ui.add(TextEdit::singleline(&mut self.config.trim_start).hint_text("00:00:00").desired_width(70.0));
+                ui.label("结束");
+                ui.add(TextEdit::singleline(&mut self.config.trim_end).hint_text(duration.map(|d| clock((d * 100.0) as i64)).unwrap_or_default()).desired_width(70.0));
+                ui.checkbox(&mut self.config.trim_relative, "时间轴从开始处计");
+            });
+            let trim = self.config.trim(duration);
+            if let Err(ref e) = trim {
+                ui.colored_label(ui.visuals().error_fg_color, e);
             }
-            if DOWNLOADING.load(Ordering::Relaxed) {
+
+            let whisper = self.latest_job(&[JobKind::Whisper, JobKind::Playlist]).map(|job| job.status());
+            let idle = whisper.as_ref().is_none_or(JobStatus::is_finished) && !DOWNLOADING.load(Ordering::Relaxed);
+            if ui.add_enabled(trim.is_ok(), Button::new("音频 -> 字幕")).clicked() && idle {
+                let job = self.whisper();
+                self.push_job(job);
+            }
+            ui.horizontal(|ui| {
+                let preview = ui.add_enabled(trim.is_ok(), Button::new("预览")).on_hover_text("只转换开头几秒，用于在完整转换前确认语言和模型");
+                ui.add(DragValue::new(&mut self.config.preview_seconds).clamp_range(5..=600).suffix(" 秒"));
+                if preview.clicked() && idle {
+                    let job = self.whisper_preview();
+                    self.push_job(job);
+                }
+            });
+            if let Some((ref text, ref srt, ref chapters)) = *self.copies.lock().unwrap() {
                 ui.horizontal(|ui| {
-                    ui.label("下载模型中");
-                    ui.add(ProgressBar::new(DOWNLOADED.load(Ordering::Relaxed) as f32 / FILE_SIZE.load(Ordering::Relaxed) as f32).desired_width(200.0).show_percentage());
+                    if ui.button("复制文本").clicked() {
+                        ui.output_mut(|o| o.copied_text = text.clone());
+                    }
+                    if ui.button("复制 SRT").clicked() {
+                        ui.output_mut(|o| o.copied_text = srt.clone());
+                    }
+                    if ui.button("复制章节").on_hover_text("YouTube 简介用的章节列表，在较长的停顿处分章，停顿不够时每5分钟一章").clicked() {
+                        ui.output_mut(|o| o.copied_text = chapters.clone());
+                    }
                 });
             }
-            ui.label(if WHISPER.load(Ordering::Relaxed) { "转换中" } else { "转换结束" });
+            ComboBox::from_label("失败策略")
+                .selected_text(policy_label(self.config.policy))
+                .show_ui(ui, |ui| {
+                    for policy in [BatchPolicy::SkipAndContinue, BatchPolicy::StopOnError, BatchPolicy::RetryN(1), BatchPolicy::RetryN(3)] {
+                        ui.selectable_value(&mut self.config.policy, policy, policy_label(policy));
+                    }
+                });
+            ui.horizontal(|ui| {
+                let auto = format!("自动 ({})", self.config.model.default_concurrency());
+                ui.add(DragValue::new(&mut self.config.concurrency).clamp_range(0..=16).custom_formatter(move |n, _| if n == 0.0 { auto.clone() } else { n.to_string() }));
+                ui.label("并发数");
+            });
+            if ui.button("列表文件 -> 字幕").clicked() && idle {
+                let job = self.whisper_playlist();
+                self.push_job(job);
+            }
+            match *self.batch.lock().unwrap() {
+                Some(Ok(ref report)) => {
+                    ui.label(format!("列表: 成功 {} 失败 {} 跳过 {}", report.succeeded.len(), report.failed.len(), report.skipped.len()))
+                        .on_hover_text(report.failed.iter().map(|(p, e)| format!("{}: {}", p.display(), e)).collect::<Vec<_>>().join("\n"));
+                }
+                Some(Err(ref e)) => {
+                    ui.label(format!("列表: {}", e));
+                }
+                None => {}
+            }
+            if let (true, Some((model, done, total))) = (DOWNLOADING.load(Ordering::Relaxed), self.progress.download) {
+                ui.horizontal(|ui| {
+                    ui.label(format!("下载模型 {} 中", model));
+                    match total {
+                        Some(total) => ui.add(ProgressBar::new(done as f32 / total.max(1) as f32).desired_width(200.0).show_percentage()),
+                        None => ui.label(format!("{} MB", done >> 20)),
+                    };
+                });
+            }
+            if let Some(ref whisper) = whisper {
+                status_ui(ui, whisper, "转换");
+                if let (JobStatus::Running { .. }, Some(percent)) = (whisper, self.progress.transcribe) {
+                    ui.add(ProgressBar::new(percent as f32 / 100.0).desired_width(200.0).show_percentage());
+                    if let Some(ref segment) = self.progress.segment {
+                        ui.label(segment);
+                    }
+                }
+            }
+            if (job::any_running() || DOWNLOADING.load(Ordering::Relaxed)) && ui.button("停止全部").clicked() {
+                self.cancel_all();
+            }
+
+            ui.horizontal(|ui| {
+                let mut prevent_sleep = !self.settings.lock().unwrap().allow_sleep;
+                if ui.checkbox(&mut prevent_sleep, "任务进行中阻止系统休眠").changed() {
+                    let mut settings = self.settings.lock().unwrap();
+                    settings.allow_sleep = !prevent_sleep;
+                    settings.save();
+                }
+                let mut check_update = !self.settings.lock().unwrap().skip_update_check;
+                if ui.checkbox(&mut check_update, "启动时检查更新").changed() {
+                    let mut settings = self.settings.lock().unwrap();
+                    settings.skip_update_check = !check_update;
+                    settings.save();
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("日志").clicked() {
+                    self.show_log = !self.show_log;
+                }
+                if ui.button("打开日志目录").clicked() {
+                    open_log_dir();
+                }
+                if ui.button("关于/诊断").clicked() {
+                    self.show_about = !self.show_about;
+                }
+            });
+        });
+
+        let mut open = self.show_preview;
+        egui::Window::new("字幕预览")
+            .open(&mut open)
+            .default_size([360.0, 300.0])
+            .show(ctx, |ui| self.preview_ui(ui));
+        self.show_preview = open;
+
+        egui::Window::new("关于/诊断")
+            .open(&mut self.show_about)
+            .show(ctx, |ui| {
+                ui.monospace(SYSTEM_INFO.as_str());
+                if ui.button("复制").clicked() {
+                    ui.output_mut(|o| o.copied_text = SYSTEM_INFO.clone());
+                }
+            });
+
+        egui::Window::new("日志")
+            .open(&mut self.show_log)
+            .default_size([380.0, 300.0])
+            .show(ctx, |ui| {
+                ScrollArea::both().stick_to_bottom(true).show(ui, |ui| {
+                    for line in LINES.lock().unwrap().iter() {
+                        ui.monospace(line);
+                    }
+                });
+            });
+    }
+
+    // the whisper worker is joined when the app is dropped, stop what it's doing so that doesn't wait for a whole file
+    fn on_exit(&mut self, _: Option<&eframe::glow::Context>) {
+        self.cancel_all();
+        self.save_config_file();
+    }
+}
+
+impl Conv {
+    // the languages to show and how many of them at the start are recently used ones
+    fn language_candidates(&self) -> (Vec<Language>, usize) {
+        // .en 模型只能识别英语
+        let english_only = self.config.model.is_english_only();
+        let allowed = |l: &&Language| !english_only || matches!(l, Language::Auto | Language::English);
+        let filter = self.lang_picker.filter.trim().to_lowercase();
+        if filter.is_empty() {
+            let recent = self.settings.lock().unwrap().recent_languages();
+            let recent_count = recent.iter().filter(allowed).count();
+            let candidates = recent
+                .iter()
+                .chain(LANGUAGES.iter().filter(|l| !recent.contains(l)))
+                .filter(allowed)
+                .copied()
+                .collect();
+            return (candidates, recent_count);
+        }
+        let candidates = LANGUAGES
+            .iter()
+            .filter(allowed)
+            .filter(|l| {
+                [<&str>::from(**l), l.name(), l.native_name()]
+                    .iter()
+                    .any(|s| s.to_lowercase().contains(&filter))
+            })
+            .copied()
+            .collect();
+        (candidates, 0)
+    }
+
+    fn select_language(&mut self, lang: Language) {
+        self.config.lang = lang;
+        let mut settings = self.settings.lock().unwrap();
+        settings.push_recent_language(lang);
+        settings.save();
+        drop(settings);
+        self.lang_picker.open = false;
+    }
+
+    fn language_picker(&mut self, ui: &mut Ui) {
+        let button = ui.add(Button::new(self.config.lang.label()).min_size(Vec2::new(250.0, 0.0)));
+        if button.clicked() {
+            self.lang_picker = LanguagePicker { open: !self.lang_picker.open, ..Default::default() };
+        }
+        if !self.lang_picker.open {
+            return;
+        }
+
+        let (candidates, recent) = self.language_candidates();
+        let mut chosen = None;
+        let area = Area::new(ui.make_persistent_id("language_picker"))
+            .order(Order::Foreground)
+            .constrain(true)
+            .fixed_pos(button.rect.left_bottom())
+            .show(ui.ctx(), |ui| {
+                PopupFrame::popup(ui.style()).show(ui, |ui| {
+                    ui.set_width(280.0);
+                    let edit = ui.text_edit_singleline(&mut self.lang_picker.filter);
+                    edit.request_focus();
+                    if edit.changed() {
+                        self.lang_picker.cursor = 0;
+                    }
+
+                    let (down, up, enter) = ui.input(|i| (i.key_pressed(Key::ArrowDown), i.key_pressed(Key::ArrowUp), i.key_pressed(Key::Enter)));
+                    let cursor = &mut self.lang_picker.cursor;
+                    if down {
+                        *cursor = (*cursor + 1).min(candidates.len().saturating_sub(1));
+                    }
+                    if up {
+                        *cursor = cursor.saturating_sub(1);
+                    }
+                    if enter {
+                        chosen = candidates.get(*cursor).copied();
+                    }
+
+                    ScrollArea::vertical().max_height(200.0).show(ui, |ui| {
+                        let mut heading = None;
+                        for (i, lang) in candidates.iter().enumerate() {
+                            let group = if i < recent { Some("最近使用") } else { lang.group().map(|g| g.label()) };
+                            if let Some(label) = group.filter(|_| group != heading) {
+                                ui.label(RichText::new(label).small().weak());
+                            }
+                            heading = group;
+                            let label = ui.selectable_label(i == *cursor, lang.label());
+                            if i == *cursor && (down || up) {
+                                label.scroll_to_me(None);
+                            }
+                            if label.clicked() {
+                                chosen = Some(*lang);
+                            }
+                        }
+                    });
+                });
+            });
+
+        if let Some(lang) = chosen {
+            self.select_language(lang);
+        } else if ui.input(|i| i.key_pressed(Key::Escape)) || (button.clicked_elsewhere() && area.response.clicked_elsewhere()) {
+            self.lang_picker.open = false;
+        }
+    }
+
+    fn preview_ui(&mut self, ui: &mut Ui) {
+        let audio = self.files.lock().unwrap().audio.clone();
+        let mut player = self.player.lock().unwrap();
+        let pos = player.position();
+        let mut seek = None;
+
+        ui.horizontal(|ui| {
+            if ui.button("播放").clicked() {
+                seek = Some(pos.unwrap_or(0));
+            }
+            if ui.button("停止").clicked() {
+                player.stop();
+            }
+            ui.label(pos.map(timestamp).unwrap_or_default());
         });
+        if let Some(ref e) = self.preview_error {
+            ui.label(format!("错误: {}", e));
+        }
+
+        let mut preview = self.preview.lock().unwrap();
+        match *self.waveform.lock().unwrap() {
+            Some((ref path, ref envelope)) if Some(path) == audio.as_ref() => {
+                waveform_ui(ui, envelope, preview.as_mut(), pos);
+            }
+            _ => {
+                if audio.is_some() && ui.button("生成波形").clicked() {
+                    self.load_waveform();
+                }
+            }
+        }
+
+        if let Some(ref mut preview) = *preview {
+            let active = pos.map(|p| preview.active(p)).unwrap_or_default();
+            let follow = active.first().copied();
+            let scroll_to = preview.scroll_to.take().or(follow.filter(|_| follow != preview.followed));
+            preview.followed = follow;
+
+            ScrollArea::vertical().show(ui, |ui| {
+                for (i, cue) in preview.cues.iter().enumerate() {
+                    let checked = active.contains(&i) || preview.selected == Some(i);
+                    let label = ui.selectable_label(checked, format!("{} {}", timestamp(cue.start), cue.text.trim()));
+                    if scroll_to == Some(i) {
+                        label.scroll_to_me(Some(Align::Center));
+                    }
+                    if label.clicked() {
+                        preview.selected = Some(i);
+                        seek = Some(cue.start);
+                    }
+                }
+            });
+        } else {
+            ui.label("无字幕");
+        }
+
+        if let (Some(from), Some(ref audio)) = (seek, audio) {
+            if player.play(audio, from).is_err() {
+                self.preview_error = Some("无法启动ffplay".to_string());
+            }
+        }
+    }
+}
+
+fn waveform_ui(ui: &mut Ui, envelope: &Envelope, preview: Option<&mut Preview>, pos: Option<i64>) {
+    let (response, painter) = ui.allocate_painter(Vec2::new(ui.available_width(), 80.0), Sense::click());
+    let rect = response.rect;
+    let duration = envelope.duration.max(1) as f32;
+    let x = |t: i64| rect.left() + rect.width() * t as f32 / duration;
+
+    painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+    if let Some(ref preview) = preview {
+        for cue in &preview.cues {
+            let shade = Rect::from_x_y_ranges(x(cue.start)..=x(cue.end), rect.y_range());
+            painter.rect_filled(shade, 0.0, Color32::from_rgba_unmultiplied(100, 150, 250, 40));
+        }
+    }
+    let columns = rect.width().max(1.0) as usize;
+    for col in 0..columns {
+        let (min, max) = envelope.range(col as f32 / columns as f32, (col + 1) as f32 / columns as f32);
+        let px = rect.left() + col as f32 + 0.5;
+        painter.vline(px, (rect.center().y - max * rect.height() / 2.0)..=(rect.center().y - min * rect.height() / 2.0), Stroke::new(1.0, ui.visuals().text_color()));
+    }
+    if let Some(pos) = pos {
+        painter.vline(x(pos), rect.y_range(), Stroke::new(1.0, Color32::RED));
+    }
+
+    let Some(preview) = preview else { return; };
+    let time_at = |p: Pos2| ((p.x - rect.left()) / rect.width() * duration) as i64;
+    if let Some(hover) = response.hover_pos() {
+        let text = preview.active(time_at(hover)).iter().map(|&i| preview.cues[i].text.trim()).collect::<Vec<_>>().join("\n");
+        if !text.is_empty() {
+            response.clone().on_hover_text_at_pointer(text);
+        }
+    }
+    if response.clicked() {
+        if let Some(click) = response.interact_pointer_pos() {
+            preview.select_at(time_at(click));
+        }
+    }
+}
+
+fn status_ui(ui: &mut Ui, status: &JobStatus, action: &str) {
+    match status {
+        JobStatus::Queued => {
+            ui.label(format!("等待{}", action));
+        }
+        JobStatus::Running { file: None } => {
+            ui.label(format!("{}中", action));
+        }
+        JobStatus::Running { file: Some(file) } => {
+            ui.label(format!("{}中: {}", action, file.file_name().unwrap_or_default().to_string_lossy()));
+        }
+        JobStatus::Succeeded(output) => {
+            ui.colored_label(Color32::from_rgb(80, 160, 80), format!("{}成功: {}", action, output.display()));
+        }
+        JobStatus::Failed(reason) => {
+            ui.colored_label(ui.visuals().error_fg_color, format!("{}失败: {}", action, reason));
+        }
+    }
+}
+
+fn alignment_label(alignment: u8) -> &'static str {
+    match alignment {
+        5 => "中间",
+        8 => "顶部",
+        _ => "底部",
+    }
+}
+
+fn sampling_label(sampling: Sampling) -> &'static str {
+    match sampling {
+        Sampling::Greedy { .. } => "贪心",
+        Sampling::BeamSearch { .. } => "束搜索",
+        Sampling::Auto(_) => "自动",
+    }
+}
+
+fn channel_label(channel: ChannelSelect) -> &'static str {
+    match channel {
+        ChannelSelect::Mix => "混合为单声道",
+        ChannelSelect::Left => "仅左声道",
+        ChannelSelect::Right => "仅右声道",
+    }
+}
+
+fn vtt_cue_label(cue: VttCue) -> &'static str {
+    match cue {
+        VttCue::Dash => "横线 \"- \"",
+        VttCue::Plain => "不加前缀",
+        VttCue::Voice => "说话人标签 <v>",
+    }
+}
+
+fn policy_label(policy: BatchPolicy) -> String {
+    match policy {
+        BatchPolicy::StopOnError => "出错即停止".to_string(),
+        BatchPolicy::SkipAndContinue => "跳过并继续".to_string(),
+        BatchPolicy::RetryN(n) => format!("重试{}次", n),
+    }
+}
+
+fn timestamp(centis: i64) -> String {
+    format!("{:02}:{:02}.{:02}", centis / 100 / 60, centis / 100 % 60, centis % 100)
+}
+
+fn clock(centis: i64) -> String {
+    format!("{:02}:{:02}:{:02}", centis / 100 / 3600, centis / 100 % 3600 / 60, centis / 100 % 60)
+}
+
+fn estimate_label(low: f64, high: f64) -> String {
+    let minutes = |s: f64| (s / 60.0).round().max(1.0);
+    if minutes(high) <= minutes(low) * 1.5 {
+        format!("~{} 分钟", minutes((low + high) / 2.0))
+    } else {
+        format!("{}–{} 分钟", minutes(low), minutes(high))
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,68 @@
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::CLIENT;
+use crate::settings::Settings;
+
+const LATEST_RELEASE: &str = "https://api.github.com/repos/yk0n9/conv/releases/latest";
+const CHECK_INTERVAL: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Release {
+    #[serde(rename = "tag_name")]
+    pub tag: String,
+    #[serde(rename = "html_url")]
+    pub url: String,
+}
+
+impl Release {
+    // v0.2.0 > 0.1.0
+    pub fn is_newer(&self) -> bool {
+        let version = |s: &str| s.trim_start_matches('v').split('.').map(|n| n.parse::<u64>().unwrap_or(0)).collect::<Vec<_>>();
+        version(&self.tag) > version(env!("CARGO_PKG_VERSION"))
+    }
+}
+
+async fn fetch() -> Option<Release> {
+    let text = CLIENT
+        .get(LATEST_RELEASE)
+        .header("User-Agent", "conv")
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?
+        .text()
+        .await
+        .ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+// asks GitHub at most once a day, otherwise and when offline the cached release is used
+pub async fn check(settings: Arc<Mutex<Settings>>, update: Arc<Mutex<Option<Release>>>) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    let (skip, last_check) = {
+        let settings = settings.lock().unwrap();
+        (settings.skip_update_check, settings.last_update_check)
+    };
+    if skip {
+        return;
+    }
+    if now.saturating_sub(last_check) >= CHECK_INTERVAL {
+        if let Some(release) = fetch().await {
+            let mut settings = settings.lock().unwrap();
+            settings.last_update_check = now;
+            settings.latest_release = Some(release);
+            settings.save();
+        }
+    }
+
+    let settings = settings.lock().unwrap();
+    if let Some(ref release) = settings.latest_release {
+        if release.is_newer() && settings.dismissed_release.as_ref() != Some(&release.tag) {
+            *update.lock().unwrap() = Some(release.clone());
+        }
+    }
+}
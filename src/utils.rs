@@ -1,83 +1,707 @@
 use std::env::temp_dir;
 use std::fs::File;
-use std::path::Path;
-use std::process::{Child, Command};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::io::{Read, Seek, SeekFrom};
+use std::process::{Child, Command, ExitStatus};
 use std::process::Stdio;
 use std::sync::atomic::AtomicBool;
+use std::time::Duration;
 
-use anyhow::{anyhow, Result};
 use audrey::Reader;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
 
+use crate::error::ConvError;
+use crate::event::{AppEvent, Events};
+use crate::job::CancelToken;
+use crate::whisper::Transcript;
+
+#[deprecated(note = "use the JobHandle returned when starting the job")]
 pub static WHISPER: AtomicBool = AtomicBool::new(false);
 pub static DOWNLOADING: AtomicBool = AtomicBool::new(false);
+#[deprecated(note = "use the JobHandle returned when starting the job")]
 pub static MERGE: AtomicBool = AtomicBool::new(false);
+static FFMPEG: OnceCell<PathBuf> = OnceCell::new();
+
+pub const SAMPLE_RATE: usize = 16000;
+pub const AUDIO_EXTENSIONS: [&str; 2] = ["mp3", "wav"];
+/// transcribed from their first audio track, extracted with ffmpeg
+pub const VIDEO_EXTENSIONS: [&str; 5] = ["mp4", "mkv", "mov", "webm", "avi"];
+
+pub fn is_media(path: &Path) -> bool {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+    AUDIO_EXTENSIONS.contains(&ext.as_str()) || VIDEO_EXTENSIONS.contains(&ext.as_str())
+}
+
+/// audio.mp3 -> audio.mp4, but video.mp4 -> video.merged.mp4 instead of overwriting the input
+pub fn merged_path(input: &Path, ext: &str) -> PathBuf {
+    let output = input.with_extension(ext);
+    if output == input {
+        input.with_extension(format!("merged.{}", ext))
+    } else {
+        output
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Background {
+    Image(PathBuf),
+    /// looped until the audio ends
+    Video(PathBuf),
+    Color([u8; 3]),
+    Waveform,
+}
+
+/// where libass puts the burned in subtitles, alignment is a numpad position (2 bottom, 5 middle, 8 top)
+/// and margin_v the distance from the top or bottom edge in libass' 288 line script resolution
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubtitleStyle {
+    pub alignment: u8,
+    pub margin_v: u32,
+}
+
+// libass defaults
+impl Default for SubtitleStyle {
+    fn default() -> Self {
+        Self { alignment: 2, margin_v: 10 }
+    }
+}
+
+impl SubtitleStyle {
+    // subtitles=sub.srt:force_style='Alignment=8,MarginV=40', left out for the defaults
+    fn filter(&self, subtitle: &str) -> String {
+        if *self == Self::default() {
+            format!("subtitles={}", subtitle)
+        } else {
+            format!("subtitles={}:force_style='Alignment={},MarginV={}'", subtitle, self.alignment, self.margin_v)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct VideoOptions {
+    pub style: SubtitleStyle,
+    /// (width, height), images and videos keep their own size and color/waveform are 1280x720 when unset
+    pub resolution: Option<(u32, u32)>,
+    /// keep the subtitle as a track that players can turn off instead of burning it into the picture
+    pub soft_subtitle: bool,
+}
+
+pub fn merge_args(audio: &str, background: &Background, subtitle: Option<&str>, options: &VideoOptions, output: &str) -> Vec<String> {
+    let (width, height) = options.resolution.unwrap_or((1280, 720));
+    let mut filters = vec![];
+    if options.resolution.is_some() && matches!(background, Background::Image(_) | Background::Video(_)) {
+        filters.push(format!("scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2", w = width, h = height));
+    }
+    if let Some(subtitle) = subtitle.filter(|_| !options.soft_subtitle) {
+        filters.push(options.style.filter(subtitle));
+    }
+    let mut args = vec!["-y".to_string()];
+    match background {
+        // ffmpeg -loop 1 -framerate 30 -i image.png -i audio.mp3 -vf subtitles=sub.srt ...
+        Background::Image(image) => {
+            args.extend(["-loop", "1", "-framerate", "30", "-i", &image.to_string_lossy(), "-i", audio].map(str::to_string));
+        }
+        // ffmpeg -stream_loop -1 -i video.mp4 -i audio.mp3 -vf subtitles=sub.srt ...
+        Background::Video(video) => {
+            args.extend(["-stream_loop", "-1", "-i", &video.to_string_lossy(), "-i", audio].map(str::to_string));
+        }
+        // ffmpeg -f lavfi -i color=c=0x000000:s=1280x720:r=30 -i audio.mp3 -vf subtitles=sub.srt ...
+        Background::Color([r, g, b]) => {
+            let color = format!("color=c=0x{:02x}{:02x}{:02x}:s={}x{}:r=30", r, g, b, width, height);
+            args.extend(["-f", "lavfi", "-i", &color, "-i", audio].map(str::to_string));
+        }
+        // ffmpeg -i audio.mp3 -filter_complex [0:a]showwaves=...,subtitles=sub.srt[v] -map [v] -map 0:a ...
+        Background::Waveform => {
+            args.extend(["-i", audio].map(str::to_string));
+        }
+    }
+    // a soft subtitle is the input after the audio
+    let subtitle_input = match background {
+        Background::Waveform => 1,
+        _ => 2,
+    };
+    if let Some(subtitle) = subtitle.filter(|_| options.soft_subtitle) {
+        args.extend(["-i", subtitle].map(str::to_string));
+    }
+    match background {
+        Background::Waveform => {
+            let filter = std::iter::once(format!("[0:a]showwaves=s={}x{}:mode=cline:rate=30,format=yuv420p", width, height))
+                .chain(filters)
+                .collect::<Vec<_>>()
+                .join(",");
+            args.extend(["-filter_complex", &format!("{}[v]", filter), "-map", "[v]", "-map", "0:a"].map(str::to_string));
+        }
+        _ => {
+            if !filters.is_empty() {
+                args.extend(["-vf".to_string(), filters.join(",")]);
+            }
+            args.extend(["-map", "0:v:0", "-map", "1:a:0"].map(str::to_string));
+        }
+    }
+    if subtitle.is_some() && options.soft_subtitle {
+        // mp4 only takes mov_text
+        let codec = if output.to_lowercase().ends_with(".mkv") { "srt" } else { "mov_text" };
+        args.extend(["-map", &format!("{}:s", subtitle_input), "-c:s", codec].map(str::to_string));
+    }
+    args.extend(["-c:v", "libx264", "-c:a", "aac", "-pix_fmt", "yuv420p", "-r", "30", "-shortest", output].map(str::to_string));
+    args
+}
+
+/// ffmpeg -i "my song.mp3" ... as it would be typed into a shell
+pub fn command_line(program: &str, args: &[String]) -> String {
+    let quote = |arg: &str| {
+        if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_.,:=/\\".contains(c)) {
+            arg.to_string()
+        } else {
+            format!("\"{}\"", arg.replace('"', "\\\""))
+        }
+    };
+    std::iter::once(program).chain(args.iter().map(String::as_str)).map(quote).collect::<Vec<_>>().join(" ")
+}
+
+pub fn set_ffmpeg(path: PathBuf) {
+    let _ = FFMPEG.set(path);
+}
+
+/// ffprobe and ffplay are looked up next to a configured ffmpeg, otherwise on PATH
+pub fn ffmpeg_tool(name: &str) -> PathBuf {
+    match FFMPEG.get() {
+        Some(ffmpeg) if name == "ffmpeg" => ffmpeg.clone(),
+        Some(ffmpeg) => match ffmpeg.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            Some(dir) => dir.join(name).with_extension(std::env::consts::EXE_EXTENSION),
+            None => PathBuf::from(name),
+        },
+        None => PathBuf::from(name),
+    }
+}
 
 #[inline]
-pub fn merge(audio: &str, image: &str, subtitle: &str, output: &str) -> std::io::Result<Child> {
-    Command::new("ffmpeg")
-        .args([
-            "-y",
-            "-loop",
-            "1",
-            "-framerate",
-            "30",
-            "-i",
-            image,
-            "-i",
-            audio,
-            "-vf",
-            &format!("subtitles={}", subtitle),
-            "-c:v",
-            "libx264",
-            "-c:a",
-            "aac",
-            "-pix_fmt",
-            "yuv420p",
-            "-r",
-            "30",
-            "-shortest",
-            output,
-        ])
+pub fn merge(args: &[String]) -> std::io::Result<Child> {
+    info!("{}", command_line("ffmpeg", args));
+    Command::new(ffmpeg_tool("ffmpeg"))
+        .args(args)
+        .stderr(Stdio::piped())
         .spawn()
 }
 
-// ffmpeg -i input.mp3 -ar 16000 output.wav
-fn use_ffmpeg<P: AsRef<Path>>(input_path: P) -> Result<Vec<i16>> {
+/// 20ms frames, loudest 10% against quietest 10% in dB
+pub fn estimate_snr(samples: &[f32]) -> f32 {
+    let mut energy = samples
+        .chunks(SAMPLE_RATE / 50)
+        .map(|frame| (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt())
+        .collect::<Vec<_>>();
+    if energy.is_empty() {
+        return 0.0;
+    }
+    energy.sort_by(|a, b| a.total_cmp(b));
+    let noise = energy[energy.len() / 10].max(1e-6);
+    let signal = energy[energy.len() * 9 / 10];
+    20.0 * (signal / noise).log10()
+}
+
+// stderr is drained on another thread so a chatty ffmpeg or yt-dlp can't fill the pipe while we poll
+pub(crate) fn wait(mut child: Child, cancel: &CancelToken, mut on_line: impl FnMut(&str) + Send + 'static) -> Result<(ExitStatus, String), ConvError> {
+    let mut stderr = child.stderr.take();
+    let reader = std::thread::spawn(move || {
+        let mut output = vec![];
+        let mut buffer = [0; 4096];
+        // progress lines end with \r, everything else with \n
+        let mut line_start = 0;
+        while let Some(Ok(n @ 1..)) = stderr.as_mut().map(|stderr| stderr.read(&mut buffer)) {
+            output.extend_from_slice(&buffer[..n]);
+            while let Some(end) = output[line_start..].iter().position(|&b| b == b'\r' || b == b'\n') {
+                on_line(&String::from_utf8_lossy(&output[line_start..line_start + end]));
+                line_start += end + 1;
+            }
+        }
+        String::from_utf8_lossy(&output).into_owned()
+    });
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if cancel.is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(ConvError::Cancelled);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
+    Ok((status, reader.join().unwrap_or_default()))
+}
+
+// frame=  750 fps=120 q=28.0 size=    1024kB time=00:00:25.00 bitrate= 335.5kbits/s speed=4.01x
+fn ffmpeg_time(line: &str) -> Option<f64> {
+    let time = line.split_once("time=")?.1.split_whitespace().next()?;
+    let mut parts = time.split(':').map(|part| part.parse::<f64>().ok());
+    let (hours, minutes, seconds) = (parts.next()??, parts.next()??, parts.next()??);
+    Some(hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+/// output defaults to merged_path(audio, "mp4")
+pub fn merge_video(audio: &Path, background: &Background, subtitle: Option<&Path>, options: &VideoOptions, output: Option<&Path>, cancel: &CancelToken, events: &Events) -> Result<PathBuf, ConvError> {
+    events.finish(render_video(audio, background, subtitle, options, output, cancel, events), |output| vec![output.clone()])
+}
+
+fn render_video(audio: &Path, background: &Background, subtitle: Option<&Path>, options: &VideoOptions, output: Option<&Path>, cancel: &CancelToken, events: &Events) -> Result<PathBuf, ConvError> {
+    let to_str = |p: &Path| p.to_str().map(str::to_string).ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, format!("路径包含无效字符: {}", p.display())));
+    let current = std::env::current_dir()?;
+    let subtitle_cache = subtitle.map(|subtitle| Path::new(&uuid::Uuid::new_v4().to_string()).with_extension(subtitle.extension().unwrap_or_default()));
+    if let (Some(subtitle), Some(ref cache)) = (subtitle, &subtitle_cache) {
+        std::fs::copy(subtitle, current.join(cache))?;
+    }
+    let output = output.map_or_else(|| merged_path(audio, "mp4"), Path::to_path_buf);
+
+    if let Background::Image(ref path) | Background::Video(ref path) = background {
+        to_str(path)?;
+    }
+    let subtitle_arg = subtitle_cache.as_deref().map(to_str).transpose()?;
+    let args = merge_args(&to_str(audio)?, background, subtitle_arg.as_deref(), options, &to_str(&output)?);
+    let note = match (&subtitle_arg, subtitle) {
+        (Some(arg), Some(subtitle)) => format!("({} is a copy of {})", arg, subtitle.display()),
+        _ => String::new(),
+    };
+    let result = run_ffmpeg(&args, &note, audio, cancel, events);
+    // a leftover copy is not worth hiding ffmpeg's error for
+    if let Some(cache) = subtitle_cache {
+        if let Err(e) = std::fs::remove_file(current.join(&cache)) {
+            warn!("failed to remove {}: {}", cache.display(), e);
+        }
+    }
+    result.map(|_| output)
+}
+
+fn run_ffmpeg(args: &[String], note: &str, audio: &Path, cancel: &CancelToken, events: &Events) -> Result<(), ConvError> {
+    let duration = audio_info(audio).ok().map(|info| info.duration.as_secs_f64()).filter(|d| *d > 0.0);
+    let progress = events.clone();
+    let on_line = move |line: &str| {
+        if let (Some(duration), Some(time)) = (duration, ffmpeg_time(line)) {
+            progress.send(AppEvent::MergeProgress { fraction: (time / duration).clamp(0.0, 1.0) as f32 });
+        }
+    };
+    let (status, stderr) = merge(args).map_err(ConvError::from_spawn).and_then(|child| wait(child, cancel, on_line))?;
+    if !status.success() {
+        let tail = stderr.lines().rev().take(20).collect::<Vec<_>>().into_iter().rev().collect::<Vec<_>>().join("\n");
+        return Err(ConvError::FfmpegFailed {
+            command: format!("{}\n{}", command_line("ffmpeg", args), note),
+            stderr: format!("ffmpeg exited with {}\n{}", status, tail),
+        });
+    }
+    Ok(())
+}
+
+/// ffmpeg -i audio.mp3 -i sub.srt -i chapters.txt -map 0:a -map 1:s -map_chapters 2 -c:a copy -c:s srt audio.mkv
+pub fn mkv_args(audio: &str, subtitle: &str, metadata: &str, output: &str) -> Vec<String> {
+    ["-y", "-i", audio, "-i", subtitle, "-i", metadata, "-map", "0:a", "-map", "1:s", "-map_chapters", "2", "-c:a", "copy", "-c:s", "srt", output]
+        .map(str::to_string)
+        .to_vec()
+}
+
+/// audio untouched, the subtitle as a soft track and a chapter wherever the speech pauses for chapter_gap centiseconds
+/// output defaults to merged_path(audio, "mkv")
+pub fn merge_mkv(audio: &Path, subtitle: &Path, chapter_gap: i64, output: Option<&Path>, cancel: &CancelToken, events: &Events) -> Result<PathBuf, ConvError> {
+    events.finish(remux_mkv(audio, subtitle, chapter_gap, output, cancel, events), |output| vec![output.clone()])
+}
+
+fn remux_mkv(audio: &Path, subtitle: &Path, chapter_gap: i64, output: Option<&Path>, cancel: &CancelToken, events: &Events) -> Result<PathBuf, ConvError> {
+    let to_str = |p: &Path| p.to_str().map(str::to_string).ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, format!("路径包含无效字符: {}", p.display())));
+    let transcript = Transcript::from_file(subtitle).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, format!("{}: {}", subtitle.display(), e)))?;
+    let end = audio_info(audio).ok().map(|info| (info.duration.as_secs_f64() * 100.0) as i64);
+    let metadata = temp_dir().join(format!("{}.txt", uuid::Uuid::new_v4()));
+    std::fs::write(&metadata, transcript.to_ffmetadata(chapter_gap, end))?;
+    let output = output.map_or_else(|| merged_path(audio, "mkv"), Path::to_path_buf);
+
+    let args = mkv_args(&to_str(audio)?, &to_str(subtitle)?, &to_str(&metadata)?, &to_str(&output)?);
+    let note = format!("({} holds the chapters)", metadata.display());
+    let result = run_ffmpeg(&args, &note, audio, cancel, events);
+    let _ = std::fs::remove_file(&metadata);
+    result?;
+    Ok(output)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioInfo {
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub duration: Duration,
+}
+
+/// MB
+#[cfg(target_os = "linux")]
+pub fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let kb = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kb / 1024)
+}
+
+#[cfg(windows)]
+pub fn available_memory() -> Option<u64> {
+    #[repr(C)]
+    struct MemoryStatusEx {
+        length: u32,
+        memory_load: u32,
+        total_phys: u64,
+        avail_phys: u64,
+        total_page_file: u64,
+        avail_page_file: u64,
+        total_virtual: u64,
+        avail_virtual: u64,
+        avail_extended_virtual: u64,
+    }
+    extern "system" {
+        fn GlobalMemoryStatusEx(buffer: *mut MemoryStatusEx) -> i32;
+    }
+
+    let mut status: MemoryStatusEx = unsafe { std::mem::zeroed() };
+    status.length = std::mem::size_of::<MemoryStatusEx>() as u32;
+    match unsafe { GlobalMemoryStatusEx(&mut status) } {
+        0 => None,
+        _ => Some(status.avail_phys / 1024 / 1024),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn available_memory() -> Option<u64> {
+    None
+}
+
+const WAVE_FORMAT_ALAW: u16 = 6;
+const WAVE_FORMAT_MULAW: u16 = 7;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+// G.711 as used by telephony wav, 8 bit codes expanded to 16 bit linear pcm
+fn mulaw_to_linear(code: u8) -> i16 {
+    let code = !code;
+    let exponent = (code >> 4) & 0x07;
+    let magnitude = ((((code & 0x0f) as i16) << 3) + 0x84) << exponent;
+    if code & 0x80 != 0 { 0x84 - magnitude } else { magnitude - 0x84 }
+}
+
+fn alaw_to_linear(code: u8) -> i16 {
+    let code = code ^ 0x55;
+    let exponent = (code >> 4) & 0x07;
+    let mantissa = ((code & 0x0f) as i16) << 4;
+    let magnitude = match exponent {
+        0 => mantissa + 8,
+        e => (mantissa + 0x108) << (e - 1),
+    };
+    if code & 0x80 != 0 { magnitude } else { -magnitude }
+}
+
+struct G711 {
+    info: AudioInfo,
+    decode: fn(u8) -> i16,
+    len: u32,
+}
+
+// hound only knows pcm and float, so a-law/μ-law wav is decoded here. None for any other wav,
+// the file is left positioned at the start of the samples
+fn g711_header(file: &mut File) -> std::io::Result<Option<G711>> {
+    let mut riff = [0; 12];
+    if file.read_exact(&mut riff).is_err() || &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Ok(None);
+    }
+    let mut format = vec![];
+    let mut header = [0; 8];
+    let size = loop {
+        if file.read_exact(&mut header).is_err() {
+            return Ok(None);
+        }
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        match &header[0..4] {
+            b"data" => break size,
+            b"fmt " => {
+                format = vec![0; size as usize];
+                file.read_exact(&mut format)?;
+                file.seek(SeekFrom::Current(size as i64 % 2))?;
+            }
+            _ => {
+                file.seek(SeekFrom::Current(size as i64 + size as i64 % 2))?;
+            }
+        }
+    };
+    let u16_at = |i: usize| format.get(i..i + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at = |i: usize| format.get(i..i + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let tag = match u16_at(0) {
+        // the real format tag is the first two bytes of the sub format guid
+        Some(WAVE_FORMAT_EXTENSIBLE) => u16_at(24),
+        tag => tag,
+    };
+    let decode: fn(u8) -> i16 = match tag {
+        Some(WAVE_FORMAT_MULAW) => mulaw_to_linear,
+        Some(WAVE_FORMAT_ALAW) => alaw_to_linear,
+        _ => return Ok(None),
+    };
+    let (Some(channels), Some(sample_rate)) = (u16_at(2), u32_at(4)) else {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, "invalid wav header"));
+    };
+    let info = AudioInfo {
+        sample_rate,
+        channels: channels as u32,
+        duration: Duration::from_secs_f64(size as f64 / channels.max(1) as f64 / sample_rate.max(1) as f64),
+    };
+    Ok(Some(G711 { info, decode, len: size }))
+}
+
+// the interleaved linear samples with the rate and channels they came at, telephony files are usually 8kHz
+fn read_g711(path: &Path) -> std::io::Result<Option<(AudioInfo, Vec<i16>)>> {
+    let mut file = File::open(path)?;
+    let Some(g711) = g711_header(&mut file)? else {
+        return Ok(None);
+    };
+    let mut data = vec![];
+    file.take(g711.len as u64).read_to_end(&mut data)?;
+    Ok(Some((g711.info, data.into_iter().map(g711.decode).collect())))
+}
+
+// one channel of interleaved samples, or all of them averaged
+fn to_mono(samples: &[i16], channels: u32, channel: ChannelSelect) -> Vec<i16> {
+    let channels = channels.max(1) as usize;
+    if channels == 1 {
+        return samples.to_vec();
+    }
+    let frames = samples.chunks_exact(channels);
+    match channel {
+        ChannelSelect::Mix => frames.map(|frame| (frame.iter().map(|&s| s as i32).sum::<i32>() / channels as i32) as i16).collect(),
+        ChannelSelect::Left => frames.map(|frame| frame[0]).collect(),
+        ChannelSelect::Right => frames.map(|frame| frame[1]).collect(),
+    }
+}
+
+// linear interpolation to 16kHz. g711 carries nothing above 4kHz, so upsampling it this way loses nothing
+fn resample_to_16k(samples: &[i16], sample_rate: u32) -> Vec<i16> {
+    if sample_rate == SAMPLE_RATE as u32 || sample_rate == 0 || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let len = (samples.len() as u64 * SAMPLE_RATE as u64 / sample_rate as u64) as usize;
+    (0..len)
+        .map(|i| {
+            let at = i as f64 * sample_rate as f64 / SAMPLE_RATE as f64;
+            let (index, fraction) = (at as usize, at.fract());
+            let a = samples[index.min(samples.len() - 1)] as f64;
+            let b = samples[(index + 1).min(samples.len() - 1)] as f64;
+            (a + (b - a) * fraction).round() as i16
+        })
+        .collect()
+}
+
+/// wav headers are read directly, everything else goes through ffprobe
+pub fn audio_info<P: AsRef<Path>>(path: P) -> Result<AudioInfo, ConvError> {
+    let path = path.as_ref();
+    if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("wav")) {
+        if let Ok(Some(g711)) = g711_header(&mut File::open(path)?) {
+            return Ok(g711.info);
+        }
+        if let Ok(Reader::Wav(wav)) = Reader::new(File::open(path)?) {
+            let spec = wav.spec();
+            return Ok(AudioInfo {
+                sample_rate: spec.sample_rate,
+                channels: spec.channels as u32,
+                duration: Duration::from_secs_f64(wav.duration() as f64 / spec.sample_rate.max(1) as f64),
+            });
+        }
+    }
+    probe(path)
+}
+
+// ffprobe -v error -select_streams a:0 -show_entries stream=sample_rate,channels:format=duration -of default=noprint_wrappers=1 input.mp3
+fn probe(path: &Path) -> Result<AudioInfo, ConvError> {
+    let decode = |reason: String| ConvError::AudioDecode { path: path.to_path_buf(), reason };
+    let output = Command::new(ffmpeg_tool("ffprobe"))
+        .args(["-v", "error", "-select_streams", "a:0", "-show_entries", "stream=sample_rate,channels:format=duration", "-of", "default=noprint_wrappers=1"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(ConvError::from_spawn)?;
+    if !output.status.success() {
+        return Err(decode(format!("ffprobe: {}", String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let field = |key: &str| {
+        stdout
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .and_then(|value| value.trim().parse::<f64>().ok())
+            .ok_or_else(|| decode(format!("ffprobe: missing {}", key)))
+    };
+    Ok(AudioInfo {
+        sample_rate: field("sample_rate")? as u32,
+        channels: field("channels")? as u32,
+        duration: Duration::from_secs_f64(field("duration")?.max(0.0)),
+    })
+}
+
+// ffmpeg -i input.mp3 -vn [-af pan=mono|c0=c1] -ar 16000 -ac 1 -c:a pcm_s16le output.wav
+fn use_ffmpeg<P: AsRef<Path>>(input_path: P, channel: ChannelSelect) -> Result<Vec<i16>, ConvError> {
+    let decode = |reason: String| ConvError::AudioDecode { path: input_path.as_ref().to_path_buf(), reason };
     let temp_file = temp_dir().join(format!("{}.wav", uuid::Uuid::new_v4()));
-    let mut pid = Command::new("ffmpeg")
-        .args([
-            "-i",
-            input_path
-                .as_ref()
-                .to_str()
-                .ok_or_else(|| anyhow!("invalid path"))?,
-            "-ar",
-            "16000",
-            "-ac",
-            "1",
-            "-c:a",
-            "pcm_s16le",
-            (temp_file.to_str().unwrap()),
-            "-hide_banner",
-            "-y",
-            "-loglevel",
-            "error",
-        ])
+    let input = input_path.as_ref().to_str().ok_or_else(|| decode("invalid path".to_string()))?;
+    let mut args = vec!["-i", input, "-vn"];
+    if let Some(pan) = channel.pan() {
+        args.extend(["-af", pan]);
+    }
+    args.extend(["-ar", "16000", "-ac", "1", "-c:a", "pcm_s16le", temp_file.to_str().unwrap()]);
+    let command = command_line("ffmpeg", &args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>());
+    debug!("{}", command);
+    let output = Command::new(ffmpeg_tool("ffmpeg"))
+        .args(args)
+        .args(["-hide_banner", "-y", "-loglevel", "error"])
         .stdin(Stdio::null())
-        .spawn()?;
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(ConvError::from_spawn)?;
 
-    if pid.wait()?.success() {
+    if output.status.success() {
         let output = File::open(&temp_file)?;
-        let mut reader = Reader::new(output)?;
+        let mut reader = Reader::new(output).map_err(|e| decode(e.to_string()))?;
         let samples: Result<Vec<i16>, _> = reader.samples().collect();
         std::fs::remove_file(temp_file)?;
-        samples.map_err(std::convert::Into::into)
+        samples.map_err(|e| decode(e.to_string()))
     } else {
-        Err(anyhow!("unable to convert file"))
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        // a video without sound leaves ffmpeg nothing to write
+        if stderr.contains("does not contain any stream") || stderr.contains("matches no streams") {
+            return Err(decode("文件中没有音轨".to_string()));
+        }
+        Err(ConvError::FfmpegFailed { command, stderr })
+    }
+}
+
+/// which channel of a stereo recording to transcribe, one speaker per channel is common for interviews
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelSelect {
+    /// downmix all channels to mono
+    #[default]
+    Mix,
+    Left,
+    Right,
+}
+
+impl ChannelSelect {
+    fn pan(&self) -> Option<&'static str> {
+        match self {
+            Self::Mix => None,
+            Self::Left => Some("pan=mono|c0=c0"),
+            Self::Right => Some("pan=mono|c0=c1"),
+        }
     }
 }
 
-pub fn read_file<P: AsRef<Path>>(audio_file_path: P) -> Result<Vec<f32>> {
-    let audio_buf = use_ffmpeg(&audio_file_path)?;
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resample {
+    /// convert anything that is not already 16kHz mono wav with ffmpeg
+    #[default]
+    Auto,
+    /// read 16kHz mono wav directly and refuse everything else
+    Assert,
+}
+
+/// a single channel only comes from files with more than one, mono files are read as they are
+pub fn read_file<P: AsRef<Path>>(audio_file_path: P, resample: Resample, channel: ChannelSelect) -> Result<Vec<f32>, ConvError> {
+    let path = audio_file_path.as_ref();
+    let decode = |reason: String| ConvError::AudioDecode { path: path.to_path_buf(), reason };
+    let wav = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("wav"));
+    // a-law/μ-law is expanded here without ffmpeg, --resample assert still only takes it at 16kHz mono
+    if wav {
+        if let Some((info, samples)) = read_g711(path)? {
+            if resample == Resample::Assert && (info.sample_rate != SAMPLE_RATE as u32 || info.channels != 1) {
+                return Err(decode(format!("需要 16000 Hz 单声道 WAV，实际为 {} Hz {} 声道", info.sample_rate, info.channels)));
+            }
+            let mono = to_mono(&samples, info.channels, channel);
+            return Ok(whisper_rs::convert_integer_to_float_audio(&resample_to_16k(&mono, info.sample_rate)));
+        }
+    }
+    let audio_buf = match (audio_info(path), resample) {
+        (Ok(info), _) if wav && info.sample_rate == SAMPLE_RATE as u32 && info.channels == 1 => Reader::new(File::open(path)?)
+            .map_err(|e| decode(e.to_string()))?
+            .samples()
+            .collect::<Result<Vec<i16>, _>>()
+            .map_err(|e| decode(e.to_string()))?,
+        (Ok(info), Resample::Assert) => {
+            return Err(decode(format!("需要 16000 Hz 单声道 WAV，实际为 {} Hz {} 声道", info.sample_rate, info.channels)));
+        }
+        (Err(e), Resample::Assert) => return Err(e),
+        (Ok(info), Resample::Auto) if info.channels < 2 => use_ffmpeg(path, ChannelSelect::Mix)?,
+        (_, Resample::Auto) => use_ffmpeg(path, channel)?,
+    };
     Ok(whisper_rs::convert_integer_to_float_audio(&audio_buf))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    // 0.1s of a 440Hz tone at amplitude 8000, starting at zero
+    fn check_tone(name: &str, first: i16) {
+        let (info, samples) = read_g711(&fixture(name)).unwrap().unwrap();
+        assert_eq!((info.sample_rate, info.channels), (8000, 1));
+        assert_eq!(samples.len(), 800);
+        assert_eq!(samples[0], first);
+        let peak = samples.iter().map(|s| s.unsigned_abs()).max().unwrap();
+        assert!((7700..=8300).contains(&peak), "peak {}", peak);
+    }
+
+    #[test]
+    fn mulaw() {
+        check_tone("mulaw-8k.wav", 0);
+    }
+
+    #[test]
+    fn alaw() {
+        check_tone("alaw-8k.wav", 8);
+    }
+
+    #[test]
+    fn g711_at_8k_is_read_without_ffmpeg() {
+        for name in ["mulaw-8k.wav", "alaw-8k.wav"] {
+            let samples = read_file(fixture(name), Resample::Auto, ChannelSelect::Mix).unwrap();
+            assert_eq!(samples.len(), 1600);
+        }
+    }
+
+    #[test]
+    fn assert_refuses_g711_at_8k() {
+        for name in ["mulaw-8k.wav", "alaw-8k.wav"] {
+            let e = read_file(fixture(name), Resample::Assert, ChannelSelect::Mix).unwrap_err();
+            assert!(matches!(e, ConvError::AudioDecode { .. }), "{:?}", e);
+        }
+    }
+
+    #[test]
+    fn other_wav_is_not_g711() {
+        let path = temp_dir().join(format!("{}.wav", uuid::Uuid::new_v4()));
+        // 16kHz mono 16 bit pcm, no samples
+        let mut wav = b"RIFF\x24\0\0\0WAVEfmt \x10\0\0\0\x01\0\x01\0".to_vec();
+        wav.extend(16000u32.to_le_bytes());
+        wav.extend(32000u32.to_le_bytes());
+        wav.extend(b"\x02\0\x10\0data\0\0\0\0");
+        std::fs::write(&path, wav).unwrap();
+        let read = read_g711(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(read.unwrap().is_none());
+    }
+
+    #[test]
+    fn channels_and_rate() {
+        assert_eq!(to_mono(&[1, 3, 5, 7], 2, ChannelSelect::Mix), [2, 6]);
+        assert_eq!(to_mono(&[1, 3, 5, 7], 2, ChannelSelect::Right), [3, 7]);
+        assert_eq!(resample_to_16k(&[0, 100], 8000), [0, 50, 100, 100]);
+    }
+}
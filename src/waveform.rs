@@ -0,0 +1,31 @@
+use crate::utils::SAMPLE_RATE;
+
+pub const RESOLUTION: usize = 4096;
+
+pub struct Envelope {
+    pub peaks: Vec<(f32, f32)>,
+    pub duration: i64,
+}
+
+impl Envelope {
+    pub fn new(samples: &[f32]) -> Self {
+        let chunk = samples.len().div_ceil(RESOLUTION).max(1);
+        let peaks = samples
+            .chunks(chunk)
+            .map(|c| c.iter().fold((0f32, 0f32), |(min, max), &s| (min.min(s), max.max(s))))
+            .collect();
+        Self {
+            peaks,
+            duration: samples.len() as i64 * 100 / SAMPLE_RATE as i64,
+        }
+    }
+
+    pub fn range(&self, from: f32, to: f32) -> (f32, f32) {
+        let len = self.peaks.len();
+        let start = ((from * len as f32) as usize).min(len.saturating_sub(1));
+        let end = ((to * len as f32).ceil() as usize).clamp(start + 1, len.max(start + 1));
+        self.peaks[start..end.min(len)]
+            .iter()
+            .fold((0f32, 0f32), |(min, max), &(lo, hi)| (min.min(lo), max.max(hi)))
+    }
+}
@@ -0,0 +1,130 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::config::CLIENT;
+
+// after the first attempt, waiting 1s, 2s and 4s in between
+const RETRIES: u32 = 3;
+pub const SIGNATURE_HEADER: &str = "X-Conv-Signature";
+
+/// {"job_id": 0, "input": "a.mp3", "status": "succeeded", "outputs": ["a.srt"], "duration_ms": 5230}
+#[derive(Debug, Clone, Serialize)]
+pub struct Payload {
+    pub job_id: u64,
+    pub input: PathBuf,
+    pub status: &'static str,
+    pub outputs: Vec<PathBuf>,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Payload {
+    pub fn new<E: Display>(job_id: u64, input: PathBuf, started: Instant, result: Result<Vec<PathBuf>, E>) -> Self {
+        let duration_ms = started.elapsed().as_millis() as u64;
+        match result {
+            Ok(outputs) => Self { job_id, input, status: "succeeded", outputs, duration_ms, error: None },
+            Err(e) => Self { job_id, input, status: "failed", outputs: vec![], duration_ms, error: Some(e.to_string()) },
+        }
+    }
+}
+
+/// posts a payload per finished job in the background, signed with `X-Conv-Signature: sha256=<hex hmac of the body>` when there's a secret
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: reqwest::Url,
+    secret: Option<String>,
+    pending: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Webhook {
+    pub fn new(url: &str, secret: Option<String>) -> Result<Self, String> {
+        let url = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err("仅支持 http 和 https".to_string());
+        }
+        Ok(Self { url, secret, pending: Default::default() })
+    }
+
+    pub fn url(&self) -> &str {
+        self.url.as_str()
+    }
+
+    pub fn host(&self) -> Option<&str> {
+        self.url.host_str()
+    }
+
+    /// returns right away, the next job doesn't wait for the delivery
+    pub fn notify(&self, payload: &Payload) {
+        let body = serde_json::to_string(payload).unwrap();
+        let signature = self.secret.as_deref().map(|secret| sign(secret, &body));
+        let delivery = tokio::spawn(deliver(self.url.clone(), body, signature));
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|delivery| !delivery.is_finished());
+        pending.push(delivery);
+    }
+
+    /// deliveries still retrying, before the process exits
+    pub async fn wait(&self) {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        for delivery in pending {
+            let _ = delivery.await;
+        }
+    }
+}
+
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac takes keys of any length");
+    mac.update(body.as_bytes());
+    let hex: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hex)
+}
+
+async fn deliver(url: reqwest::Url, body: String, signature: Option<String>) {
+    let started = Instant::now();
+    for attempt in 0..=RETRIES {
+        if attempt > 0 {
+            tokio::time::sleep(Duration::from_secs(1 << (attempt - 1))).await;
+        }
+        let mut request = CLIENT.post(url.clone()).header(reqwest::header::CONTENT_TYPE, "application/json").timeout(Duration::from_secs(30)).body(body.clone());
+        if let Some(ref signature) = signature {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+        match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                info!("webhook {} delivered in {:?}", url, started.elapsed());
+                return;
+            }
+            Ok(response) => warn!("webhook {} answered {} (attempt {})", url, response.status(), attempt + 1),
+            Err(e) => warn!("webhook {} failed: {} (attempt {})", url, e, attempt + 1),
+        }
+    }
+    warn!("webhook {} gave up after {} attempts", url, RETRIES + 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_matches_rfc_4231() {
+        assert_eq!(sign("Jefe", "what do ya want for nothing?"), "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+
+    #[test]
+    fn only_http_and_https() {
+        assert!(Webhook::new("https://example.com/hook", None).is_ok());
+        assert!(Webhook::new("http://127.0.0.1:9000/conv", None).is_ok());
+        for url in ["ftp://example.com/hook", "file:///etc/passwd", "gopher://example.com", "example.com/hook"] {
+            assert!(Webhook::new(url, None).is_err(), "{}", url);
+        }
+    }
+}
@@ -1,63 +1,368 @@
+use std::ffi::{c_int, c_void, CStr};
+use std::fmt::Display;
 use std::fs::File;
-use std::io::{Error, ErrorKind, Write};
-use std::path::Path;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
 
-use anyhow::anyhow;
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use clap::ValueEnum;
 use whisper_rs::{FullParams, SamplingStrategy, WhisperContext};
 
 use crate::config::{Language, Model};
-use crate::utils;
+use crate::error::ConvError;
+use crate::event::{AppEvent, Events};
+use crate::job::CancelToken;
+use crate::utils::{ChannelSelect, estimate_snr, Resample, SAMPLE_RATE};
 
-#[derive(Debug, Serialize, Deserialize)]
+// whisper-rs 0.8 bundles whisper.cpp 1.4.2 through whisper-rs-sys 0.6
+const WHISPER_CPP: &str = "whisper.cpp 1.4.2 (whisper-rs 0.8)";
+
+// whisper.cpp still calls hebrew by its withdrawn ISO code
+fn whisper_code(lang: Language) -> &'static str {
+    match lang {
+        Language::Hebrew => "iw",
+        lang => lang.into(),
+    }
+}
+
+fn from_whisper_code(code: &str) -> Option<Language> {
+    match code {
+        "iw" => Some(Language::Hebrew),
+        code => Language::from_str(code, true).ok(),
+    }
+}
+
+// whisper.cpp takes any code and decodes with a garbage language token for one it doesn't know,
+// letting it detect the language gives usable output instead
+fn supported(lang: Language) -> Language {
+    if lang == Language::Auto || whisper_rs::get_lang_id(whisper_code(lang)).is_some() {
+        return lang;
+    }
+    warn!("{} doesn't know language {}, detecting it instead", WHISPER_CPP, <&str>::from(lang));
+    Language::Auto
+}
+
+pub static SYSTEM_INFO: Lazy<String> = Lazy::new(|| {
+    let info = whisper_rs::print_system_info().trim().trim_end_matches('|').trim();
+    let gpu = ["BLAS = 1", "COREML = 1", "OPENVINO = 1"].iter().any(|flag| info.contains(flag));
+    format!(
+        "conv {}\n{}\nGPU/BLAS: {}\n{} {}\n{}",
+        env!("CARGO_PKG_VERSION"),
+        WHISPER_CPP,
+        if gpu { "yes" } else { "no" },
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        info,
+    )
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transcript {
     pub processing_time: Duration,
     pub utterances: Vec<Utterance>,
     pub word_utterances: Option<Vec<Utterance>>,
+    #[serde(default)]
+    pub timings: Timings,
+}
+
+/// where the time went, load only when the model wasn't loaded yet
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Timings {
+    pub load: Option<Duration>,
+    pub decode: Duration,
+    pub inference: Duration,
+    /// length of the decoded audio
+    pub audio: Duration,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Timings {
+    pub fn realtime_factor(&self) -> Option<f64> {
+        (!self.audio.is_zero()).then(|| self.inference.as_secs_f64() / self.audio.as_secs_f64())
+    }
+}
+
+// load 3.1s, decode 1.2s, inference 214.8s (0.12x realtime)
+impl Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(load) = self.load {
+            write!(f, "load {:.1}s, ", load.as_secs_f32())?;
+        }
+        write!(f, "decode {:.1}s, inference {:.1}s", self.decode.as_secs_f32(), self.inference.as_secs_f32())?;
+        if let Some(rtf) = self.realtime_factor() {
+            write!(f, " ({:.2}x realtime)", rtf)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Utterance {
     pub start: i64,
     pub end: i64,
     pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<Language>,
+    /// whisper's confidence in the segment: the mean log probability of its text tokens, and how likely it was silence.
+    /// the whisper.cpp bundled here doesn't keep its no-speech probability per segment, so that one stays empty for now
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avg_logprob: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_speech_prob: Option<f32>,
+    /// who is speaking, only known from the `<v Name>` voice spans of a vtt read back for now
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speaker: Option<String>,
+    /// filled in by Transcript::translate_with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub translation: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sampling {
+    Greedy { best_of: i32 },
+    BeamSearch { beam_size: i32, patience: f32 },
+    Auto(AutoSampling),
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self::Greedy { best_of: 1 }
+    }
+}
+
+/// greedy for short and clean audio, beam search otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AutoSampling {
+    pub max_greedy_seconds: f32,
+    pub min_greedy_snr: f32,
+    pub beam_size: i32,
+}
+
+impl Default for AutoSampling {
+    fn default() -> Self {
+        Self {
+            max_greedy_seconds: 60.0,
+            min_greedy_snr: 15.0,
+            beam_size: 5,
+        }
+    }
+}
+
+impl Sampling {
+    pub fn resolve(&self, audio: &[f32]) -> SamplingStrategy {
+        match *self {
+            Self::Greedy { best_of } => SamplingStrategy::Greedy { best_of },
+            Self::BeamSearch { beam_size, patience } => SamplingStrategy::BeamSearch { beam_size, patience },
+            Self::Auto(auto) => {
+                let seconds = audio.len() as f32 / SAMPLE_RATE as f32;
+                if seconds <= auto.max_greedy_seconds && estimate_snr(audio) >= auto.min_greedy_snr {
+                    SamplingStrategy::Greedy { best_of: 1 }
+                } else {
+                    SamplingStrategy::BeamSearch { beam_size: auto.beam_size, patience: -1.0 }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TranscribeOptions {
+    pub translate: bool,
+    pub word_timestamps: bool,
+    pub verbose: bool,
+    pub sampling: Sampling,
+    pub offset_ms: i32,
+    pub duration_ms: i32,
+    pub resample: Resample,
+    pub channel: ChannelSelect,
+    pub glossary: Vec<String>,
+    /// re-detect the language every n ms and tag utterances with it, 0 to disable
+    pub language_window_ms: i32,
+    /// tokens whisper may generate per segment, 0 for no limit
+    pub max_tokens: i32,
+    #[serde(skip)]
+    pub cancel: CancelToken,
+    /// audio path and formats to write segment by segment while transcribing
+    pub stream_to: Option<(PathBuf, Vec<Format>)>,
+    /// tidy up spacing around punctuation once transcription is done
+    pub clean_text: bool,
+    #[serde(skip)]
+    pub events: Events,
+}
+
+// called before each 30s window is encoded, returning false stops whisper
+unsafe extern "C" fn keep_going(_: *mut whisper_rs_sys::whisper_context, _: *mut whisper_rs_sys::whisper_state, cancelled: *mut c_void) -> bool {
+    !(*(cancelled as *const AtomicBool)).load(Ordering::Relaxed)
+}
+
+unsafe extern "C" fn on_progress(_: *mut whisper_rs_sys::whisper_context, _: *mut whisper_rs_sys::whisper_state, progress: c_int, events: *mut c_void) {
+    (*(events as *const Events)).send(AppEvent::TranscribeProgress { percent: progress.clamp(0, 100) as u8 });
+}
+
+/// one term per line, blank lines and # comments are skipped
+pub fn load_glossary<P: AsRef<Path>>(path: P) -> Result<Vec<String>, ConvError> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+// the last loaded model and how long loading it took, jobs with the same model only create a new state
+static LOADED: Mutex<Option<(Model, Arc<WhisperContext>, Duration)>> = Mutex::new(None);
+
+pub fn unload() {
+    if let Some((model, _, _)) = LOADED.lock().unwrap().take() {
+        info!("unloaded {}", model);
+    }
+}
+
+pub fn loaded() -> Option<Model> {
+    LOADED.lock().unwrap().as_ref().map(|(model, _, _)| *model)
 }
 
 pub struct Whisper {
-    ctx: WhisperContext,
+    ctx: Arc<WhisperContext>,
     lang: Language,
 }
 
 impl Whisper {
-    pub async fn new(lang: Language, model: Model) -> std::io::Result<Self> {
-        model.download().await?;
-        Ok(Self {
-            ctx: WhisperContext::new(model.get_path().to_str().unwrap()).map_err(|_| Error::from(ErrorKind::InvalidData))?,
-            lang,
-        })
+    pub async fn new(lang: Language, model: Model, cancel: &CancelToken, events: &Events) -> Result<Self, ConvError> {
+        if let Some((loaded, ref ctx, load_time)) = *LOADED.lock().unwrap() {
+            if loaded == model {
+                info!("reusing loaded {}, saved {:.1}s", model, load_time.as_secs_f32());
+                return Ok(Self { ctx: ctx.clone(), lang: supported(lang) });
+            }
+        }
+        // free the previous model before loading another one
+        unload();
+        model.download(cancel, events).await?;
+        info!("loading {}", model.get_path().display());
+        let st = Instant::now();
+        let ctx = WhisperContext::new(model.get_path().to_str().unwrap()).map_err(|_| match model.memory_warning() {
+            Some(warning) => ConvError::ModelLoad(warning),
+            None => ConvError::ModelLoad(format!("无法加载 {} 模型，文件可能已损坏或内存不足，可尝试重新下载或选择更小的模型", model)),
+        })?;
+        let ctx = Arc::new(ctx);
+        info!("loaded {} in {:.1}s", model, st.elapsed().as_secs_f32());
+        *LOADED.lock().unwrap() = Some((model, ctx.clone(), st.elapsed()));
+        Ok(Self { ctx, lang: supported(lang) })
     }
 
-    pub fn transcribe<P: AsRef<Path>>(&mut self, audio: P, translate: bool, word_timestamps: bool) -> anyhow::Result<Transcript> {
-        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+    /// the context doesn't depend on the language, switching only changes what the next transcribe asks for
+    pub fn set_language(&mut self, lang: Language) {
+        self.lang = supported(lang);
+    }
+
+    // whisper only looks at the last n_text_ctx / 2 prompt tokens, terms that don't fit are dropped whole
+    fn glossary_prompt(&self, glossary: &[String]) -> Vec<c_int> {
+        let limit = self.ctx.n_text_ctx() as usize / 2;
+        let mut prompt = String::new();
+        let mut tokens = vec![];
+        for (i, term) in glossary.iter().enumerate() {
+            let candidate = if prompt.is_empty() { term.clone() } else { format!("{}, {}", prompt, term) };
+            match self.ctx.tokenize(&candidate, limit) {
+                Ok(t) => {
+                    prompt = candidate;
+                    tokens = t;
+                }
+                Err(_) => {
+                    warn!("glossary truncated to {} of {} terms", i, glossary.len());
+                    break;
+                }
+            }
+        }
+        tokens
+    }
+
+    /// (start in centiseconds, language code) for each window of the transcribed range
+    pub fn detect_languages(&self, audio: &[f32], options: &TranscribeOptions) -> Result<Vec<(i64, &'static str)>, ConvError> {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get()).min(4);
+        let window = options.language_window_ms as usize * SAMPLE_RATE / 1000;
+        let offset = (options.offset_ms as usize * SAMPLE_RATE / 1000).min(audio.len());
+        let end = match options.duration_ms {
+            0 => audio.len(),
+            ms => (offset + ms as usize * SAMPLE_RATE / 1000).min(audio.len()),
+        };
+        let mut state = self.ctx.create_state().map_err(|e| ConvError::Transcribe(format!("failed to create state due to {:?}", e)))?;
+        let mut windows = vec![];
+        for (i, chunk) in audio[offset..end].chunks(window).enumerate() {
+            let detect = |e| ConvError::Transcribe(format!("failed to detect language due to {:?}", e));
+            state.pcm_to_mel(chunk, threads).map_err(detect)?;
+            let probs = state.lang_detect(0, threads).map_err(detect)?;
+            let Some(id) = probs.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)).map(|(id, _)| id) else {
+                continue;
+            };
+            if let Some(code) = whisper_rs::get_lang_str(id as i32) {
+                let lang = from_whisper_code(code).map_or(code, <&str>::from);
+                windows.push((((offset + i * window) * 100 / SAMPLE_RATE) as i64, lang));
+            }
+        }
+        info!("detected languages: {:?}", windows);
+        Ok(windows)
+    }
+
+    pub fn transcribe(&mut self, audio: &[f32], options: &TranscribeOptions) -> Result<Transcript, ConvError> {
+        let TranscribeOptions { translate, word_timestamps, verbose, sampling, offset_ms, duration_ms, max_tokens, ref glossary, .. } = *options;
+        let prompt = self.glossary_prompt(glossary);
+        let mut params = FullParams::new(sampling.resolve(audio));
 
         params.set_translate(translate);
         params.set_print_special(false);
-        params.set_print_progress(false);
+        params.set_print_progress(verbose);
         params.set_print_realtime(false);
-        params.set_print_timestamps(false);
+        params.set_print_timestamps(verbose);
         params.set_token_timestamps(word_timestamps);
-        params.set_language(Some(<&str>::from(self.lang)));
-
-        let audio = utils::read_file(audio)?;
+        params.set_offset_ms(offset_ms);
+        params.set_duration_ms(duration_ms);
+        params.set_max_tokens(max_tokens);
+        params.set_language(Some(whisper_code(self.lang)));
+        params.set_tokens(&prompt);
+        unsafe {
+            params.set_start_encoder_callback(Some(keep_going));
+            params.set_start_encoder_callback_user_data(options.cancel.flag() as *const AtomicBool as *mut c_void);
+        }
+        let mut stream = match options.stream_to {
+            Some((ref audio, ref formats)) => Stream::create(audio, formats),
+            None => Stream::default(),
+        };
+        stream.events = options.events.clone();
+        if !stream.files.is_empty() || stream.events.is_listening() {
+            unsafe {
+                params.set_new_segment_callback(Some(on_segment));
+                params.set_new_segment_callback_user_data(&mut stream as *mut Stream as *mut c_void);
+            }
+        }
+        if options.events.is_listening() {
+            unsafe {
+                params.set_progress_callback(Some(on_progress));
+                params.set_progress_callback_user_data(&options.events as *const Events as *mut c_void);
+            }
+        }
 
+        info!("transcribing {:.1}s of audio, lang: {}, {:?}", audio.len() as f32 / SAMPLE_RATE as f32, <&str>::from(self.lang), options);
         let st = Instant::now();
-        let mut state = self.ctx.create_state().expect("failed to create state");
-        state.full(params, &audio).expect("failed to transcribe");
+        let mut state = self.ctx.create_state().map_err(|e| ConvError::Transcribe(format!("failed to create state due to {:?}", e)))?;
+        let inference = Instant::now();
+        let result = state.full(params, audio);
+        let inference = inference.elapsed();
+        if options.cancel.is_cancelled() {
+            return Err(ConvError::Cancelled);
+        }
+        result.map_err(|e| ConvError::Transcribe(format!("failed to transcribe due to {:?}", e)))?;
 
-        let num_segments = state.full_n_segments().expect("failed to get segments");
+        let segment = |e| ConvError::Transcribe(format!("failed to get segment due to {:?}", e));
+        let token = |e| ConvError::Transcribe(format!("failed to get token due to {:?}", e));
+        let num_segments = state.full_n_segments().map_err(segment)?;
         if num_segments == 0 {
-            return Err(anyhow!("No segments found"));
+            return Err(ConvError::Transcribe("No segments found".to_string()));
         };
 
         let mut words = vec![];
@@ -65,89 +370,425 @@ impl Whisper {
         for s in 0..num_segments {
             let text = state
                 .full_get_segment_text(s)
-                .map_err(|e| anyhow!("failed to get segment due to {:?}", e))?;
+                .map_err(segment)?;
             let start = state
                 .full_get_segment_t0(s)
-                .map_err(|e| anyhow!("failed to get segment due to {:?}", e))?;
+                .map_err(segment)?;
             let end = state
                 .full_get_segment_t1(s)
-                .map_err(|e| anyhow!("failed to get segment due to {:?}", e))?;
-
-            utterances.push(Utterance { text, start, end });
-
-            if !word_timestamps {
-                continue;
-            }
+                .map_err(segment)?;
 
             let num_tokens = state
                 .full_n_tokens(s)
-                .map_err(|e| anyhow!("failed to get segment due to {:?}", e))?;
+                .map_err(segment)?;
 
+            let (mut logprob, mut counted) = (0.0, 0);
             for t in 0..num_tokens {
                 let text = state
                     .full_get_token_text(s, t)
-                    .map_err(|e| anyhow!("failed to get token due to {:?}", e))?;
+                    .map_err(token)?;
                 let token_data = state
                     .full_get_token_data(s, t)
-                    .map_err(|e| anyhow!("failed to get token due to {:?}", e))?;
+                    .map_err(token)?;
 
                 if text.starts_with("[_") {
                     continue;
                 }
+                logprob += token_data.plog;
+                counted += 1;
 
-                words.push(Utterance {
-                    text,
-                    start: token_data.t0,
-                    end: token_data.t1,
-                });
+                if word_timestamps {
+                    words.push(Utterance {
+                        text,
+                        start: token_data.t0,
+                        end: token_data.t1,
+                        lang: None,
+                        avg_logprob: Some(token_data.plog),
+                        no_speech_prob: None,
+                        speaker: None,
+                        translation: None,
+                    });
+                }
+            }
+
+            let avg_logprob = (counted > 0).then(|| logprob / counted as f32);
+            utterances.push(Utterance { text, start, end, lang: None, avg_logprob, no_speech_prob: None, speaker: None, translation: None });
+        }
+
+        if options.language_window_ms > 0 {
+            let windows = self.detect_languages(audio, options)?;
+            let lang_at = |u: &Utterance| {
+                let middle = (u.start + u.end) / 2;
+                windows.iter().rev().find(|(start, _)| *start <= middle).or(windows.first()).and_then(|(_, code)| Language::from_str(code, true).ok())
+            };
+            for u in utterances.iter_mut().chain(words.iter_mut()) {
+                u.lang = lang_at(u);
+            }
+        } else if self.lang == Language::Auto {
+            // without windows every segment gets the language whisper settled on for the whole run
+            let detected = state.full_lang_id_from_state().ok().and_then(whisper_rs::get_lang_str).and_then(from_whisper_code);
+            for u in utterances.iter_mut().chain(words.iter_mut()) {
+                u.lang = detected;
             }
         }
 
-        Ok(Transcript {
+        info!("transcribed {} segments in {:.1}s", utterances.len(), st.elapsed().as_secs_f32());
+        let mut t = Transcript {
             utterances,
             processing_time: Instant::now().duration_since(st),
             word_utterances: if word_timestamps { Some(words) } else { None },
-        })
+            timings: Timings {
+                inference,
+                audio: Duration::from_secs_f64(audio.len() as f64 / SAMPLE_RATE as f64),
+                ..Default::default()
+            },
+        };
+        if options.clean_text {
+            t.clean_text();
+        }
+        Ok(t)
     }
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
 pub enum Format {
     Lrc,
+    /// [mm:ss.xxx] for players that want thousandths
+    LrcMillis,
     Srt,
+    SrtCrlf,
     Vtt,
+    /// vtt without the "- " in front of each cue, some players show the dash as is
+    VttPlain,
+    /// vtt with `<v Name>` voice spans on the cues that know their speaker
+    VttVoice,
+    Json,
+    /// just the text, one line per cue
+    Txt,
+    /// \k tagged Dialogue lines filled word by word, best with word timestamps. written only, not read back
+    AssKaraoke,
+    /// "03:12 Title" lines for a youtube description, a.chapters.txt. written only
+    Chapters,
 }
 
-impl Transcript {
-    pub fn write_file<P: AsRef<Path>>(&self, audio: P, format: Format) {
-        let (path, subtitle) = match format {
-            Format::Lrc => (audio.as_ref().with_extension("lrc"), self.to_lrc()),
-            Format::Srt => (audio.as_ref().with_extension("srt"), self.to_srt()),
-            Format::Vtt => (audio.as_ref().with_extension("vtt"), self.to_vtt()),
-        };
-        if let Ok(mut file) = File::create(path) {
-            file.write_all(subtitle.as_bytes()).unwrap();
+impl Format {
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_lowercase().as_str() {
+            "lrc" => Some(Self::Lrc),
+            "srt" => Some(Self::Srt),
+            "vtt" => Some(Self::Vtt),
+            "json" => Some(Self::Json),
+            "txt" => Some(Self::Txt),
+            _ => None,
         }
     }
 
-    pub fn to_lrc(&self) -> String {
-        self.word_utterances
-            .as_ref()
-            .unwrap_or(&self.utterances)
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Lrc | Self::LrcMillis => "lrc",
+            Self::Srt | Self::SrtCrlf => "srt",
+            Self::Vtt | Self::VttPlain | Self::VttVoice => "vtt",
+            Self::Json => "json",
+            Self::Txt => "txt",
+            Self::AssKaraoke => "ass",
+            Self::Chapters => "chapters.txt",
+        }
+    }
+
+    /// one of each in the order given, variants of one format write the same file so the first of them wins
+    pub fn dedup(formats: impl IntoIterator<Item = Format>) -> Vec<Format> {
+        let mut unique: Vec<Format> = vec![];
+        for format in formats {
+            if !unique.iter().any(|f| f.extension() == format.extension()) {
+                unique.push(format);
+            }
+        }
+        unique
+    }
+}
+
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+// {
+//   "schema_version": 1,
+//   "processing_time": 12.5,
+//   "throughput": null | 143.2,
+//   "utterances": [{"start": 0, "end": 250, "start_seconds": 0.0, "end_seconds": 2.5, "text": "...", "lang": "en"}],
+//   "word_utterances": null | [<same as utterances>],
+//   "timings": {"load_seconds": null | 3.1, "decode_seconds": 1.2, "inference_seconds": 214.8, "audio_seconds": 1790.0}
+// }
+// start/end are centiseconds, *_seconds the same instant in seconds, lang whenever it was detected,
+// with --language-window per cue, otherwise the language auto detection settled on for the whole run,
+// throughput is seconds of audio per second of processing_time
+#[derive(Serialize)]
+struct JsonTranscript<'a> {
+    schema_version: u32,
+    processing_time: f64,
+    throughput: Option<f64>,
+    utterances: Vec<JsonUtterance<'a>>,
+    word_utterances: Option<Vec<JsonUtterance<'a>>>,
+    timings: JsonTimings,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct JsonTimings {
+    load_seconds: Option<f64>,
+    decode_seconds: f64,
+    inference_seconds: f64,
+    audio_seconds: f64,
+}
+
+impl From<Timings> for JsonTimings {
+    fn from(t: Timings) -> Self {
+        Self {
+            load_seconds: t.load.map(|d| d.as_secs_f64()),
+            decode_seconds: t.decode.as_secs_f64(),
+            inference_seconds: t.inference.as_secs_f64(),
+            audio_seconds: t.audio.as_secs_f64(),
+        }
+    }
+}
+
+impl From<JsonTimings> for Timings {
+    fn from(t: JsonTimings) -> Self {
+        let seconds = |s: f64| Duration::from_secs_f64(s.max(0.0));
+        Self {
+            load: t.load_seconds.map(seconds),
+            decode: seconds(t.decode_seconds),
+            inference: seconds(t.inference_seconds),
+            audio: seconds(t.audio_seconds),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonUtterance<'a> {
+    start: i64,
+    end: i64,
+    start_seconds: f64,
+    end_seconds: f64,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lang: Option<Language>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avg_logprob: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    no_speech_prob: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    speaker: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    translation: Option<&'a str>,
+}
+
+impl<'a> From<&'a Utterance> for JsonUtterance<'a> {
+    fn from(u: &'a Utterance) -> Self {
+        Self {
+            start: u.start,
+            end: u.end,
+            start_seconds: u.start as f64 / 100.0,
+            end_seconds: u.end as f64 / 100.0,
+            text: u.text.trim(),
+            lang: u.lang,
+            avg_logprob: u.avg_logprob,
+            no_speech_prob: u.no_speech_prob,
+            speaker: u.speaker.as_deref(),
+            translation: u.translation.as_deref().map(str::trim),
+        }
+    }
+}
+
+const VTT_HEADER: &str = "WEBVTT\n\n";
+
+/// what a vtt cue's text starts with. players that don't take "- " as a change of speaker show the dash as is
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VttCue {
+    /// "- text", as conv has always written it
+    #[default]
+    Dash,
+    /// just the text
+    Plain,
+    /// `<v Name>text` when the cue knows its speaker, just the text otherwise
+    Voice,
+}
+
+impl VttCue {
+    pub fn format(&self) -> Format {
+        match self {
+            Self::Dash => Format::Vtt,
+            Self::Plain => Format::VttPlain,
+            Self::Voice => Format::VttVoice,
+        }
+    }
+}
+
+// [mm:ss.cc], or [mm:ss.xxx] with millis, the fraction always zero padded so 5cs is .05
+fn lrc_time(time: i64, millis: bool) -> String {
+    if millis {
+        format!("[{:02}:{:02}.{:03}]", time / 100 / 60, time / 100 % 60, time % 100 * 10)
+    } else {
+        format!("[{:02}:{:02}.{:02}]", time / 100 / 60, time / 100 % 60, time % 100)
+    }
+}
+
+fn lrc_cue(fragment: &Utterance, millis: bool) -> String {
+    format!(
+        "{}{}\n{}\n",
+        lrc_time(fragment.start, millis),
+        fragment.text.trim().replace('\n', " "),
+        lrc_time(fragment.end, millis),
+    )
+}
+
+fn srt_cue(i: usize, fragment: &Utterance) -> String {
+    format!(
+        "{i}\n{:02}:{:02}:{:02},{:03} --> {:02}:{:02}:{:02},{:03}\n{}\n\n",
+        fragment.start / 100 / 3600,
+        fragment.start / 100 % 3600 / 60,
+        fragment.start / 100 % 60,
+        fragment.start * 10 % 1000,
+        fragment.end / 100 / 3600,
+        fragment.end / 100 % 3600 / 60,
+        fragment.end / 100 % 60,
+        fragment.end * 10 % 1000,
+        fragment.text.trim()
+    )
+}
+
+fn vtt_cue(fragment: &Utterance, cue: VttCue) -> String {
+    let prefix = match (cue, &fragment.speaker) {
+        (VttCue::Dash, _) => "- ".to_string(),
+        // a > would end the tag early
+        (VttCue::Voice, Some(speaker)) => format!("<v {}>", speaker.replace('>', "")),
+        _ => String::new(),
+    };
+    format!(
+        "{:02}:{:02}.{:03} --> {:02}:{:02}.{:03}\n{}{}\n\n",
+        fragment.start / 100 / 60,
+        fragment.start / 100 % 60,
+        fragment.start * 10 % 1000,
+        fragment.end / 100 / 60,
+        fragment.end / 100 % 60,
+        fragment.end * 10 % 1000,
+        prefix,
+        fragment.text.trim()
+    )
+}
+
+fn txt_line(fragment: &Utterance) -> String {
+    format!("{}\n", fragment.text.trim().replace('\n', " "))
+}
+
+// segments are appended to the output files as whisper finishes them, so an interrupted job
+// still leaves valid subtitles behind. write_file rewrites them in full once transcription is done
+#[derive(Default)]
+struct Stream {
+    files: Vec<(Format, File)>,
+    cues: usize,
+    events: Events,
+}
+
+impl Stream {
+    fn create(audio: &Path, formats: &[Format]) -> Self {
+        let files = formats
             .iter()
-            .fold(String::new(), |lrc, fragment| {
-                lrc +
-                    &format!(
-                        "[{:02}:{:02}.{:02}]{}\n[{:02}:{:02}.{:02}]\n",
-                        fragment.start / 100 / 60,
-                        fragment.start / 100 % 60,
-                        fragment.start % 100,
-                        fragment.text.trim(),
-                        fragment.end / 100 / 60,
-                        fragment.end / 100 % 60,
-                        fragment.end % 100,
-                    )
+            .filter_map(|&format| {
+                let (ext, header) = match format {
+                    Format::Lrc | Format::LrcMillis => ("lrc", ""),
+                    Format::Srt | Format::SrtCrlf => ("srt", ""),
+                    Format::Vtt | Format::VttPlain | Format::VttVoice => ("vtt", VTT_HEADER),
+                    Format::Txt => ("txt", ""),
+                    // a Dialogue line needs the segment's words and chapters the pauses, which come with the whole transcript
+                    Format::Json | Format::AssKaraoke | Format::Chapters => return None,
+                };
+                let mut file = File::create(audio.with_extension(ext)).ok()?;
+                file.write_all(header.as_bytes()).ok()?;
+                Some((format, file))
             })
+            .collect();
+        Self { files, ..Default::default() }
+    }
+
+    fn push(&mut self, fragment: &Utterance) {
+        self.cues += 1;
+        for (format, file) in &mut self.files {
+            let cue = match format {
+                Format::Lrc => lrc_cue(fragment, false),
+                Format::LrcMillis => lrc_cue(fragment, true),
+                Format::Srt => srt_cue(self.cues, fragment),
+                Format::SrtCrlf => srt_cue(self.cues, fragment).replace('\n', "\r\n"),
+                Format::Vtt => vtt_cue(fragment, VttCue::Dash),
+                Format::VttPlain => vtt_cue(fragment, VttCue::Plain),
+                Format::VttVoice => vtt_cue(fragment, VttCue::Voice),
+                Format::Txt => txt_line(fragment),
+                Format::Json | Format::AssKaraoke | Format::Chapters => continue,
+            };
+            let _ = file.write_all(cue.as_bytes()).and_then(|_| file.flush());
+        }
+        self.events.send(AppEvent::Segment(fragment.clone()));
+    }
+}
+
+unsafe extern "C" fn on_segment(_: *mut whisper_rs_sys::whisper_context, state: *mut whisper_rs_sys::whisper_state, n_new: c_int, stream: *mut c_void) {
+    let stream = &mut *(stream as *mut Stream);
+    let n = whisper_rs_sys::whisper_full_n_segments_from_state(state);
+    for s in (n - n_new).max(0)..n {
+        let text = whisper_rs_sys::whisper_full_get_segment_text_from_state(state, s);
+        if text.is_null() {
+            continue;
+        }
+        stream.push(&Utterance {
+            start: whisper_rs_sys::whisper_full_get_segment_t0_from_state(state, s),
+            end: whisper_rs_sys::whisper_full_get_segment_t1_from_state(state, s),
+            text: CStr::from_ptr(text).to_string_lossy().into_owned(),
+            lang: None,
+            avg_logprob: None,
+            no_speech_prob: None,
+            speaker: None,
+            translation: None,
+        });
+    }
+}
+
+impl Transcript {
+    /// written to a.srt.tmp next to it and renamed over a.srt, so nothing ever sees half a file
+    pub fn write_file<P: AsRef<Path>>(&self, audio: P, format: Format) -> Result<(), ConvError> {
+        let path = audio.as_ref().with_extension(format.extension());
+        let tmp = audio.as_ref().with_extension(format!("{}.tmp", format.extension()));
+        File::create(&tmp)
+            .and_then(|mut file| self.write_to(&mut file, format).and_then(|_| file.sync_all()))
+            .and_then(|_| std::fs::rename(&tmp, &path))
+            .map_err(|source| {
+                let _ = std::fs::remove_file(&tmp);
+                ConvError::SubtitleWrite { path, source }
+            })
+    }
+
+    /// the same bytes write_file would put in the file
+    pub fn write_to<W: Write>(&self, mut writer: W, format: Format) -> std::io::Result<()> {
+        let subtitle = match format {
+            Format::Lrc => self.to_lrc(),
+            Format::LrcMillis => self.to_lrc_millis(),
+            Format::Srt => self.to_srt(),
+            Format::SrtCrlf => self.to_srt_crlf(),
+            Format::Vtt => self.to_vtt(),
+            Format::VttPlain => self.to_vtt_with(VttCue::Plain),
+            Format::VttVoice => self.to_vtt_with(VttCue::Voice),
+            Format::Json => self.to_json(),
+            Format::Txt => self.to_txt(),
+            Format::AssKaraoke => self.to_ass_karaoke(),
+            Format::Chapters => self.to_chapters(),
+        };
+        writer.write_all(subtitle.as_bytes())
+    }
+
+    pub fn to_lrc(&self) -> String {
+        self.word_utterances.as_ref().unwrap_or(&self.utterances).iter().map(|u| lrc_cue(u, false)).collect()
+    }
+
+    pub fn to_lrc_millis(&self) -> String {
+        self.word_utterances.as_ref().unwrap_or(&self.utterances).iter().map(|u| lrc_cue(u, true)).collect()
     }
 
     pub fn to_srt(&self) -> String {
@@ -155,44 +796,123 @@ impl Transcript {
             .as_ref()
             .unwrap_or(&self.utterances)
             .iter()
-            .fold((1, String::new()), |(i, srt), fragment| {
-                (
-                    i + 1,
-                    srt +
-                        &format!(
-                            "{i}\n{:02}:{:02}:{:02},{:03} --> {:02}:{:02}:{:02},{:03}\n{}\n\n",
-                            fragment.start / 100 / 3600,
-                            fragment.start / 100 % 3600 / 60,
-                            fragment.start / 100 % 60,
-                            fragment.start * 10 % 1000,
-                            fragment.end / 100 / 3600,
-                            fragment.end / 100 % 3600 / 60,
-                            fragment.end / 100 % 60,
-                            fragment.end * 10 % 1000,
-                            fragment.text.trim()
-                        )
-                )
-            })
-            .1
+            .enumerate()
+            .map(|(i, fragment)| srt_cue(i + 1, fragment))
+            .collect()
+    }
+
+    pub fn to_srt_crlf(&self) -> String {
+        self.to_srt().replace('\n', "\r\n")
     }
 
     pub fn to_vtt(&self) -> String {
+        self.to_vtt_with(VttCue::Dash)
+    }
+
+    pub fn to_vtt_with(&self, cue: VttCue) -> String {
         self.word_utterances
             .as_ref()
             .unwrap_or(&self.utterances)
             .iter()
-            .fold(String::from("WEBVTT\n\n"), |vtt, fragment| {
-                vtt +
-                    &format!(
-                        "{:02}:{:02}.{:03} --> {:02}:{:02}.{:03}\n- {}\n\n",
-                        fragment.start / 100 / 60,
-                        fragment.start / 100 % 60,
-                        fragment.start * 10 % 1000,
-                        fragment.end / 100 / 60,
-                        fragment.end / 100 % 60,
-                        fragment.end * 10 % 1000,
-                        fragment.text.trim()
-                    )
-            })
+            .fold(String::from(VTT_HEADER), |vtt, fragment| vtt + &vtt_cue(fragment, cue))
     }
-}
\ No newline at end of file
+
+    pub fn to_txt(&self) -> String {
+        self.utterances.iter().map(txt_line).collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        let json = JsonTranscript {
+            schema_version: JSON_SCHEMA_VERSION,
+            processing_time: self.processing_time.as_secs_f64(),
+            throughput: self.throughput(),
+            utterances: self.utterances.iter().map(JsonUtterance::from).collect(),
+            word_utterances: self.word_utterances.as_ref().map(|w| w.iter().map(JsonUtterance::from).collect()),
+            timings: self.timings.into(),
+        };
+        serde_json::to_string_pretty(&json).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utterance(start: i64, end: i64, text: &str, speaker: Option<&str>) -> Utterance {
+        Utterance { start, end, text: text.to_string(), lang: None, avg_logprob: None, no_speech_prob: None, speaker: speaker.map(str::to_string), translation: None }
+    }
+
+    fn transcript() -> Transcript {
+        Transcript {
+            processing_time: Duration::ZERO,
+            utterances: vec![utterance(100, 250, " Hello there", Some("Alice")), utterance(300, 400, "Plain line", None)],
+            word_utterances: None,
+            timings: Timings::default(),
+        }
+    }
+
+    fn round_trip(options: &TranscribeOptions) {
+        let json = serde_json::to_value(options).unwrap();
+        let back = serde_json::from_value::<TranscribeOptions>(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&back).unwrap(), json);
+    }
+
+    #[test]
+    fn transcribe_options_round_trip() {
+        let samplings = [Sampling::default(), Sampling::BeamSearch { beam_size: 5, patience: -1.0 }, Sampling::Auto(AutoSampling::default())];
+        for sampling in samplings {
+            round_trip(&TranscribeOptions { sampling, ..Default::default() });
+        }
+        for resample in Resample::value_variants() {
+            round_trip(&TranscribeOptions { resample: *resample, ..Default::default() });
+        }
+        for channel in ChannelSelect::value_variants() {
+            round_trip(&TranscribeOptions { channel: *channel, ..Default::default() });
+        }
+        let stream_to = Some((PathBuf::from("a.wav"), Format::value_variants().to_vec()));
+        round_trip(&TranscribeOptions { stream_to, glossary: vec!["conv".to_string()], max_tokens: 32, ..Default::default() });
+        // everything is optional
+        assert_eq!(serde_json::from_str::<TranscribeOptions>("{}").unwrap().sampling, Sampling::default());
+    }
+
+    #[test]
+    fn lrc_time_pads_the_fraction() {
+        assert_eq!(lrc_time(5, false), "[00:00.05]");
+        assert_eq!(lrc_time(5, true), "[00:00.050]");
+        assert_eq!(lrc_time(0, false), "[00:00.00]");
+        assert_eq!(lrc_time(0, true), "[00:00.000]");
+        // 1:02.05
+        assert_eq!(lrc_time(6205, false), "[01:02.05]");
+        assert_eq!(lrc_time(6205, true), "[01:02.050]");
+        assert_eq!(lrc_time(61299, false), "[10:12.99]");
+        assert_eq!(lrc_time(61299, true), "[10:12.990]");
+    }
+
+    #[test]
+    fn vtt_dash() {
+        let vtt = "WEBVTT\n\n00:01.000 --> 00:02.500\n- Hello there\n\n00:03.000 --> 00:04.000\n- Plain line\n\n";
+        assert_eq!(transcript().to_vtt(), vtt);
+        assert_eq!(transcript().to_vtt_with(VttCue::Dash), vtt);
+    }
+
+    #[test]
+    fn vtt_plain() {
+        let vtt = "WEBVTT\n\n00:01.000 --> 00:02.500\nHello there\n\n00:03.000 --> 00:04.000\nPlain line\n\n";
+        assert_eq!(transcript().to_vtt_with(VttCue::Plain), vtt);
+    }
+
+    #[test]
+    fn vtt_voice() {
+        let vtt = "WEBVTT\n\n00:01.000 --> 00:02.500\n<v Alice>Hello there\n\n00:03.000 --> 00:04.000\nPlain line\n\n";
+        assert_eq!(transcript().to_vtt_with(VttCue::Voice), vtt);
+    }
+
+    #[test]
+    fn vtt_formats_write_their_cue() {
+        let mut voice = vec![];
+        transcript().write_to(&mut voice, Format::VttVoice).unwrap();
+        assert_eq!(String::from_utf8(voice).unwrap(), transcript().to_vtt_with(VttCue::Voice));
+        assert_eq!(Format::from_str("vtt-plain", false), Ok(Format::VttPlain));
+        assert_eq!(Format::VttPlain.extension(), "vtt");
+    }
+}
@@ -0,0 +1,202 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+use tracing::{error, info};
+
+use crate::config::{Language, Model};
+use crate::error::ConvError;
+use crate::event::Events;
+use crate::job::CancelToken;
+use crate::transcriber::Transcriber;
+use crate::utils::{read_file, SAMPLE_RATE};
+use crate::whisper::{self, Timings, TranscribeOptions, Transcript, Whisper};
+
+enum Request {
+    Load { lang: Language, model: Model, cancel: CancelToken, events: Events, reply: oneshot::Sender<Result<(), ConvError>> },
+    Transcribe { audio: PathBuf, lang: Language, model: Model, options: TranscribeOptions, reply: oneshot::Sender<Result<Transcript, ConvError>> },
+    DetectLanguage { audio: PathBuf, model: Model, options: TranscribeOptions, reply: oneshot::Sender<Result<Vec<(i64, &'static str)>, ConvError>> },
+    Unload,
+}
+
+/// owns the loaded model on a thread of its own and works through requests one at a time,
+/// every part of the app that transcribes goes through one of these
+pub struct WhisperWorker {
+    sender: Option<mpsc::Sender<Request>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WhisperWorker {
+    /// models are downloaded on the runtime this is called from
+    pub fn spawn() -> Self {
+        Self::start(None)
+    }
+
+    /// a worker with another backend already loaded as `model`, asking for any other model loads whisper
+    pub fn with_transcriber(model: Model, transcriber: Box<dyn Transcriber>) -> Self {
+        Self::start(Some((model, transcriber)))
+    }
+
+    fn start(loaded: Option<(Model, Box<dyn Transcriber>)>) -> Self {
+        let runtime = Handle::current();
+        let (sender, requests) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("whisper".to_string())
+            .spawn(move || run(runtime, requests, loaded))
+            .expect("failed to spawn the whisper thread");
+        Self { sender: Some(sender), thread: Some(thread) }
+    }
+
+    async fn request<T>(&self, request: impl FnOnce(oneshot::Sender<Result<T, ConvError>>) -> Request) -> Result<T, ConvError> {
+        let stopped = || ConvError::Transcribe("whisper worker stopped".to_string());
+        let (reply, result) = oneshot::channel();
+        self.sender.as_ref().ok_or_else(stopped)?.send(request(reply)).map_err(|_| stopped())?;
+        result.await.map_err(|_| stopped())?
+    }
+
+    pub async fn load(&self, lang: Language, model: Model, cancel: &CancelToken, events: &Events) -> Result<(), ConvError> {
+        let (cancel, events) = (cancel.clone(), events.clone());
+        self.request(|reply| Request::Load { lang, model, cancel, events, reply }).await
+    }
+
+    pub async fn transcribe(&self, audio: PathBuf, lang: Language, model: Model, options: TranscribeOptions) -> Result<Transcript, ConvError> {
+        self.request(|reply| Request::Transcribe { audio, lang, model, options, reply }).await
+    }
+
+    /// the original and whisper's english in two passes, the english matched to the original segments by time.
+    /// only the first pass streams to files
+    pub async fn transcribe_bilingual(&self, audio: PathBuf, lang: Language, model: Model, options: TranscribeOptions) -> Result<Transcript, ConvError> {
+        let mut original = self.transcribe(audio.clone(), lang, model, TranscribeOptions { translate: false, ..options.clone() }).await?;
+        let english = self.transcribe(audio, lang, model, TranscribeOptions { translate: true, stream_to: None, ..options }).await?;
+        original.align_translation(&english);
+        original.processing_time += english.processing_time;
+        original.timings.decode += english.timings.decode;
+        original.timings.inference += english.timings.inference;
+        Ok(original)
+    }
+
+    /// only `seconds` from offset_ms on and without writing any file, to check the language and model before a long run
+    pub async fn preview(&self, audio: PathBuf, lang: Language, model: Model, seconds: u32, options: TranscribeOptions) -> Result<Transcript, ConvError> {
+        let options = TranscribeOptions { duration_ms: seconds.saturating_mul(1000).min(i32::MAX as u32) as i32, stream_to: None, ..options };
+        self.transcribe(audio, lang, model, options).await
+    }
+
+    /// (start in centiseconds, language code) per language_window_ms of audio, 30s when it's not set
+    pub async fn detect_language(&self, audio: PathBuf, model: Model, options: TranscribeOptions) -> Result<Vec<(i64, &'static str)>, ConvError> {
+        self.request(|reply| Request::DetectLanguage { audio, model, options, reply }).await
+    }
+
+    pub fn unload(&self) {
+        if let Some(ref sender) = self.sender {
+            let _ = sender.send(Request::Unload);
+        }
+    }
+}
+
+// waits for the request being worked on, cancel it first to not wait for a whole file
+impl Drop for WhisperWorker {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("whisper worker panicked");
+            }
+        }
+    }
+}
+
+// the time it took when the model had to be loaded
+fn load(runtime: &Handle, loaded: &mut Option<(Model, Box<dyn Transcriber>)>, lang: Language, model: Model, cancel: &CancelToken, events: &Events) -> Result<Option<Duration>, ConvError> {
+    let mut load_time = None;
+    if !matches!(*loaded, Some((current, _)) if current == model) {
+        // let go of the old context before loading the next one
+        *loaded = None;
+        let cached = whisper::loaded() == Some(model);
+        let st = Instant::now();
+        *loaded = Some((model, Box::new(runtime.block_on(Whisper::new(lang, model, cancel, events))?)));
+        load_time = (!cached).then(|| st.elapsed());
+    }
+    if let Some((_, ref mut w)) = *loaded {
+        w.set_language(lang);
+    }
+    Ok(load_time)
+}
+
+fn decode(audio: &Path, options: &TranscribeOptions) -> Result<(Vec<f32>, Duration), ConvError> {
+    let st = Instant::now();
+    Ok((read_file(audio, options.resample, options.channel)?, st.elapsed()))
+}
+
+fn run(runtime: Handle, requests: mpsc::Receiver<Request>, mut loaded: Option<(Model, Box<dyn Transcriber>)>) {
+    for request in requests {
+        match request {
+            Request::Load { lang, model, cancel, events, reply } => {
+                let _ = reply.send(load(&runtime, &mut loaded, lang, model, &cancel, &events).map(|_| ()));
+            }
+            Request::Transcribe { audio, lang, model, options, reply } => {
+                let result = load(&runtime, &mut loaded, lang, model, &options.cancel, &options.events).and_then(|load| {
+                    let (samples, decode) = decode(&audio, &options)?;
+                    let mut t = loaded.as_mut().unwrap().1.transcribe(&samples, &options)?;
+                    t.timings = Timings { load, decode, audio: Duration::from_secs_f64(samples.len() as f64 / SAMPLE_RATE as f64), ..t.timings };
+                    Ok(t)
+                });
+                let _ = reply.send(result);
+            }
+            Request::DetectLanguage { audio, model, options, reply } => {
+                let options = TranscribeOptions { language_window_ms: if options.language_window_ms > 0 { options.language_window_ms } else { 30_000 }, ..options };
+                let result = load(&runtime, &mut loaded, Language::Auto, model, &options.cancel, &options.events)
+                    .and_then(|_| loaded.as_mut().unwrap().1.detect_languages(&decode(&audio, &options)?.0, &options));
+                let _ = reply.send(result);
+            }
+            Request::Unload => {
+                loaded = None;
+                whisper::unload();
+            }
+        }
+    }
+    info!("whisper worker stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    // stands in for a long transcription, blocking the thread it runs on
+    struct Sleepy;
+
+    impl Transcriber for Sleepy {
+        fn set_language(&mut self, _lang: Language) {}
+
+        fn transcribe(&mut self, _audio: &[f32], _options: &TranscribeOptions) -> Result<Transcript, ConvError> {
+            std::thread::sleep(Duration::from_millis(500));
+            Ok(Transcript { processing_time: Duration::ZERO, utterances: vec![], word_utterances: None, timings: Timings::default() })
+        }
+    }
+
+    // a single threaded runtime is the worst case, a transcription blocking it would stop the timer entirely
+    #[tokio::test(flavor = "current_thread")]
+    async fn runtime_stays_responsive() {
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let timer = tokio::spawn({
+            let ticks = ticks.clone();
+            async move {
+                let mut interval = tokio::time::interval(Duration::from_millis(10));
+                loop {
+                    interval.tick().await;
+                    ticks.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+        let worker = WhisperWorker::with_transcriber(Model::Base, Box::new(Sleepy));
+        let audio = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/mulaw-8k.wav");
+        worker.transcribe(audio, Language::English, Model::Base, TranscribeOptions::default()).await.unwrap();
+        timer.abort();
+        assert!(ticks.load(Ordering::Relaxed) >= 20, "{} ticks", ticks.load(Ordering::Relaxed));
+    }
+}
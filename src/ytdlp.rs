@@ -0,0 +1,138 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use once_cell::sync::OnceCell;
+use tracing::info;
+
+use crate::error::ConvError;
+use crate::job::CancelToken;
+use crate::utils::{command_line, ffmpeg_tool, wait};
+
+static YT_DLP: OnceCell<PathBuf> = OnceCell::new();
+
+// links that count as one without http(s)://, anything else needs the scheme so a file name is never taken for one
+const KNOWN_HOSTS: [&str; 8] = ["youtube.com", "www.youtube.com", "m.youtube.com", "youtu.be", "bilibili.com", "www.bilibili.com", "m.bilibili.com", "b23.tv"];
+
+pub fn set_yt_dlp(path: PathBuf) {
+    let _ = YT_DLP.set(path);
+}
+
+fn yt_dlp() -> PathBuf {
+    YT_DLP.get().cloned().unwrap_or_else(|| PathBuf::from("yt-dlp"))
+}
+
+/// `https://www.youtube.com/watch?v=...` as is, `youtu.be/...` with `https://` in front, None for anything else
+pub fn parse_url(s: &str) -> Option<String> {
+    let lower = s.to_ascii_lowercase();
+    if let Some(rest) = lower.strip_prefix("https://").or_else(|| lower.strip_prefix("http://")) {
+        return rest.split(['/', '?', '#']).next().is_some_and(|host| host.contains('.')).then(|| s.to_string());
+    }
+    let host = lower.split(['/', '?', '#']).next().unwrap_or_default();
+    (KNOWN_HOSTS.contains(&host) && lower.len() > host.len() + 1).then(|| format!("https://{}", s))
+}
+
+/// a title as a file name on any system: no separators or characters windows refuses, no leading dot, at most 200 bytes
+pub fn sanitize(title: &str) -> String {
+    let mut name = String::new();
+    for c in title.trim().chars() {
+        let c = if c.is_control() || "<>:\"/\\|?*".contains(c) { '_' } else { c };
+        if name.len() + c.len_utf8() > 200 {
+            break;
+        }
+        name.push(c);
+    }
+    let name = name.trim_start_matches('.').trim_end_matches(['.', ' ']);
+    match name.is_empty() {
+        true => "video".to_string(),
+        false => name.to_string(),
+    }
+}
+
+/// the audio in a directory of its own under the system temp directory, removed with it when dropped
+#[derive(Debug)]
+pub struct Download {
+    pub audio: PathBuf,
+    dir: PathBuf,
+}
+
+impl Download {
+    /// moved out before the temp directory goes, a copy when it's on another file system
+    pub fn keep(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        let kept = dir.join(self.audio.file_name().unwrap_or_default());
+        if std::fs::rename(&self.audio, &kept).is_err() {
+            std::fs::copy(&self.audio, &kept)?;
+        }
+        Ok(kept)
+    }
+}
+
+impl Drop for Download {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+// yt-dlp -x --no-playlist --newline --progress --no-simulate --print after_move:title --print after_move:filepath -o /tmp/conv-.../audio.%(ext)s -- URL
+// --print keeps yt-dlp quiet and its progress on stderr, so stdout only has the title and where the audio ended up
+fn args(url: &str, dir: &Path) -> Vec<String> {
+    let mut args = ["-x", "--no-playlist", "--newline", "--progress", "--no-simulate", "--print", "after_move:title", "--print", "after_move:filepath", "-o"]
+        .map(str::to_string)
+        .to_vec();
+    args.push(dir.join("audio.%(ext)s").to_string_lossy().into_owned());
+    // -x converts with ffmpeg, the configured one if there is one
+    let ffmpeg = ffmpeg_tool("ffmpeg");
+    if ffmpeg != Path::new("ffmpeg") {
+        args.extend(["--ffmpeg-location".to_string(), ffmpeg.to_string_lossy().into_owned()]);
+    }
+    args.extend(["--".to_string(), url.to_string()]);
+    args
+}
+
+/// the best audio of the video, named after its title. yt-dlp's progress goes to the log line by line
+pub fn download(url: &str, cancel: &CancelToken) -> Result<Download, ConvError> {
+    let dir = std::env::temp_dir().join(format!("conv-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir)?;
+    let mut download = Download { audio: PathBuf::new(), dir };
+    let args = args(url, &download.dir);
+    info!("{}", command_line("yt-dlp", &args));
+    let mut child = Command::new(yt_dlp())
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => ConvError::YtDlpMissing,
+            _ => ConvError::Io(e),
+        })?;
+    let mut stdout = child.stdout.take();
+    let (status, stderr) = wait(child, cancel, |line| {
+        if !line.trim().is_empty() {
+            info!("yt-dlp: {}", line.trim());
+        }
+    })?;
+    let mut printed = String::new();
+    if let Some(ref mut stdout) = stdout {
+        stdout.read_to_string(&mut printed)?;
+    }
+
+    if !status.success() {
+        let errors = stderr.lines().filter(|line| line.starts_with("ERROR")).collect::<Vec<_>>();
+        let tail = match errors.is_empty() {
+            true => stderr.lines().rev().take(5).collect::<Vec<_>>().into_iter().rev().collect::<Vec<_>>().join("\n"),
+            false => errors.join("\n"),
+        };
+        return Err(ConvError::YtDlpFailed(format!("{}\n{}", status, tail)));
+    }
+    let mut lines = printed.lines().filter(|line| !line.trim().is_empty());
+    let (Some(title), Some(path)) = (lines.next(), lines.next_back()) else {
+        return Err(ConvError::YtDlpFailed("没有给出下载的文件".to_string()));
+    };
+    let path = PathBuf::from(path.trim());
+    let ext = path.extension().unwrap_or_default().to_string_lossy();
+    download.audio = download.dir.join(format!("{}.{}", sanitize(title), ext));
+    std::fs::rename(&path, &download.audio)?;
+    info!("downloaded {} to {}", url, download.audio.display());
+    Ok(download)
+}